@@ -0,0 +1,173 @@
+use chrono::{Local, NaiveDateTime};
+use std::fs;
+use std::path::{Path, PathBuf};
+use tmus_engine::models::AppId;
+use tmus_engine::storage::forget::ForgetReport;
+use tmus_engine::storage::snapshot::{self, SnapshotEntry};
+use tmus_engine::storage::{Storage, StorageError};
+use tracing::{error, info};
+
+use crate::app::constant::{
+    category_file_path, config_file_path, data_dir, rule_file_path, statistic_scheme_file_path,
+};
+use crate::state::category::get_category_tree;
+use crate::state::scheme::get_statistic_scheme;
+use crate::state::{get_config, get_rule};
+
+const SNAPSHOT_EXTENSION: &str = "snapshot";
+/// File stem of the backups named by their time
+const BACKUP_NAME_FORMAT: &str = "tmus-%Y%m%d-%H%M%S";
+
+/// Backups are saved to the configured directory, or `backup` in data directory
+pub fn backup_dir() -> PathBuf {
+    get_config()
+        .backup
+        .dir
+        .clone()
+        .filter(|x| !x.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| data_dir().join("backup"))
+}
+
+/// Take a snapshot of the storage and the app state into one archive
+/// The state is serialized from memory, so files being written are never captured
+/// If path is none, the archive is saved to backup dir, named by the current time
+pub fn create_backup(storage: &Storage, path: Option<PathBuf>) -> Result<PathBuf, String> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let dir = backup_dir();
+            fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+            dir.join(format!(
+                "{}.{}",
+                Local::now().format(BACKUP_NAME_FORMAT),
+                SNAPSHOT_EXTENSION
+            ))
+        }
+    };
+    let mut entries = storage.snapshot().map_err(|e| e.to_string())?;
+    let state = [
+        (config_file_path(), to_json(&*get_config())?),
+        (rule_file_path(), to_json(&*get_rule().lock().unwrap())?),
+        (category_file_path(), to_json(&get_category_tree())?),
+        (
+            statistic_scheme_file_path(),
+            to_json(&*get_statistic_scheme())?,
+        ),
+    ];
+    for (file_path, content) in state {
+        entries.push(SnapshotEntry {
+            name: file_name(file_path),
+            content,
+        });
+    }
+    snapshot::write_snapshot(&path, &entries).map_err(|e| e.to_string())?;
+    info!("Backup saved to {:?}", path);
+    Ok(path)
+}
+
+/// Create a backup if the latest one in backup dir is older than the configured interval,
+/// then remove the oldest backups beyond the configured count
+/// Only backups named by their time are counted, other snapshots in the dir are never removed
+pub fn scheduled_backup(storage: &Storage) {
+    let config = get_config().backup.clone();
+    if config.interval_days == 0 {
+        return;
+    }
+    let backups = scheduled_backups(&backup_dir());
+    let due = backups.last().is_none_or(|x| {
+        x.metadata()
+            .and_then(|x| x.modified())
+            .ok()
+            .and_then(|x| x.elapsed().ok())
+            .is_none_or(|x| x.as_secs() >= config.interval_days as u64 * 24 * 60 * 60)
+    });
+    if !due {
+        return;
+    }
+    if let Err(e) = create_backup(storage, None) {
+        error!("Failed to create scheduled backup: {}", e);
+        return;
+    }
+    if config.keep == 0 {
+        return;
+    }
+    let backups = scheduled_backups(&backup_dir());
+    for path in &backups[..backups.len().saturating_sub(config.keep as usize)] {
+        if let Err(e) = fs::remove_file(path) {
+            error!("Failed to remove old backup {:?}: {}", path, e);
+        }
+    }
+}
+
+/// Forget the app, backups which may keep its history are removed only if confirmed,
+/// including backups in the configured backup dir outside the data dir
+pub fn forget_app(
+    storage: &Storage,
+    app_id: AppId,
+    remove_backups: bool,
+) -> Result<ForgetReport, String> {
+    let external = external_backups(storage);
+    if !external.is_empty() && !remove_backups {
+        let count = all_backups(storage)?.len();
+        return Err(StorageError::BackupsExist(count).to_string());
+    }
+    let mut report = storage
+        .forget_app(app_id, remove_backups)
+        .map_err(|e| e.to_string())?;
+    for path in external {
+        fs::remove_file(&path).map_err(|e| format!("Failed to remove {:?}: {}", path, e))?;
+        report.backups.push(path);
+    }
+    Ok(report)
+}
+
+/// Backups which may keep the history of apps, removed when an app is forgotten
+pub fn all_backups(storage: &Storage) -> Result<Vec<PathBuf>, String> {
+    let mut backups = storage.backups().map_err(|e| e.to_string())?;
+    backups.extend(external_backups(storage));
+    Ok(backups)
+}
+
+/// Backups in the configured backup dir if it's outside the data dir, whose backups are listed
+/// by the storage
+fn external_backups(storage: &Storage) -> Vec<PathBuf> {
+    let dir = backup_dir();
+    if dir.starts_with(storage.data_dir()) {
+        return vec![];
+    }
+    list_backups(&dir)
+}
+
+/// Backups in the dir, from the oldest to the latest
+fn list_backups(dir: &Path) -> Vec<PathBuf> {
+    let mut backups: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|x| {
+            x.filter_map(|x| x.ok().map(|x| x.path()))
+                .filter(|x| x.extension().is_some_and(|x| x == SNAPSHOT_EXTENSION))
+                .collect()
+        })
+        .unwrap_or_default();
+    backups.sort();
+    backups
+}
+
+/// Backups in the dir named by their time, from the oldest to the latest
+fn scheduled_backups(dir: &Path) -> Vec<PathBuf> {
+    list_backups(dir)
+        .into_iter()
+        .filter(|x| {
+            x.file_stem()
+                .and_then(|x| x.to_str())
+                .is_some_and(|x| NaiveDateTime::parse_from_str(x, BACKUP_NAME_FORMAT).is_ok())
+        })
+        .collect()
+}
+
+fn to_json<T: serde::Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, String> {
+    serde_json::to_vec_pretty(value).map_err(|e| e.to_string())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap().to_string_lossy().into_owned()
+}
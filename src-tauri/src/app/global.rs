@@ -10,3 +10,14 @@ pub fn get_app_handle<'a>() -> &'a AppHandle {
 pub fn set_app_handle(app_handle: AppHandle) {
     APP_HANDLE.set(app_handle).unwrap();
 }
+
+/// The error of opening the storage for tracking, the storage is read-only if set.
+static STORAGE_ERROR: OnceLock<String> = OnceLock::new();
+
+pub fn get_storage_error<'a>() -> Option<&'a String> {
+    STORAGE_ERROR.get()
+}
+
+pub fn set_storage_error(error: String) {
+    STORAGE_ERROR.set(error).unwrap();
+}
@@ -1,3 +1,4 @@
+pub mod backup;
 pub mod constant;
 mod exit;
 pub mod global;
@@ -1,20 +1,72 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use tmus_engine::storage::get_storage;
 use tokio::time::{interval, Duration};
 use tracing::{error, info};
 
-use crate::state::{category::save_category_data, scheme::save_statistic_scheme};
+use crate::app::backup;
+use crate::state::{category::save_category_data, get_config, scheme::save_statistic_scheme};
+use crate::sync;
+
+/// Apply the retention policy, compress segments and check scheduled backup once every this many ticks.
+const RETENTION_TICKS: u64 = 24 * 60;
+/// The tick in [`RETENTION_TICKS`] to start the maintenance, after the first tick saved the state.
+const RETENTION_TICK: u64 = 1;
+
+static MAINTAINING: AtomicBool = AtomicBool::new(false);
 
 // Start a timer to save data
 pub fn start_timer() {
     info!("Starting save timer");
     tauri::async_runtime::spawn(async move {
         let mut interval = interval(Duration::from_secs(60));
+        let mut ticks: u64 = 0;
         loop {
             interval.tick().await;
             save_data();
+            // Uploads read the records, which the maintenance may rewrite, so they don't share a tick.
+            if ticks % RETENTION_TICKS == RETENTION_TICK {
+                maintain();
+            } else {
+                sync::client::scheduled_upload(ticks);
+            }
+            ticks += 1;
         }
     });
 }
 
+/// Retention, compression and backup rewrite or read all records, so they run on the blocking
+/// threads instead of the timer. Skipped if the storage is read-only or the last run isn't done.
+fn maintain() {
+    if get_storage().is_read_only() || MAINTAINING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tauri::async_runtime::spawn_blocking(|| {
+        apply_retention();
+        compress_segments();
+        backup::scheduled_backup(get_storage());
+        MAINTAINING.store(false, Ordering::SeqCst);
+    });
+}
+
+fn apply_retention() {
+    let retention = get_config().retention.clone();
+    let Some(before) = retention.before() else {
+        return;
+    };
+    if let Err(e) = get_storage().apply_retention(before, retention.mode) {
+        error!("Failed to apply retention: {}", e);
+    }
+}
+
+fn compress_segments() {
+    if !get_config().retention.compress_segments {
+        return;
+    }
+    if let Err(e) = get_storage().compress_segments() {
+        error!("Failed to compress segments: {}", e);
+    }
+}
+
 fn save_data() {
     if let Err(e) = save_category_data() {
         error!("Failed to save category data: {}", e);
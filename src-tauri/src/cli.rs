@@ -1,10 +1,22 @@
 use clap::{Parser, Subcommand, ValueEnum};
+use std::path::{Path, PathBuf};
 use tracing::Level;
 
-use crate::app::constant::APP_NAME;
+use crate::app::backup;
+use crate::app::constant::{data_dir, APP_NAME};
+use crate::cmd::app_detail::remove_app_detail;
+use crate::cmd::export::{write_export, ExportFormat};
 use crate::cmd::read_by_timestamp;
-use tmus_engine::storage::focus_app;
-use tmus_engine::util::Timestamp;
+use crate::import::csv::CsvMapping;
+use crate::import::{self, activity_watch, ImportResult, ParsedSpans};
+use crate::state::{category, get_config};
+use crate::sync;
+use tmus_engine::models::AppId;
+use tmus_engine::storage::retention::RetentionMode;
+use tmus_engine::storage::snapshot;
+use tmus_engine::storage::verify::{self, VerifyReport};
+use tmus_engine::storage::{compact, get_storage, Storage};
+use tmus_engine::util::{now_timestamp, s_as_ms, Timestamp};
 
 #[derive(Debug, Clone, Copy, ValueEnum, Default)]
 pub enum LogLevel {
@@ -72,6 +84,171 @@ pub enum Commands {
         #[arg(short, long, help = "Limit number of records")]
         limit: Option<usize>,
     },
+    #[command(about = "Check the data directory for broken records and index")]
+    Verify,
+    #[command(about = "Repair the data directory, tmus must not be running")]
+    Repair,
+    #[command(about = "Rebuild index.bin from the records, tmus must not be running")]
+    RebuildIndex,
+    #[command(about = "Merge adjacent records of the same app, tmus must not be running")]
+    Compact {
+        #[arg(
+            short,
+            long,
+            default_value_t = 1,
+            help = "Max gap (seconds) between records to merge"
+        )]
+        max_gap: i64,
+    },
+    #[command(about = "Age out old records by the retention policy, tmus must not be running")]
+    Retention {
+        #[arg(
+            short,
+            long,
+            help = "Keep raw records of the recent months (default from config)"
+        )]
+        keep_months: Option<u32>,
+        #[arg(long, help = "Move old records to archive.bin instead of daily totals")]
+        archive: bool,
+    },
+    #[command(about = "Compress record segments of the past months, tmus must not be running")]
+    Compress,
+    #[command(about = "Merge the history of apps into another app, tmus must not be running")]
+    Merge {
+        #[arg(short, long, help = "App ID to merge into")]
+        target: AppId,
+        #[arg(required = true, help = "App IDs to merge")]
+        sources: Vec<AppId>,
+    },
+    #[command(about = "Erase an app and its whole history, tmus must not be running")]
+    Forget {
+        #[arg(help = "App ID to forget")]
+        id: AppId,
+        #[arg(long, help = "Remove backups which may keep the history of the app")]
+        remove_backups: bool,
+    },
+    #[command(about = "Export focus records with app names and categories")]
+    Export {
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Csv, help = "Output format")]
+        format: ExportFormat,
+        #[arg(
+            short,
+            long,
+            required_unless_present = "sqlite",
+            help = "Output file path"
+        )]
+        output: Option<PathBuf>,
+        #[arg(
+            long,
+            conflicts_with_all = ["format", "output"],
+            help = "Export to a SQLite database, same as --format sqlite --output"
+        )]
+        sqlite: Option<PathBuf>,
+        #[arg(short, long, help = "Start timestamp (ms)")]
+        start: Option<Timestamp>,
+        #[arg(short, long, help = "End timestamp (ms)")]
+        end: Option<Timestamp>,
+    },
+    #[command(about = "Import history from an ActivityWatch export, tmus must not be running")]
+    ImportAw {
+        #[arg(help = "Exported JSON file of aw-watcher-window buckets")]
+        path: PathBuf,
+        #[arg(long, help = "Only report what would be imported")]
+        dry_run: bool,
+    },
+    #[command(
+        about = "Import history from a CSV file by a column mapping, tmus must not be running"
+    )]
+    ImportCsv {
+        #[arg(help = "CSV file path")]
+        path: PathBuf,
+        #[arg(long, help = "Column of the app path or executable name")]
+        app: String,
+        #[arg(long, help = "Column of the start time")]
+        start: String,
+        #[arg(
+            long,
+            required_unless_present = "duration",
+            help = "Column of the end time"
+        )]
+        end: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "end",
+            help = "Column of the duration, in seconds or h:mm:ss"
+        )]
+        duration: Option<String>,
+        #[arg(long, help = "utc, local or an offset like +08:00 (default local)")]
+        time_zone: Option<String>,
+        #[arg(long, help = "chrono format of times without an offset")]
+        time_format: Option<String>,
+        #[arg(long, help = "Column delimiter (default ,)")]
+        delimiter: Option<char>,
+        #[arg(long, help = "The first row is data, columns are 0-based indexes")]
+        no_header: bool,
+        #[arg(long, help = "Only report what would be imported")]
+        dry_run: bool,
+    },
+    #[command(
+        about = "Merge the data directory of tmus on another machine, tmus must not be running"
+    )]
+    MergeDir {
+        #[arg(help = "Data directory, or a copy of it")]
+        path: PathBuf,
+        #[arg(long, help = "Tag the merged records with the device name")]
+        device: Option<String>,
+        #[arg(long, help = "Only report what would be merged")]
+        dry_run: bool,
+    },
+    #[command(
+        about = "Run the sync server, which stores records pushed by other devices, tmus must not be running \
+                 (set sync.hostServer in the config to host it in tmus). \
+                 The token which clients must send is read from TMUS_SYNC_TOKEN or --token-file"
+    )]
+    Serve {
+        #[arg(
+            long,
+            default_value = sync::server::DEFAULT_ADDR,
+            help = "Address to listen on, 0.0.0.0:2372 accepts other devices"
+        )]
+        addr: String,
+        #[arg(long, help = "File of the token, TMUS_SYNC_TOKEN is used if not set")]
+        token_file: Option<PathBuf>,
+    },
+    #[command(about = "Push new records to the sync server of the config")]
+    Push,
+    #[command(about = "Save a snapshot of all data into one archive")]
+    Backup {
+        #[arg(short, long, help = "Archive path (default in the backup directory)")]
+        output: Option<PathBuf>,
+    },
+    #[command(about = "Replace all data by a backup archive, tmus must not be running")]
+    Restore {
+        #[arg(help = "Archive path")]
+        path: PathBuf,
+    },
+}
+
+impl Commands {
+    /// Whether the command reads the data through the opened storage.
+    pub fn need_storage(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Verify
+                | Commands::Repair
+                | Commands::RebuildIndex
+                | Commands::Compact { .. }
+                | Commands::Retention { .. }
+                | Commands::Compress
+                | Commands::Merge { .. }
+                | Commands::Forget { .. }
+                | Commands::Restore { .. }
+                | Commands::ImportAw { .. }
+                | Commands::ImportCsv { .. }
+                | Commands::MergeDir { .. }
+                | Commands::Serve { .. }
+        )
+    }
 }
 
 pub fn handle_cli(cli: Cli) {
@@ -90,12 +267,97 @@ pub fn handle_cli(cli: Cli) {
         }) => {
             query_records(start, end, app_id, limit);
         }
+        Some(Commands::Verify) => {
+            verify_data();
+        }
+        Some(Commands::Repair) => {
+            repair_data();
+        }
+        Some(Commands::RebuildIndex) => {
+            rebuild_index();
+        }
+        Some(Commands::Compact { max_gap }) => {
+            compact(max_gap);
+        }
+        Some(Commands::Retention {
+            keep_months,
+            archive,
+        }) => {
+            apply_retention(keep_months, archive);
+        }
+        Some(Commands::Compress) => {
+            compress_segments();
+        }
+        Some(Commands::Merge { target, sources }) => {
+            merge_apps(&sources, target);
+        }
+        Some(Commands::Forget { id, remove_backups }) => {
+            forget_app(id, remove_backups);
+        }
+        Some(Commands::Export {
+            format,
+            output,
+            sqlite,
+            start,
+            end,
+        }) => match sqlite {
+            Some(path) => export_records(ExportFormat::Sqlite, &path, start, end),
+            None => export_records(format, &output.unwrap(), start, end),
+        },
+        Some(Commands::ImportAw { path, dry_run }) => {
+            run_import(dry_run, |storage| activity_watch::parse(storage, &path));
+        }
+        Some(Commands::ImportCsv {
+            path,
+            app,
+            start,
+            end,
+            duration,
+            time_zone,
+            time_format,
+            delimiter,
+            no_header,
+            dry_run,
+        }) => {
+            let mapping = CsvMapping {
+                app,
+                start,
+                end,
+                duration,
+                time_zone,
+                time_format,
+                delimiter,
+                no_header,
+            };
+            run_import(dry_run, |storage| {
+                import::csv::parse(storage, &path, &mapping)
+            });
+        }
+        Some(Commands::MergeDir {
+            path,
+            device,
+            dry_run,
+        }) => {
+            merge_data_dir(&path, device.as_deref(), dry_run);
+        }
+        Some(Commands::Serve { addr, token_file }) => {
+            serve(&addr, token_file.as_deref());
+        }
+        Some(Commands::Push) => {
+            push_records();
+        }
+        Some(Commands::Backup { output }) => {
+            create_backup(output);
+        }
+        Some(Commands::Restore { path }) => {
+            restore_backup(&path);
+        }
         None => {}
     }
 }
 
 fn list_apps(show_id: bool) {
-    let apps = focus_app::get_all_app();
+    let apps = get_storage().get_all_app();
     for (i, app) in apps.iter().enumerate() {
         if show_id {
             println!("{}: {}", i, app);
@@ -140,3 +402,320 @@ fn query_records(
 
     println!("Total records: {}", count);
 }
+
+fn verify_data() {
+    match verify::verify(data_dir()) {
+        Ok(report) => {
+            print_report(&report);
+            if !report.is_ok() {
+                println!("Run the repair command to fix them.");
+                std::process::exit(1);
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to verify: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn repair_data() {
+    match verify::repair(data_dir()) {
+        Ok(report) => {
+            print_report(&report);
+            if !report.is_ok() {
+                println!("Repaired.");
+            }
+        }
+        Err(e) => {
+            eprintln!("Failed to repair: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn rebuild_index() {
+    if let Err(e) = verify::rebuild_index(data_dir()) {
+        eprintln!("Failed to rebuild index: {}", e);
+        std::process::exit(1);
+    }
+    println!("Index rebuilt.");
+}
+
+fn compact(max_gap: i64) {
+    match compact::compact(data_dir(), s_as_ms(max_gap)) {
+        Ok(report) => println!(
+            "Records before the cutoff: {} -> {}",
+            report.before, report.after
+        ),
+        Err(e) => {
+            eprintln!("Failed to compact: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn apply_retention(keep_months: Option<u32>, archive: bool) {
+    let mut retention = get_config().retention.clone();
+    if let Some(keep_months) = keep_months {
+        retention.keep_months = keep_months;
+    }
+    if archive {
+        retention.mode = RetentionMode::Archive;
+    }
+    let Some(before) = retention.before() else {
+        println!("Retention is disabled, set keep months to enable it.");
+        return;
+    };
+    let result = Storage::open(data_dir())
+        .and_then(|storage| storage.apply_retention(before, retention.mode));
+    match result {
+        Ok(report) => println!(
+            "Records before the cutoff: {} -> {}",
+            report.before, report.after
+        ),
+        Err(e) => {
+            eprintln!("Failed to apply retention: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn compress_segments() {
+    match Storage::open(data_dir()).and_then(|storage| storage.compress_segments()) {
+        Ok(count) => println!("Compressed segments: {}", count),
+        Err(e) => {
+            eprintln!("Failed to compress segments: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn merge_apps(sources: &[AppId], target: AppId) {
+    match Storage::open(data_dir()).and_then(|storage| storage.merge_apps(sources, target)) {
+        Ok(report) => {
+            category::merge_app_category(sources, report.target);
+            if let Err(e) = category::save_category_data() {
+                eprintln!("Failed to save categories: {}", e);
+                std::process::exit(1);
+            }
+            println!(
+                "Merged into app {}, records moved: {}",
+                report.target, report.records
+            );
+        }
+        Err(e) => {
+            eprintln!("Failed to merge apps: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn forget_app(id: AppId, remove_backups: bool) {
+    let report = Storage::open(data_dir())
+        .map_err(|e| e.to_string())
+        .and_then(|storage| backup::forget_app(&storage, id, remove_backups));
+    let report = match report {
+        Ok(report) => report,
+        Err(e) => {
+            eprintln!("Failed to forget app: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for app_id in &report.apps {
+        category::remove_app_from_category(*app_id);
+    }
+    if let Err(e) = category::save_category_data() {
+        eprintln!("Failed to save categories: {}", e);
+        std::process::exit(1);
+    }
+    tokio::runtime::Runtime::new()
+        .expect("Create runtime failed.")
+        .block_on(remove_app_detail(&report.apps));
+    println!(
+        "Forgot apps: {:?}, records removed: {}, backups removed: {:?}",
+        report.apps, report.records, report.backups
+    );
+}
+
+fn export_records(
+    format: ExportFormat,
+    output: &Path,
+    start: Option<Timestamp>,
+    end: Option<Timestamp>,
+) {
+    let start = start.unwrap_or(0);
+    let end = end.unwrap_or_else(now_timestamp);
+    let result = tokio::runtime::Runtime::new()
+        .expect("Create runtime failed.")
+        .block_on(write_export(output, format, start, end));
+    match result {
+        Ok(count) => println!("Exported {} records to {}", count, output.display()),
+        Err(e) => {
+            eprintln!("Failed to export records: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// A dry run opens the storage read-only, so it works while tmus is running.
+fn run_import(dry_run: bool, parse: impl FnOnce(&Storage) -> Result<ParsedSpans, String>) {
+    let storage = if dry_run {
+        Storage::open_read_only(data_dir())
+    } else {
+        Storage::open(data_dir())
+    };
+    let result = storage.map_err(|e| e.to_string()).and_then(|storage| {
+        let parsed = parse(&storage)?;
+        import::import_spans(&storage, parsed, dry_run)
+    });
+    match result {
+        Ok(result) => print_import_result(&result),
+        Err(e) => {
+            eprintln!("Failed to import: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn merge_data_dir(path: &Path, device: Option<&str>, dry_run: bool) {
+    let storage = if dry_run {
+        Storage::open_read_only(data_dir())
+    } else {
+        Storage::open(data_dir())
+    };
+    let result = storage.map_err(|e| e.to_string()).and_then(|storage| {
+        let matcher = tokio::runtime::Runtime::new()
+            .expect("Create runtime failed.")
+            .block_on(import::data_dir::AppMatcher::new(&storage));
+        import::data_dir::merge_data_dir(&storage, path, device, &matcher, dry_run)
+    });
+    let result = match result {
+        Ok(result) => result,
+        Err(e) => {
+            eprintln!("Failed to merge: {}", e);
+            std::process::exit(1);
+        }
+    };
+    println!(
+        "Apps: {}, matched at another path: {}",
+        result.apps, result.mapped
+    );
+    if dry_run {
+        println!(
+            "Would merge: {} ({} trimmed), skip: {}, records to write: {}",
+            result.imported, result.trimmed, result.skipped, result.record_count
+        );
+    } else {
+        println!(
+            "Merged: {} ({} trimmed), skipped: {}, records written: {}",
+            result.imported, result.trimmed, result.skipped, result.record_count
+        );
+    }
+}
+
+/// Environment variable of the token of `tmus serve`, so it isn't shown in the process list
+const SYNC_TOKEN_ENV: &str = "TMUS_SYNC_TOKEN";
+
+fn serve(addr: &str, token_file: Option<&Path>) {
+    let token = match token_file {
+        Some(path) => std::fs::read_to_string(path)
+            .map(|x| x.trim().to_string())
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e)),
+        None => std::env::var(SYNC_TOKEN_ENV)
+            .map_err(|_| format!("Set {} or --token-file", SYNC_TOKEN_ENV)),
+    };
+    let result = token.and_then(|token| {
+        let storage = Storage::open(data_dir()).map_err(|e| e.to_string())?;
+        tokio::runtime::Runtime::new()
+            .expect("Create runtime failed.")
+            .block_on(sync::server::serve(
+                Box::leak(Box::new(storage)),
+                addr,
+                token,
+                async {
+                    let _ = tokio::signal::ctrl_c().await;
+                },
+            ))
+    });
+    if let Err(e) = result {
+        eprintln!("Failed to serve: {}", e);
+        std::process::exit(1);
+    }
+}
+
+fn push_records() {
+    let config = get_config().sync.clone();
+    if config.server.is_empty() {
+        eprintln!("No sync server in the config");
+        std::process::exit(1);
+    }
+    let result = tokio::runtime::Runtime::new()
+        .expect("Create runtime failed.")
+        .block_on(sync::client::upload(get_storage(), &config));
+    match result {
+        Ok(count) => println!("Pushed {} records to {}", count, config.server),
+        Err(e) => {
+            eprintln!("Failed to push: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn create_backup(output: Option<PathBuf>) {
+    match backup::create_backup(get_storage(), output) {
+        Ok(path) => println!("Backup saved to {}", path.display()),
+        Err(e) => {
+            eprintln!("Failed to create backup: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn restore_backup(path: &Path) {
+    match snapshot::restore(data_dir(), path) {
+        Ok(backup_dir) => println!(
+            "Restored, the replaced data is saved to {}",
+            backup_dir.display()
+        ),
+        Err(e) => {
+            eprintln!("Failed to restore: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn print_import_result(result: &ImportResult) {
+    for error in &result.errors {
+        println!("{}", error);
+    }
+    println!(
+        "Entries: {}, invalid: {}, unknown apps: {}, excluded by rules: {}, merged by rules: {}",
+        result.total, result.invalid, result.unknown, result.excluded, result.merged
+    );
+    if result.dry_run {
+        println!(
+            "Would import: {} ({} trimmed), skip: {}, records to write: {}",
+            result.imported, result.trimmed, result.skipped, result.record_count
+        );
+    } else {
+        println!(
+            "Imported: {} ({} trimmed), skipped: {}, records written: {}",
+            result.imported, result.trimmed, result.skipped, result.record_count
+        );
+    }
+}
+
+fn print_report(report: &VerifyReport) {
+    println!(
+        "Apps: {}, records: {}",
+        report.app_count, report.record_count
+    );
+    for issue in &report.issues {
+        println!("{}", issue);
+    }
+    for warning in &report.warnings {
+        println!("Warning: {}", warning);
+    }
+    println!("Issues found: {}", report.issues.len());
+}
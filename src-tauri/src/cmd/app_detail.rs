@@ -6,7 +6,7 @@ use std::collections::HashMap;
 use std::path::Path;
 use std::sync::OnceLock;
 use tmus_engine::models::AppId;
-use tmus_engine::storage::focus_app;
+use tmus_engine::storage::get_storage;
 use tokio::sync::{Mutex, MutexGuard};
 use windows_icons::get_icon_base64_by_path;
 
@@ -55,10 +55,21 @@ pub async fn update_app_detail_cache(
     }
 }
 
+/// Remove apps from the cache, used when apps are forgotten
+pub async fn remove_app_detail(ids: &[AppId]) {
+    let mut app_detail_map = get_app_detail_cache().await;
+    for id in ids {
+        app_detail_map.remove(id);
+    }
+    update_app_detail_cache(&mut *app_detail_map, vec![]).await;
+}
+
 #[tauri::command]
 #[tracing::instrument]
-pub async fn get_app_detail(id: usize) -> FileDetail {
-    let path = focus_app::get_path_by_id(id);
+pub async fn get_app_detail(id: usize) -> Result<FileDetail, String> {
+    let path = get_storage()
+        .get_path_by_id(id)
+        .map_err(|e| e.to_string())?;
     let mut detail = query_file_detail(id, &path);
     let mut app_detail_cache = get_app_detail_cache().await;
     // File has been deleted, use cache data
@@ -69,13 +80,13 @@ pub async fn get_app_detail(id: usize) -> FileDetail {
         }
     }
     update_app_detail_cache(&mut *app_detail_cache, vec![detail.clone()]).await;
-    detail
+    Ok(detail)
 }
 
 #[tauri::command]
 #[tracing::instrument]
 pub async fn get_all_app_detail() -> HashMap<AppId, FileDetail> {
-    let app_vec = focus_app::get_all_app();
+    let app_vec = get_storage().get_all_app();
     let mut app_detail_cache = get_app_detail_cache().await;
     app_detail_cache.values_mut().for_each(|detail| {
         detail.exist = Path::new(&detail.path).exists();
@@ -83,7 +94,7 @@ pub async fn get_all_app_detail() -> HashMap<AppId, FileDetail> {
     let not_exist_app_detail: Vec<FileDetail> = app_vec
         .iter()
         .enumerate()
-        .filter(|(id, _)| !app_detail_cache.contains_key(id))
+        .filter(|(id, path)| !path.is_empty() && !app_detail_cache.contains_key(id))
         .map(|(id, path)| query_file_detail(id, path))
         .collect();
     if not_exist_app_detail.len() > 0 {
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use tauri::command;
+use tmus_engine::models::AppId;
+use tmus_engine::storage::get_storage;
+
+use crate::app::backup;
+use crate::cmd::app_detail::remove_app_detail;
+use crate::state::category;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeAppsRequest {
+    pub source_ids: Vec<AppId>,
+    pub target_id: AppId,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeAppsResponse {
+    pub target_id: AppId,
+    pub record_count: usize,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgetAppRequest {
+    pub app_id: AppId,
+    /// Remove backups which may keep the history, forgetting fails if any exists without it
+    #[serde(default)]
+    pub remove_backups: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgetAppResponse {
+    pub app_ids: Vec<AppId>,
+    pub record_count: usize,
+    pub removed_backups: Vec<String>,
+}
+
+/// Merge the history of source apps into target app, including their categories
+/// Records are rewritten, which blocks, so it runs on the blocking threads
+#[command(async)]
+#[tracing::instrument]
+pub async fn merge_apps(request: MergeAppsRequest) -> Result<MergeAppsResponse, String> {
+    let (source_ids, target_id) = (request.source_ids.clone(), request.target_id);
+    let report = tauri::async_runtime::spawn_blocking(move || {
+        get_storage().merge_apps(&source_ids, target_id)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| e.to_string())?;
+    category::merge_app_category(&request.source_ids, report.target);
+    Ok(MergeAppsResponse {
+        target_id: report.target,
+        record_count: report.records,
+    })
+}
+
+/// Erase the app and its history, including app detail cache, categories and backups
+/// Apps merged into it are erased too
+#[command(async)]
+#[tracing::instrument]
+pub async fn forget_app(request: ForgetAppRequest) -> Result<ForgetAppResponse, String> {
+    let report = backup::forget_app(get_storage(), request.app_id, request.remove_backups)?;
+    for app_id in &report.apps {
+        category::remove_app_from_category(*app_id);
+    }
+    remove_app_detail(&report.apps).await;
+    Ok(ForgetAppResponse {
+        app_ids: report.apps,
+        record_count: report.records,
+        removed_backups: report
+            .backups
+            .iter()
+            .map(|x| x.to_string_lossy().into_owned())
+            .collect(),
+    })
+}
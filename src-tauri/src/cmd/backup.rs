@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+use tauri::{command, AppHandle};
+use tmus_engine::storage::get_storage;
+use tmus_engine::storage::snapshot;
+
+use crate::app::backup;
+use crate::app::constant::data_dir;
+
+/// Create a backup, saved to backup dir if path is none
+/// Returns the path of the backup
+#[command]
+#[tracing::instrument]
+pub fn create_backup(path: Option<String>) -> Result<String, String> {
+    let path = backup::create_backup(get_storage(), path.map(PathBuf::from))?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// Validate the backup and restart to restore it, since the data files are opened
+#[command]
+#[tracing::instrument(skip(app_handle))]
+pub fn restore_backup(path: String, app_handle: AppHandle) -> Result<(), String> {
+    snapshot::stage_restore(data_dir(), &path).map_err(|e| e.to_string())?;
+    app_handle.restart();
+}
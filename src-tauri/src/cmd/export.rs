@@ -0,0 +1,279 @@
+use chrono::{DateTime, Local, SecondsFormat};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use tauri::command;
+use tmus_engine::models::{AppId, FocusRecord};
+use tmus_engine::storage::get_storage;
+use tmus_engine::util::Timestamp;
+use tracing::info;
+
+use crate::cmd::app_detail::get_all_app_detail;
+use crate::cmd::export_sqlite::write_sqlite;
+use crate::cmd::read_helper::iter_by_timestamp;
+use crate::state::category::{get_app_category_map, get_category_path_map};
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "camelCase")]
+pub enum ExportFormat {
+    Csv,
+    Jsonl,
+    /// A database of apps, records, categories and rules
+    Sqlite,
+}
+
+/// A record with its app resolved, the columns of CSV are the fields in order
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRow {
+    pub app_id: AppId,
+    pub app_name: String,
+    pub app_path: String,
+    /// Category names from the root joined by `/`, empty if uncategorized
+    pub category: String,
+    /// ISO-8601 local time with offset
+    pub focus_at: String,
+    pub blur_at: String,
+    pub duration_ms: Timestamp,
+    /// Name of the device the record is from
+    pub device: String,
+}
+
+const CSV_HEADER: [&str; 8] = [
+    "appId",
+    "appName",
+    "appPath",
+    "category",
+    "focusAt",
+    "blurAt",
+    "durationMs",
+    "device",
+];
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportRecordsRequest {
+    pub path: String,
+    pub format: ExportFormat,
+    pub start_timestamp: Timestamp,
+    pub end_timestamp: Timestamp,
+}
+
+/// Export records in the range to the file, returns the count of records
+#[command(async)]
+#[tracing::instrument]
+pub async fn export_records(request: ExportRecordsRequest) -> Result<usize, String> {
+    write_export(
+        &request.path,
+        request.format,
+        request.start_timestamp,
+        request.end_timestamp,
+    )
+    .await
+}
+
+/// Write the records in the range to the file, records are trimmed to the range
+/// Returns the count of records
+pub async fn write_export(
+    path: impl AsRef<Path>,
+    format: ExportFormat,
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+) -> Result<usize, String> {
+    let path = path.as_ref();
+    let count = match format {
+        ExportFormat::Sqlite => write_sqlite(path, start_timestamp, end_timestamp).await?,
+        _ => write_rows(path, format, start_timestamp, end_timestamp).await?,
+    };
+    info!("Exported {} records to {:?}", count, path);
+    Ok(count)
+}
+
+async fn write_rows(
+    path: &Path,
+    format: ExportFormat,
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+) -> Result<usize, String> {
+    let resolver = RowResolver::new().await?;
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut writer = BufWriter::new(file);
+    let mut count = 0;
+    (|| {
+        if let ExportFormat::Csv = format {
+            writeln!(writer, "{}", CSV_HEADER.join(","))?;
+        }
+        for record in iter_by_timestamp(start_timestamp, end_timestamp) {
+            let row = resolver.resolve(&record);
+            if let ExportFormat::Csv = format {
+                writeln!(writer, "{}", csv_line(&row))?;
+            } else {
+                serde_json::to_writer(&mut writer, &row)?;
+                writeln!(writer)?;
+            }
+            count += 1;
+        }
+        writer.flush()
+    })()
+    .map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+/// Apps, categories and devices read once for the rows of an export
+struct RowResolver {
+    apps: HashMap<AppId, (String, String)>,
+    app_category: HashMap<AppId, String>,
+    devices: Vec<String>,
+}
+
+impl RowResolver {
+    async fn new() -> Result<Self, String> {
+        let details = get_all_app_detail().await;
+        let apps = get_storage()
+            .get_all_app()
+            .into_iter()
+            .enumerate()
+            .map(|(id, path)| {
+                let name = details.get(&id).map_or_else(
+                    || {
+                        Path::new(&path)
+                            .file_stem()
+                            .map_or_else(|| path.clone(), |x| x.to_string_lossy().into_owned())
+                    },
+                    |x| x.name.clone(),
+                );
+                (id, (name, path))
+            })
+            .collect();
+        let path_map = get_category_path_map();
+        let app_category = get_app_category_map()
+            .iter()
+            .filter_map(|(app_id, category_id)| {
+                path_map.get(category_id).map(|x| (*app_id, x.clone()))
+            })
+            .collect();
+        let devices = get_storage().devices().map_err(|e| e.to_string())?;
+        Ok(Self {
+            apps,
+            app_category,
+            devices,
+        })
+    }
+
+    fn resolve(&self, record: &FocusRecord) -> ExportRow {
+        let (app_name, app_path) = self.apps.get(&record.id).cloned().unwrap_or_default();
+        ExportRow {
+            app_id: record.id,
+            app_name,
+            app_path,
+            category: self
+                .app_category
+                .get(&record.id)
+                .cloned()
+                .unwrap_or_default(),
+            focus_at: local_time(record.focus_at),
+            blur_at: local_time(record.blur_at),
+            duration_ms: record.duration(),
+            device: self
+                .devices
+                .get(record.device as usize)
+                .cloned()
+                .unwrap_or_default(),
+        }
+    }
+}
+
+fn local_time(timestamp: Timestamp) -> String {
+    DateTime::from_timestamp_millis(timestamp)
+        .unwrap_or_default()
+        .with_timezone(&Local)
+        .to_rfc3339_opts(SecondsFormat::Millis, false)
+}
+
+fn csv_line(row: &ExportRow) -> String {
+    [
+        row.app_id.to_string(),
+        csv_field(&row.app_name),
+        csv_field(&row.app_path),
+        csv_field(&row.category),
+        row.focus_at.clone(),
+        row.blur_at.clone(),
+        row.duration_ms.to_string(),
+        csv_field(&row.device),
+    ]
+    .join(",")
+}
+
+/// Quote the field if it contains a separator, quote or line break, as RFC 4180
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmus_engine::models::LOCAL_DEVICE;
+
+    #[test]
+    fn test_csv_field() {
+        assert_eq!(csv_field("chrome"), "chrome");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("a\nb"), "\"a\nb\"");
+        assert_eq!(csv_field("a\rb"), "\"a\rb\"");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let resolver = RowResolver {
+            apps: HashMap::from([(0, ("Code".to_string(), "C:\\code.exe".to_string()))]),
+            app_category: HashMap::from([(0, "Work/Dev, Tools".to_string())]),
+            devices: vec!["local".to_string(), "laptop".to_string()],
+        };
+        let record = FocusRecord {
+            id: 0,
+            focus_at: 1_000,
+            blur_at: 61_000,
+            device: 1,
+        };
+        let row = resolver.resolve(&record);
+        assert_eq!(row.app_id, 0);
+        assert_eq!(row.app_name, "Code");
+        assert_eq!(row.app_path, "C:\\code.exe");
+        assert_eq!(row.category, "Work/Dev, Tools");
+        assert_eq!(row.focus_at, local_time(1_000));
+        assert_eq!(row.blur_at, local_time(61_000));
+        assert_eq!(row.duration_ms, 60_000);
+        assert_eq!(row.device, "laptop");
+        assert_eq!(
+            csv_line(&row),
+            format!(
+                "0,Code,C:\\code.exe,\"Work/Dev, Tools\",{},{},60000,laptop",
+                row.focus_at, row.blur_at
+            )
+        );
+        assert_eq!(csv_line(&row).split(',').count(), CSV_HEADER.len() + 1);
+
+        // Unknown apps and devices are empty instead of failing the export.
+        let row = resolver.resolve(&FocusRecord {
+            id: 5,
+            device: 7,
+            ..record
+        });
+        assert_eq!(row.app_name, "");
+        assert_eq!(row.category, "");
+        assert_eq!(row.device, "");
+        let row = resolver.resolve(&FocusRecord {
+            device: LOCAL_DEVICE,
+            ..record
+        });
+        assert_eq!(row.device, "local");
+    }
+}
@@ -0,0 +1,304 @@
+//! Export to a SQLite database, so the data can be queried by SQL directly.
+//!
+//! Timestamps are milliseconds since the unix epoch in UTC, as they are stored.
+//! The database is written to a temporary file first, so an existing file is only replaced
+//! by a complete export.
+
+use rusqlite::{params, Connection};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tmus_engine::models::{AppId, AppInfo, FocusRecord};
+use tmus_engine::storage::get_storage;
+use tmus_engine::util::{now_timestamp, Timestamp};
+
+use crate::cmd::app_detail::{get_all_app_detail, FileDetail};
+use crate::cmd::read_helper::iter_by_timestamp;
+use crate::state::category::get_category_tree;
+use crate::state::get_rule;
+
+const SCHEMA: &str = "
+CREATE TABLE meta (
+    key TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE app (
+    id INTEGER PRIMARY KEY,
+    path TEXT NOT NULL,
+    name TEXT NOT NULL,
+    exist INTEGER NOT NULL,
+    first_seen INTEGER NOT NULL,
+    last_seen INTEGER NOT NULL,
+    merged_into INTEGER REFERENCES app (id),
+    comments TEXT,
+    internal_name TEXT,
+    product_name TEXT,
+    company_name TEXT,
+    legal_copyright TEXT,
+    product_version TEXT,
+    file_description TEXT,
+    legal_trademarks TEXT,
+    private_build TEXT,
+    file_version TEXT,
+    original_filename TEXT,
+    special_build TEXT
+);
+CREATE TABLE device (
+    id INTEGER PRIMARY KEY,
+    name TEXT NOT NULL
+);
+CREATE TABLE record (
+    id INTEGER PRIMARY KEY,
+    app_id INTEGER NOT NULL REFERENCES app (id),
+    focus_at INTEGER NOT NULL,
+    blur_at INTEGER NOT NULL,
+    duration INTEGER NOT NULL,
+    device_id INTEGER NOT NULL REFERENCES device (id)
+);
+CREATE INDEX record_focus_at ON record (focus_at);
+CREATE INDEX record_app_id ON record (app_id);
+CREATE TABLE category (
+    id INTEGER PRIMARY KEY,
+    parent_id INTEGER REFERENCES category (id),
+    name TEXT NOT NULL
+);
+CREATE TABLE category_app (
+    category_id INTEGER NOT NULL REFERENCES category (id),
+    app_id INTEGER NOT NULL REFERENCES app (id),
+    PRIMARY KEY (category_id, app_id)
+);
+CREATE TABLE rule (
+    kind TEXT NOT NULL,
+    path TEXT NOT NULL,
+    to_path TEXT
+);
+";
+
+/// Write apps, records in the range, categories and rules to a new database at the path
+/// Returns the count of records
+pub async fn write_sqlite(
+    path: &Path,
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+) -> Result<usize, String> {
+    let details = get_all_app_detail().await;
+    let devices = get_storage().devices().map_err(|e| e.to_string())?;
+    let mut temp_path = path.as_os_str().to_owned();
+    temp_path.push(".tmp");
+    let temp_path = PathBuf::from(temp_path);
+    if temp_path.exists() {
+        fs::remove_file(&temp_path).map_err(|e| e.to_string())?;
+    }
+    let count = write_database(
+        &temp_path,
+        &details,
+        &devices,
+        start_timestamp,
+        end_timestamp,
+    )
+    .map_err(|e| e.to_string())?;
+    fs::rename(&temp_path, path).map_err(|e| e.to_string())?;
+    Ok(count)
+}
+
+fn write_database(
+    path: &Path,
+    details: &HashMap<AppId, FileDetail>,
+    devices: &[String],
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+) -> rusqlite::Result<usize> {
+    let mut conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+    let tx = conn.transaction()?;
+
+    let mut stmt = tx.prepare("INSERT INTO meta (key, value) VALUES (?1, ?2)")?;
+    let meta = [
+        ("tmusVersion", env!("CARGO_PKG_VERSION").to_string()),
+        ("exportedAt", now_timestamp().to_string()),
+        ("startTimestamp", start_timestamp.to_string()),
+        ("endTimestamp", end_timestamp.to_string()),
+    ];
+    for (key, value) in meta {
+        stmt.execute(params![key, value])?;
+    }
+    drop(stmt);
+
+    let mut stmt = tx.prepare(
+        "INSERT INTO app VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, \
+         ?16, ?17, ?18, ?19)",
+    )?;
+    for AppInfo {
+        id,
+        path,
+        first_seen,
+        last_seen,
+        merged_into,
+    } in get_storage().get_all_app_info()
+    {
+        let detail = details.get(&id);
+        let name = detail.map_or_else(
+            || {
+                Path::new(&path)
+                    .file_stem()
+                    .map_or_else(|| path.clone(), |x| x.to_string_lossy().into_owned())
+            },
+            |x| x.name.clone(),
+        );
+        let exist = detail.is_some_and(|x| x.exist);
+        let version = detail.and_then(|x| x.version.clone()).unwrap_or_default();
+        stmt.execute(params![
+            id,
+            path,
+            name,
+            exist,
+            first_seen,
+            last_seen,
+            merged_into,
+            version.comments,
+            version.internal_name,
+            version.product_name,
+            version.company_name,
+            version.legal_copyright,
+            version.product_version,
+            version.file_description,
+            version.legal_trademarks,
+            version.private_build,
+            version.file_version,
+            version.original_filename,
+            version.special_build,
+        ])?;
+    }
+    drop(stmt);
+
+    let count = insert_records(
+        &tx,
+        devices,
+        iter_by_timestamp(start_timestamp, end_timestamp),
+    )?;
+
+    let mut category_stmt =
+        tx.prepare("INSERT INTO category (id, parent_id, name) VALUES (?1, ?2, ?3)")?;
+    let mut app_stmt =
+        tx.prepare("INSERT OR IGNORE INTO category_app (category_id, app_id) VALUES (?1, ?2)")?;
+    let root = get_category_tree();
+    let mut stack = vec![(root, None)];
+    while let Some((node, parent_id)) = stack.pop() {
+        let node = node.lock().unwrap();
+        category_stmt.execute(params![node.id, parent_id, node.name])?;
+        for app_id in &node.app_ids {
+            app_stmt.execute(params![node.id, app_id])?;
+        }
+        stack.extend(node.children.iter().map(|x| (x.clone(), Some(node.id))));
+    }
+    drop(category_stmt);
+    drop(app_stmt);
+
+    let rule = get_rule().lock().unwrap().clone();
+    let mut stmt = tx.prepare("INSERT INTO rule (kind, path, to_path) VALUES (?1, ?2, ?3)")?;
+    for (kind, path, to_path) in rule.items() {
+        stmt.execute(params![kind, path, to_path])?;
+    }
+    drop(stmt);
+
+    tx.commit()?;
+    Ok(count)
+}
+
+/// Insert the devices and the records, returns the count of records
+fn insert_records(
+    conn: &Connection,
+    devices: &[String],
+    records: impl IntoIterator<Item = FocusRecord>,
+) -> rusqlite::Result<usize> {
+    let mut stmt = conn.prepare("INSERT INTO device (id, name) VALUES (?1, ?2)")?;
+    for (id, name) in devices.iter().enumerate() {
+        stmt.execute(params![id, name])?;
+    }
+    drop(stmt);
+
+    let mut stmt = conn.prepare(
+        "INSERT INTO record (app_id, focus_at, blur_at, duration, device_id) \
+         VALUES (?1, ?2, ?3, ?4, ?5)",
+    )?;
+    let mut count = 0;
+    for record in records {
+        stmt.execute(params![
+            record.id,
+            record.focus_at,
+            record.blur_at,
+            record.duration(),
+            record.device,
+        ])?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmus_engine::models::LOCAL_DEVICE;
+
+    #[test]
+    fn test_insert_records() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(SCHEMA).unwrap();
+        conn.execute(
+            "INSERT INTO app (id, path, name, exist, first_seen, last_seen) \
+             VALUES (0, 'C:\\code.exe', 'Code', 1, 1000, 9000)",
+            [],
+        )
+        .unwrap();
+        let devices = ["local".to_string(), "laptop".to_string()];
+        let records = [
+            FocusRecord {
+                id: 0,
+                focus_at: 1000,
+                blur_at: 4000,
+                device: LOCAL_DEVICE,
+            },
+            FocusRecord {
+                id: 0,
+                focus_at: 5000,
+                blur_at: 9000,
+                device: 1,
+            },
+        ];
+        assert_eq!(insert_records(&conn, &devices, records).unwrap(), 2);
+
+        let mut stmt = conn
+            .prepare(
+                "SELECT record.app_id, record.focus_at, record.blur_at, record.duration, device.name \
+                 FROM record JOIN device ON device.id = record.device_id ORDER BY record.focus_at",
+            )
+            .unwrap();
+        let rows: Vec<(AppId, Timestamp, Timestamp, Timestamp, String)> = stmt
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(
+            rows,
+            [
+                (0, 1000, 4000, 3000, "local".to_string()),
+                (0, 5000, 9000, 4000, "laptop".to_string()),
+            ]
+        );
+        // Every record refers to an app and a device.
+        let violations: usize = conn
+            .query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(violations, 0);
+    }
+}
@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::command;
+use tmus_engine::storage::get_storage;
+
+use crate::import::csv::CsvMapping;
+use crate::import::data_dir::{self, AppMatcher, MergeDirResult};
+use crate::import::{self, activity_watch, ImportResult};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportActivityWatchRequest {
+    pub path: String,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportCsvRequest {
+    pub path: String,
+    pub mapping: CsvMapping,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeDataDirRequest {
+    pub path: String,
+    /// Tag the merged records with the device, or count them as local if none
+    pub device: Option<String>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+/// Import the window events of an ActivityWatch export
+/// Parsing and writing block, so they run on the blocking threads
+#[command(async)]
+#[tracing::instrument]
+pub async fn import_activity_watch(
+    request: ImportActivityWatchRequest,
+) -> Result<ImportResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let storage = get_storage();
+        let parsed = activity_watch::parse(storage, Path::new(&request.path))?;
+        import::import_spans(storage, parsed, request.dry_run)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Import the rows of a CSV file by the column mapping
+/// Parsing and writing block, so they run on the blocking threads
+#[command(async)]
+#[tracing::instrument]
+pub async fn import_csv(request: ImportCsvRequest) -> Result<ImportResult, String> {
+    tauri::async_runtime::spawn_blocking(move || {
+        let storage = get_storage();
+        let parsed = import::csv::parse(storage, Path::new(&request.path), &request.mapping)?;
+        import::import_spans(storage, parsed, request.dry_run)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Merge the records of the data directory of tmus on another machine
+/// Merging blocks, so it runs on the blocking threads
+#[command(async)]
+#[tracing::instrument]
+pub async fn merge_data_dir(request: MergeDataDirRequest) -> Result<MergeDirResult, String> {
+    let storage = get_storage();
+    let matcher = AppMatcher::new(storage).await;
+    tauri::async_runtime::spawn_blocking(move || {
+        data_dir::merge_data_dir(
+            storage,
+            Path::new(&request.path),
+            request.device.as_deref(),
+            &matcher,
+            request.dry_run,
+        )
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
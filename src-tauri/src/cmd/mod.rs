@@ -1,18 +1,24 @@
+use crate::app::global::get_storage_error;
 use crate::app::update;
 
 use crate::util;
 use serde::{Deserialize, Serialize};
 use tauri::ipc::Invoke;
 use tauri::Wry;
-use tmus_engine::models::{EngineMeta, FocusRecord};
-use tmus_engine::storage;
+use tmus_engine::models::{AppId, EngineMeta, FocusRecord};
 use tmus_engine::storage::focus_index;
+use tmus_engine::storage::get_storage;
 use tmus_engine::util::Timestamp;
 
 pub mod app_detail;
 pub mod app_duration_area;
+pub mod app_history;
+pub mod backup;
 pub mod category;
 pub mod duration;
+pub mod export;
+pub mod export_sqlite;
+pub mod import;
 pub mod read_helper;
 pub mod scheme;
 pub mod statistic;
@@ -22,8 +28,13 @@ pub use read_helper::read_by_timestamp;
 pub fn handler() -> impl Fn(Invoke<Wry>) -> bool + Send + Sync + 'static {
     tauri::generate_handler![
         get_raw_record,
+        insert_record,
+        delete_record_range,
+        reattribute_record_range,
+        split_record,
         show_in_folder,
         get_tmus_meta,
+        get_devices,
         focus_index_record,
         crate::state::get_app_config,
         crate::state::set_app_config,
@@ -32,8 +43,19 @@ pub fn handler() -> impl Fn(Invoke<Wry>) -> bool + Send + Sync + 'static {
         crate::mcp::server::start_mcp_server,
         crate::mcp::server::stop_mcp_server,
         crate::mcp::server::get_mcp_server_status,
+        crate::sync::server::start_sync_server,
+        crate::sync::server::stop_sync_server,
+        crate::sync::server::get_sync_server_status,
         app_detail::get_app_detail,
         app_detail::get_all_app_detail,
+        app_history::merge_apps,
+        app_history::forget_app,
+        backup::create_backup,
+        backup::restore_backup,
+        export::export_records,
+        import::import_activity_watch,
+        import::import_csv,
+        import::merge_data_dir,
         update::fetch_update,
         update::install_update,
         app_duration_area::get_app_duration_area,
@@ -66,7 +88,10 @@ pub fn handler() -> impl Fn(Invoke<Wry>) -> bool + Send + Sync + 'static {
 pub struct TmusMeta {
     pub initial_timestamp: Timestamp,
     pub engine_version: String,
+    pub format_version: u32,
     pub tmus_version: String,
+    /// Set if the storage failed to open for tracking, it's read-only then
+    pub storage_error: Option<String>,
 }
 
 #[tauri::command]
@@ -75,6 +100,54 @@ pub fn get_raw_record(start_timestamp: Timestamp, end_timestamp: Timestamp) -> V
     read_helper::read_by_timestamp(start_timestamp, end_timestamp)
 }
 
+/// Add a manual record, the time of other records in its range is replaced
+#[tauri::command]
+#[tracing::instrument]
+pub fn insert_record(
+    app_path: String,
+    focus_at: Timestamp,
+    blur_at: Timestamp,
+) -> Result<AppId, String> {
+    get_storage()
+        .insert_record(&app_path, focus_at, blur_at)
+        .map_err(|e| e.to_string())
+}
+
+/// Delete the time of records in the range, of all apps if app id is none
+#[tauri::command]
+#[tracing::instrument]
+pub fn delete_record_range(
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+    app_id: Option<AppId>,
+) -> Result<usize, String> {
+    get_storage()
+        .delete_range(start_timestamp, end_timestamp, app_id)
+        .map_err(|e| e.to_string())
+}
+
+/// Move the time of records in the range to another app, from all apps if from app id is none
+#[tauri::command]
+#[tracing::instrument]
+pub fn reattribute_record_range(
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+    from_app_id: Option<AppId>,
+    to_app_id: AppId,
+) -> Result<usize, String> {
+    get_storage()
+        .reattribute_range(start_timestamp, end_timestamp, from_app_id, to_app_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+#[tracing::instrument]
+pub fn split_record(timestamp: Timestamp) -> Result<bool, String> {
+    get_storage()
+        .split_record(timestamp)
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 #[tracing::instrument]
 pub fn show_in_folder(path: String) {
@@ -87,16 +160,26 @@ pub fn get_tmus_meta() -> TmusMeta {
     let EngineMeta {
         initial_timestamp,
         engine_version,
-    } = storage::get_tmus_meta();
+        format_version,
+    } = get_storage().meta();
     TmusMeta {
         initial_timestamp,
         engine_version,
+        format_version,
         tmus_version: env!("CARGO_PKG_VERSION").to_string(),
+        storage_error: get_storage_error().cloned(),
     }
 }
 
+/// Device names indexed by the device id of records
+#[tauri::command]
+#[tracing::instrument]
+pub fn get_devices() -> Result<Vec<String>, String> {
+    get_storage().devices().map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 #[tracing::instrument]
 pub fn focus_index_record() -> Vec<focus_index::FileIndexRecord> {
-    focus_index::all_record()
+    get_storage().all_index_record()
 }
@@ -1,8 +1,10 @@
 use std::cmp::{max, min};
 use tmus_engine::models::FocusRecord;
-use tmus_engine::storage;
+use tmus_engine::storage::get_storage;
 use tmus_engine::util::Timestamp;
-use tracing::debug;
+use tracing::{debug, error};
+
+use crate::state::get_config;
 
 // For compute convenience, remember convert them back after compute.
 pub fn timezone_convert(mut vec: Vec<FocusRecord>, timezone_offset: Timestamp) -> Vec<FocusRecord> {
@@ -15,6 +17,7 @@ pub fn timezone_convert(mut vec: Vec<FocusRecord>, timezone_offset: Timestamp) -
 
 /// Reads all focus records that fall within the specified time range.
 /// If a record not entirely falls inside the time range, it will be trimmed to fit.
+/// Records of other devices are skipped if the config filters a device.
 ///
 /// # Arguments
 /// - `start_timestamp`: The start of the time range, inclusive.
@@ -27,7 +30,10 @@ pub fn read_by_timestamp(start_timestamp: Timestamp, end_timestamp: Timestamp) -
         start_timestamp < end_timestamp,
         "Start_timestamp must be less than end_timestamp"
     );
-    let rough_records = storage::read_by_timestamp(start_timestamp, end_timestamp);
+    let mut rough_records = get_storage().read_by_timestamp(start_timestamp, end_timestamp);
+    if let Some(device) = get_config().device {
+        rough_records.retain(|x| x.device == device);
+    }
     debug!(
         "Read rough records, start: {:?}, end: {:?}, len: {:?}",
         start_timestamp,
@@ -37,6 +43,32 @@ pub fn read_by_timestamp(start_timestamp: Timestamp, end_timestamp: Timestamp) -
     trim_focus_records(rough_records, start_timestamp, end_timestamp)
 }
 
+/// Iterates focus records within the specified time range, same as [`read_by_timestamp`],
+/// but records are read lazily instead of collected first.
+///
+/// # Arguments
+/// - `start_timestamp`: The start of the time range, inclusive.
+/// - `end_timestamp`: The end of the time range, inclusive.
+pub fn iter_by_timestamp(
+    start_timestamp: Timestamp,
+    end_timestamp: Timestamp,
+) -> impl Iterator<Item = FocusRecord> {
+    let device = get_config().device;
+    get_storage()
+        .iter_by_timestamp(start_timestamp, end_timestamp)
+        .filter_map(|x| {
+            x.inspect_err(|e| error!("Failed to read records: {}", e))
+                .ok()
+        })
+        .filter(move |x| device.is_none_or(|device| x.device == device))
+        .filter(move |x| x.blur_at > start_timestamp && x.focus_at < end_timestamp)
+        .map(move |mut x| {
+            x.focus_at = max(x.focus_at, start_timestamp);
+            x.blur_at = min(x.blur_at, end_timestamp);
+            x
+        })
+}
+
 /// Trims focus records to retain only those within the specified time range.
 ///
 /// This function accepts a vector of focus records along with start and end times in milliseconds.
@@ -83,6 +115,7 @@ fn trim_focus_records(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tmus_engine::models::LOCAL_DEVICE;
 
     #[test]
     fn test_trim_focus_records() {
@@ -134,6 +167,7 @@ mod tests {
             id: 0,
             focus_at,
             blur_at,
+            device: LOCAL_DEVICE,
         }
     }
 }
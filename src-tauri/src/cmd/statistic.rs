@@ -145,9 +145,8 @@ pub fn get_base_time() -> u64 {
 pub async fn get_app_total_duration(
     request: AppDurationRequest,
 ) -> Result<AppDurationResponse, String> {
-    let records = read_helper::read_by_timestamp(request.start_time, request.end_time);
     let mut map = HashMap::new();
-    for record in records {
+    for record in read_helper::iter_by_timestamp(request.start_time, request.end_time) {
         let app_id = record.id;
         let duration = record.duration();
         *map.entry(app_id).or_insert(0) += duration;
@@ -166,10 +165,9 @@ pub async fn get_app_total_duration(
 pub async fn get_app_usage_days(
     request: AppDurationRequest,
 ) -> Result<AppDayCountResponse, String> {
-    let records = read_helper::read_by_timestamp(request.start_time, request.end_time);
     let timezone_offset = infer_timezone_offset(request.start_time);
     let mut map: HashMap<AppId, HashSet<i64>> = HashMap::new();
-    for record in records {
+    for record in read_helper::iter_by_timestamp(request.start_time, request.end_time) {
         let start_day = ms_as_d(record.focus_at + timezone_offset);
         let end_day = ms_as_d(record.blur_at + timezone_offset);
         let entry = map.entry(record.id).or_insert_with(HashSet::new);
@@ -203,8 +201,7 @@ pub fn get_category_total_duration(
     let app_category_map = get_app_category_map();
     let mut categorized_duration_map: HashMap<CategoryId, i64> = HashMap::new();
     let mut uncategorized_duration = 0;
-    let records = read_helper::read_by_timestamp(request.start_time, request.end_time);
-    for record in records {
+    for record in read_helper::iter_by_timestamp(request.start_time, request.end_time) {
         let category_id = app_category_map.get(&record.id);
         if let Some(category_id) = category_id {
             *categorized_duration_map.entry(*category_id).or_insert(0) += record.duration();
@@ -261,8 +258,7 @@ pub fn get_category_usage_days(
     let timezone_offset = infer_timezone_offset(request.start_time);
     let mut uncategorized_days = HashSet::new();
     let mut categorized_days_map: HashMap<CategoryId, HashSet<i64>> = HashMap::new();
-    let records = read_helper::read_by_timestamp(request.start_time, request.end_time);
-    for record in records {
+    for record in read_helper::iter_by_timestamp(request.start_time, request.end_time) {
         let category_id = app_category_map.get(&record.id);
         let start_day = ms_as_d(record.focus_at + timezone_offset);
         let end_day = ms_as_d(record.blur_at + timezone_offset);
@@ -374,14 +370,10 @@ pub fn get_category_usage_rhythm(request: RhythmRequest) -> Result<RhythmDataRes
                     }
                 };
                 let mut ret = vec![0; granularity_multiple as usize];
-                let records = read_helper::read_by_timestamp(start_time, end_time);
-                if records.is_empty() {
-                    return ret;
-                }
                 let mut cur_index = -1;
                 let mut cur_bound = 0;
 
-                for record in records {
+                for record in read_helper::iter_by_timestamp(start_time, end_time) {
                     if !filter_records(&record) {
                         continue;
                     }
@@ -0,0 +1,191 @@
+//! Read the window buckets of an ActivityWatch export, made by "Export bucket" or
+//! "Export all buckets" in ActivityWatch.
+
+use chrono::DateTime;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use tmus_engine::models::LOCAL_DEVICE;
+use tmus_engine::storage::import::ImportSpan;
+use tmus_engine::storage::Storage;
+use tmus_engine::util::Timestamp;
+
+use crate::import::{resolve_app_path, ParsedSpans};
+
+/// Buckets of aw-watcher-window
+const WINDOW_BUCKET_TYPE: &str = "currentwindow";
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Export {
+    Buckets { buckets: HashMap<String, Bucket> },
+    Bucket(Bucket),
+}
+
+#[derive(Debug, Deserialize)]
+struct Bucket {
+    #[serde(rename = "type", default)]
+    bucket_type: String,
+    #[serde(default)]
+    events: Vec<Event>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Event {
+    timestamp: Option<String>,
+    /// Seconds
+    duration: Option<f64>,
+    #[serde(default)]
+    data: EventData,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct EventData {
+    app: Option<String>,
+}
+
+/// Read the events of the window buckets as spans.
+/// Apps are resolved to registered paths by their executable name, events of unknown apps are
+/// counted and skipped.
+pub fn parse(storage: &Storage, path: &Path) -> Result<ParsedSpans, String> {
+    let content = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let export: Export = serde_json::from_str(&content).map_err(|e| e.to_string())?;
+    let buckets: Vec<Bucket> = match export {
+        Export::Buckets { buckets } => buckets.into_values().collect(),
+        Export::Bucket(bucket) => vec![bucket],
+    };
+    let buckets: Vec<Bucket> = buckets
+        .into_iter()
+        .filter(|x| x.bucket_type == WINDOW_BUCKET_TYPE)
+        .collect();
+    if buckets.is_empty() {
+        return Err("No window bucket found in the export".to_string());
+    }
+
+    let mut parsed = ParsedSpans::default();
+    let mut app_paths: HashMap<String, Option<String>> = HashMap::new();
+    for (index, event) in buckets.into_iter().flat_map(|x| x.events).enumerate() {
+        let Some((app, focus_at, blur_at)) = parse_event(event) else {
+            parsed.add_invalid(format!(
+                "Event {} has no valid app, timestamp or duration",
+                index
+            ));
+            continue;
+        };
+        // A single heartbeat of the watcher, which has nothing to import.
+        if blur_at == focus_at {
+            continue;
+        }
+        let Some(path) = app_paths
+            .entry(app)
+            .or_insert_with_key(|app| resolve_app_path(storage, app))
+            .clone()
+        else {
+            parsed.unknown += 1;
+            continue;
+        };
+        parsed.spans.push(ImportSpan {
+            path,
+            focus_at,
+            blur_at,
+            device: LOCAL_DEVICE,
+        });
+    }
+    Ok(parsed)
+}
+
+fn parse_event(event: Event) -> Option<(String, Timestamp, Timestamp)> {
+    let app = event.data.app.filter(|x| !x.is_empty())?;
+    let focus_at = DateTime::parse_from_rfc3339(&event.timestamp?)
+        .ok()?
+        .timestamp_millis();
+    let duration = event.duration.filter(|x| x.is_finite() && *x >= 0.0)?;
+    Some((app, focus_at, focus_at + (duration * 1000.0) as Timestamp))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tmus_engine::storage::test_util::temp_data_dir;
+    use tmus_engine::util::now_timestamp;
+
+    #[test]
+    fn test_parse_event() {
+        let event = |app: Option<&str>, timestamp: Option<&str>, duration: Option<f64>| Event {
+            timestamp: timestamp.map(str::to_string),
+            duration,
+            data: EventData {
+                app: app.map(str::to_string),
+            },
+        };
+        let at = "2024-05-01T08:00:00.250+00:00";
+        let focus_at = DateTime::parse_from_rfc3339(at).unwrap().timestamp_millis();
+        assert_eq!(
+            parse_event(event(Some("chrome.exe"), Some(at), Some(1.5))),
+            Some(("chrome.exe".to_string(), focus_at, focus_at + 1500))
+        );
+        assert_eq!(
+            parse_event(event(Some("chrome.exe"), Some(at), Some(0.0))),
+            Some(("chrome.exe".to_string(), focus_at, focus_at))
+        );
+        assert_eq!(parse_event(event(None, Some(at), Some(1.0))), None);
+        assert_eq!(parse_event(event(Some(""), Some(at), Some(1.0))), None);
+        assert_eq!(parse_event(event(Some("a"), None, Some(1.0))), None);
+        assert_eq!(
+            parse_event(event(Some("a"), Some("yesterday"), Some(1.0))),
+            None
+        );
+        assert_eq!(parse_event(event(Some("a"), Some(at), None)), None);
+        assert_eq!(parse_event(event(Some("a"), Some(at), Some(-1.0))), None);
+        assert_eq!(
+            parse_event(event(Some("a"), Some(at), Some(f64::NAN))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_parse() {
+        let data_dir = temp_data_dir("activity-watch");
+        let storage = Storage::open(&data_dir).unwrap();
+        let now = now_timestamp();
+        storage
+            .write_record("C:/Chrome/chrome.exe", now - 2000, now)
+            .unwrap();
+        let path = data_dir.join("aw-buckets-export.json");
+        let export = r#"{"buckets": {
+            "aw-watcher-window_host": {"type": "currentwindow", "events": [
+                {"timestamp": "2024-05-01T08:00:00+00:00", "duration": 60, "data": {"app": "chrome.exe"}},
+                {"timestamp": "2024-05-01T08:01:00+00:00", "duration": 0, "data": {"app": "chrome.exe"}},
+                {"timestamp": "2024-05-01T08:01:00+00:00", "duration": 30.5, "data": {"app": "Code"}},
+                {"timestamp": "2024-05-01T08:02:00+00:00", "duration": 10, "data": {}}
+            ]},
+            "aw-watcher-afk_host": {"type": "afkstatus", "events": [
+                {"timestamp": "2024-05-01T08:00:00+00:00", "duration": 600, "data": {"status": "afk"}}
+            ]}
+        }}"#;
+        fs::write(&path, export).unwrap();
+
+        let parsed = parse(&storage, &path).unwrap();
+        let focus_at = DateTime::parse_from_rfc3339("2024-05-01T08:00:00+00:00")
+            .unwrap()
+            .timestamp_millis();
+        let spans: Vec<(&str, Timestamp, Timestamp)> = parsed
+            .spans
+            .iter()
+            .map(|x| (x.path.as_str(), x.focus_at, x.blur_at))
+            .collect();
+        // The executable name is resolved to the registered app, unknown apps are skipped.
+        assert_eq!(
+            spans,
+            [("C:/Chrome/chrome.exe", focus_at, focus_at + 60_000)]
+        );
+        assert_eq!(parsed.unknown, 1);
+        // The zero-length event is skipped without being invalid.
+        assert_eq!(parsed.invalid, 1);
+        assert_eq!(parsed.errors.len(), 1);
+
+        fs::write(&path, r#"{"type": "afkstatus", "events": []}"#).unwrap();
+        assert!(parse(&storage, &path).is_err());
+    }
+}
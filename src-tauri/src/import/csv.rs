@@ -0,0 +1,357 @@
+//! Read spans from any CSV file by a column mapping, like exports of RescueTime or ManicTime.
+
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use tmus_engine::models::LOCAL_DEVICE;
+use tmus_engine::storage::import::ImportSpan;
+use tmus_engine::storage::Storage;
+use tmus_engine::util::Timestamp;
+
+use crate::import::{resolve_app_path, ParsedSpans};
+
+/// Formats tried for times without an offset if the mapping has no time format
+const TIME_FORMATS: [&str; 5] = [
+    "%Y-%m-%d %H:%M:%S%.f",
+    "%Y-%m-%dT%H:%M:%S%.f",
+    "%Y/%m/%d %H:%M:%S%.f",
+    "%Y-%m-%d %H:%M",
+    "%Y/%m/%d %H:%M",
+];
+
+/// Which columns hold the span, a column is a header name or a 0-based index
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CsvMapping {
+    /// App path, or the executable name which is resolved to a registered app
+    pub app: String,
+    pub start: String,
+    /// End time, or use the duration
+    pub end: Option<String>,
+    /// Seconds, or `h:mm:ss`
+    pub duration: Option<String>,
+    /// `utc`, `local` or an offset like `+08:00`, for times without an offset, `local` if none
+    pub time_zone: Option<String>,
+    /// chrono format of times without an offset, common formats are tried if none
+    pub time_format: Option<String>,
+    /// `,` if none
+    pub delimiter: Option<char>,
+    /// The first row is data instead of header names
+    #[serde(default)]
+    pub no_header: bool,
+}
+
+enum Zone {
+    Utc,
+    Local,
+    Fixed(FixedOffset),
+}
+
+struct Columns {
+    app: usize,
+    start: usize,
+    end: Option<usize>,
+    duration: Option<usize>,
+}
+
+/// Read the rows as spans, invalid rows are counted and reported
+pub fn parse(storage: &Storage, path: &Path, mapping: &CsvMapping) -> Result<ParsedSpans, String> {
+    if mapping.end.is_none() == mapping.duration.is_none() {
+        return Err("Map either the end or the duration column".to_string());
+    }
+    let zone = match mapping.time_zone.as_deref().map(str::trim) {
+        None | Some("") | Some("local") => Zone::Local,
+        Some("utc") | Some("UTC") => Zone::Utc,
+        Some(offset) => Zone::Fixed(
+            offset
+                .parse()
+                .map_err(|_| format!("Invalid time zone {}", offset))?,
+        ),
+    };
+    let delimiter = mapping.delimiter.unwrap_or(',');
+    if !delimiter.is_ascii() {
+        return Err(format!("Invalid delimiter {}", delimiter));
+    }
+    let mut reader = ::csv::ReaderBuilder::new()
+        .delimiter(delimiter as u8)
+        .has_headers(!mapping.no_header)
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| e.to_string())?;
+    let headers = if mapping.no_header {
+        Vec::new()
+    } else {
+        let headers = reader.headers().map_err(|e| e.to_string())?;
+        headers.iter().map(|x| x.trim().to_string()).collect()
+    };
+    let column = |name: &str| -> Result<usize, String> {
+        headers
+            .iter()
+            .position(|x| x == name)
+            .or_else(|| name.parse().ok())
+            .ok_or_else(|| format!("Column {} not found", name))
+    };
+    let columns = Columns {
+        app: column(&mapping.app)?,
+        start: column(&mapping.start)?,
+        end: mapping.end.as_deref().map(column).transpose()?,
+        duration: mapping.duration.as_deref().map(column).transpose()?,
+    };
+
+    let mut parsed = ParsedSpans::default();
+    let mut app_paths: HashMap<String, String> = HashMap::new();
+    for row in reader.records() {
+        // Lines are counted by the reader, including line breaks in quoted fields.
+        let line = match &row {
+            Ok(row) => row.position(),
+            Err(e) => e.position(),
+        }
+        .map(|x| x.line());
+        let span = row.map_err(|e| e.to_string()).and_then(|row| {
+            let field = |column: usize| {
+                row.get(column)
+                    .map(str::trim)
+                    .filter(|x| !x.is_empty())
+                    .ok_or_else(|| format!("column {} is empty", column))
+            };
+            let app = field(columns.app)?;
+            let focus_at = parse_time(field(columns.start)?, &zone, mapping)?;
+            let blur_at = match (columns.end, columns.duration) {
+                (Some(end), _) => parse_time(field(end)?, &zone, mapping)?,
+                (_, Some(duration)) => focus_at + parse_duration(field(duration)?)?,
+                _ => unreachable!(),
+            };
+            if blur_at <= focus_at {
+                return Err("end isn't after start".to_string());
+            }
+            Ok((app.to_string(), focus_at, blur_at))
+        });
+        let (app, focus_at, blur_at) = match span {
+            Ok(span) => span,
+            Err(e) => {
+                parsed.add_invalid(match line {
+                    Some(line) => format!("Line {}: {}", line, e),
+                    None => e,
+                });
+                continue;
+            }
+        };
+        let path = app_paths
+            .entry(app)
+            .or_insert_with_key(|app| resolve_app_path(storage, app).unwrap_or_else(|| app.clone()))
+            .clone();
+        parsed.spans.push(ImportSpan {
+            path,
+            focus_at,
+            blur_at,
+            device: LOCAL_DEVICE,
+        });
+    }
+    Ok(parsed)
+}
+
+fn parse_time(value: &str, zone: &Zone, mapping: &CsvMapping) -> Result<Timestamp, String> {
+    if mapping.time_format.is_none() {
+        if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+            return Ok(time.timestamp_millis());
+        }
+    }
+    let naive = match &mapping.time_format {
+        Some(format) => NaiveDateTime::parse_from_str(value, format).ok(),
+        None => TIME_FORMATS
+            .iter()
+            .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok()),
+    }
+    .ok_or_else(|| format!("invalid time {}", value))?;
+    let time = match zone {
+        Zone::Utc => Some(Utc.from_utc_datetime(&naive).timestamp_millis()),
+        Zone::Local => Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(|x| x.timestamp_millis()),
+        Zone::Fixed(offset) => offset
+            .from_local_datetime(&naive)
+            .single()
+            .map(|x| x.timestamp_millis()),
+    };
+    time.ok_or_else(|| format!("time {} doesn't exist in the time zone", value))
+}
+
+/// Seconds like `90.5`, or `h:mm:ss` like `1:02:03`
+fn parse_duration(value: &str) -> Result<Timestamp, String> {
+    let invalid = || format!("invalid duration {}", value);
+    let seconds = if value.contains(':') {
+        value.split(':').try_fold(0.0, |total, part| {
+            part.parse::<f64>()
+                .map(|x| total * 60.0 + x)
+                .map_err(|_| invalid())
+        })?
+    } else {
+        value.parse::<f64>().map_err(|_| invalid())?
+    };
+    if !seconds.is_finite() || seconds < 0.0 {
+        return Err(invalid());
+    }
+    Ok((seconds * 1000.0) as Timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tmus_engine::storage::test_util::temp_data_dir;
+    use tmus_engine::util::now_timestamp;
+
+    fn map_columns(app: &str, start: &str) -> CsvMapping {
+        CsvMapping {
+            app: app.to_string(),
+            start: start.to_string(),
+            end: None,
+            duration: None,
+            time_zone: Some("utc".to_string()),
+            time_format: None,
+            delimiter: None,
+            no_header: false,
+        }
+    }
+
+    fn spans(parsed: &ParsedSpans) -> Vec<(&str, Timestamp, Timestamp)> {
+        parsed
+            .spans
+            .iter()
+            .map(|x| (x.path.as_str(), x.focus_at, x.blur_at))
+            .collect()
+    }
+
+    fn utc(value: &str) -> Timestamp {
+        DateTime::parse_from_rfc3339(value)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn test_parse() {
+        let data_dir = temp_data_dir("csv");
+        let storage = Storage::open(&data_dir).unwrap();
+        let now = now_timestamp();
+        storage
+            .write_record("C:/Chrome/chrome.exe", now - 2000, now)
+            .unwrap();
+        let path = data_dir.join("export.csv");
+        let start = utc("2024-05-01T08:00:00Z");
+
+        // Columns by header names, the end time, and a quoted field with the delimiter.
+        fs::write(
+            &path,
+            "Application,Start,End\n\
+             chrome.exe,2024-05-01 08:00:00,2024-05-01 08:01:00\n\
+             \"Code, Insiders\",2024-05-01T08:01:00+00:00,2024-05-01 08:02:30\n",
+        )
+        .unwrap();
+        let mapping = CsvMapping {
+            end: Some("End".to_string()),
+            ..map_columns("Application", "Start")
+        };
+        let parsed = parse(&storage, &path, &mapping).unwrap();
+        assert_eq!(parsed.invalid, 0);
+        assert_eq!(
+            spans(&parsed),
+            [
+                ("C:/Chrome/chrome.exe", start, start + 60_000),
+                ("Code, Insiders", start + 60_000, start + 150_000),
+            ]
+        );
+
+        // Columns by indexes without a header, the duration, a delimiter and a time zone.
+        fs::write(
+            &path,
+            "2024/05/01 16:00;1:00:30;chrome.exe\n\
+             2024/05/01 17:00;90.5;Code\n",
+        )
+        .unwrap();
+        let mapping = CsvMapping {
+            duration: Some("1".to_string()),
+            time_zone: Some("+08:00".to_string()),
+            delimiter: Some(';'),
+            no_header: true,
+            ..map_columns("2", "0")
+        };
+        let parsed = parse(&storage, &path, &mapping).unwrap();
+        assert_eq!(
+            spans(&parsed),
+            [
+                ("C:/Chrome/chrome.exe", start, start + 3_630_000),
+                ("Code", start + 3_600_000, start + 3_690_500),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let data_dir = temp_data_dir("csv-errors");
+        let storage = Storage::open(&data_dir).unwrap();
+        let path = data_dir.join("export.csv");
+        fs::write(
+            &path,
+            "app,start,seconds\n\
+             \"Code\nInsiders\",2024-05-01 08:00:00,60\n\
+             ,2024-05-01 08:00:00,60\n\
+             Code,yesterday,60\n\
+             Code,2024-05-01 08:00:00,-1\n\
+             Code,2024-05-01 08:00:00,0\n\
+             Code,2024-05-01 08:00:00\n",
+        )
+        .unwrap();
+        let mapping = CsvMapping {
+            duration: Some("seconds".to_string()),
+            ..map_columns("app", "start")
+        };
+        let parsed = parse(&storage, &path, &mapping).unwrap();
+        assert_eq!(parsed.spans.len(), 1);
+        assert_eq!(parsed.invalid, 5);
+        // Lines are numbered from the header, counting line breaks in fields.
+        assert_eq!(
+            parsed.errors,
+            [
+                "Line 4: column 0 is empty",
+                "Line 5: invalid time yesterday",
+                "Line 6: invalid duration -1",
+                "Line 7: end isn't after start",
+                "Line 8: column 2 is empty",
+            ]
+        );
+
+        let mapping_error = |mapping: CsvMapping| parse(&storage, &path, &mapping).unwrap_err();
+        assert_eq!(
+            mapping_error(map_columns("app", "start")),
+            "Map either the end or the duration column"
+        );
+        assert_eq!(
+            mapping_error(CsvMapping {
+                duration: Some("minutes".to_string()),
+                ..map_columns("app", "start")
+            }),
+            "Column minutes not found"
+        );
+        assert_eq!(
+            mapping_error(CsvMapping {
+                duration: Some("seconds".to_string()),
+                time_zone: Some("Mars".to_string()),
+                ..map_columns("app", "start")
+            }),
+            "Invalid time zone Mars"
+        );
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90"), Ok(90_000));
+        assert_eq!(parse_duration("0.5"), Ok(500));
+        assert_eq!(parse_duration("1:02:03"), Ok(3_723_000));
+        assert_eq!(parse_duration("2:30"), Ok(150_000));
+        assert!(parse_duration("-1").is_err());
+        assert!(parse_duration("1:xx").is_err());
+        assert!(parse_duration("inf").is_err());
+    }
+}
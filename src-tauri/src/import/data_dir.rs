@@ -0,0 +1,115 @@
+//! Merge the data directory of tmus on another machine, like a laptop besides the desktop.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use tmus_engine::storage::Storage;
+
+use crate::cmd::app_detail::get_app_detail_cache;
+use crate::util::get_file_version;
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeDirResult {
+    /// Apps of the other directory which have records
+    pub apps: usize,
+    /// Apps matched to a local app at another path
+    pub mapped: usize,
+    pub imported: usize,
+    /// Imported records which are partly recorded already
+    pub trimmed: usize,
+    /// Records which are recorded already or too short
+    pub skipped: usize,
+    pub record_count: usize,
+    /// Nothing is written if it's a dry run
+    pub dry_run: bool,
+}
+
+/// Merge the records of the other data directory, tagged with the device name if some.
+/// Apps are matched to local apps by the matcher, see [`AppMatcher`].
+/// All records of the directory are read, so it blocks.
+pub fn merge_data_dir(
+    storage: &Storage,
+    path: &Path,
+    device: Option<&str>,
+    matcher: &AppMatcher,
+    dry_run: bool,
+) -> Result<MergeDirResult, String> {
+    let report = storage
+        .merge_data_dir(path, device, |x| matcher.map(x), dry_run)
+        .map_err(|e| e.to_string())?;
+    Ok(MergeDirResult {
+        apps: report.apps,
+        mapped: report.mapped,
+        imported: report.import.imported,
+        trimmed: report.import.trimmed,
+        skipped: report.import.skipped,
+        record_count: report.import.records,
+        dry_run,
+    })
+}
+
+/// Match the apps of another machine to local ones, as the same app may be installed in
+/// another directory. The path is used if it's registered, otherwise the only local app of
+/// the same file name, which must be of the same product name if both are known.
+pub struct AppMatcher {
+    paths: HashSet<String>,
+    /// Local apps of the lowercase file name, with their product names
+    by_name: HashMap<String, Vec<(String, Option<String>)>>,
+}
+
+impl AppMatcher {
+    pub async fn new(storage: &Storage) -> Self {
+        // Only cached details are read, the storage may not be the global one.
+        let details = get_app_detail_cache().await;
+        let mut by_name: HashMap<String, Vec<(String, Option<String>)>> = HashMap::new();
+        let apps = storage.get_all_app();
+        for (id, path) in apps.iter().enumerate() {
+            let Some(name) = file_name(path) else {
+                continue;
+            };
+            let product = details
+                .get(&id)
+                .and_then(|x| x.version.as_ref())
+                .and_then(|x| x.product_name.clone());
+            by_name
+                .entry(name)
+                .or_default()
+                .push((path.clone(), product));
+        }
+        drop(details);
+        Self {
+            paths: apps.into_iter().filter(|x| !x.is_empty()).collect(),
+            by_name,
+        }
+    }
+
+    fn map(&self, path: &str) -> String {
+        if self.paths.contains(path) {
+            return path.to_string();
+        }
+        let candidates = file_name(path)
+            .and_then(|x| self.by_name.get(&x))
+            .map_or(&[][..], Vec::as_slice);
+        // The app may be installed on this machine too, then its product name is known.
+        let product = get_file_version(path).and_then(|x| x.product_name);
+        let matched: Vec<&String> = candidates
+            .iter()
+            .filter(|(_, x)| match (&product, x) {
+                (Some(product), Some(x)) => product == x,
+                _ => true,
+            })
+            .map(|(path, _)| path)
+            .collect();
+        match matched[..] {
+            [local] => local.clone(),
+            _ => path.to_string(),
+        }
+    }
+}
+
+fn file_name(path: &str) -> Option<String> {
+    Path::new(path)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_lowercase())
+}
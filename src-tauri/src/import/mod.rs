@@ -0,0 +1,114 @@
+//! Import history from other trackers.
+//! Paths of the imported apps go through the rules like tracked ones, so excluded apps are
+//! dropped and merge rules apply before the spans are written.
+
+pub mod activity_watch;
+pub mod csv;
+pub mod data_dir;
+
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tmus_engine::storage::import::ImportSpan;
+use tmus_engine::storage::Storage;
+
+use crate::state::get_rule_radix_tree;
+
+/// Spans read from an export, before the rules apply
+#[derive(Debug, Default)]
+pub struct ParsedSpans {
+    pub spans: Vec<ImportSpan>,
+    /// Count of entries which can't be read as a span
+    pub invalid: usize,
+    /// Why the first entries are invalid, at most [`MAX_ERRORS`]
+    pub errors: Vec<String>,
+    /// Count of entries whose app isn't registered, which are skipped since only its name is
+    /// known instead of the path
+    pub unknown: usize,
+}
+
+/// Errors reported of invalid entries, the rest are only counted
+pub const MAX_ERRORS: usize = 20;
+
+impl ParsedSpans {
+    pub fn add_invalid(&mut self, error: String) {
+        self.invalid += 1;
+        if self.errors.len() < MAX_ERRORS {
+            self.errors.push(error);
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    /// Count of entries read from the export
+    pub total: usize,
+    pub invalid: usize,
+    /// Entries of apps which aren't registered
+    pub unknown: usize,
+    /// Spans dropped by the exclude rules
+    pub excluded: usize,
+    /// Spans whose path is changed by the merge rules
+    pub merged: usize,
+    pub imported: usize,
+    /// Imported spans which are partly recorded already
+    pub trimmed: usize,
+    /// Spans which are recorded already or too short
+    pub skipped: usize,
+    pub record_count: usize,
+    pub errors: Vec<String>,
+    /// Nothing is written if it's a dry run
+    pub dry_run: bool,
+}
+
+/// Apply the rules to the spans and write them, or only report the result if it's a dry run
+pub fn import_spans(
+    storage: &Storage,
+    parsed: ParsedSpans,
+    dry_run: bool,
+) -> Result<ImportResult, String> {
+    let mut result = ImportResult {
+        total: parsed.spans.len() + parsed.invalid + parsed.unknown,
+        invalid: parsed.invalid,
+        unknown: parsed.unknown,
+        errors: parsed.errors,
+        dry_run,
+        ..Default::default()
+    };
+    let rule = get_rule_radix_tree().lock().unwrap();
+    let spans: Vec<ImportSpan> = parsed
+        .spans
+        .into_iter()
+        .filter_map(|span| {
+            let Some(path) = rule.filter(&span.path) else {
+                result.excluded += 1;
+                return None;
+            };
+            if path != span.path {
+                result.merged += 1;
+            }
+            Some(ImportSpan { path, ..span })
+        })
+        .collect();
+    drop(rule);
+    let report = storage
+        .import_spans(&spans, dry_run)
+        .map_err(|e| e.to_string())?;
+    result.imported = report.imported;
+    result.trimmed = report.trimmed;
+    result.skipped = report.skipped;
+    result.record_count = report.records;
+    Ok(result)
+}
+
+/// Find the registered app by its file name, with or without the extension.
+/// Other trackers may only know the executable name, like `chrome.exe`.
+pub fn resolve_app_path(storage: &Storage, name: &str) -> Option<String> {
+    let name = name.to_lowercase();
+    let matches =
+        |x: Option<&std::ffi::OsStr>| x.is_some_and(|x| x.to_string_lossy().to_lowercase() == name);
+    storage.get_all_app().into_iter().find(|path| {
+        let path = Path::new(path);
+        matches(path.file_name()) || matches(path.file_stem())
+    })
+}
@@ -1,21 +1,24 @@
 pub mod app;
 pub mod cli;
 pub mod cmd;
+pub mod import;
 pub mod mcp;
 pub mod state;
+pub mod sync;
 pub mod util;
 
 use crate::app::constant::data_dir;
-use crate::app::global::set_app_handle;
+use crate::app::global::{set_app_handle, set_storage_error};
 use crate::app::start_timer;
 use crate::app::update;
 use crate::cli::handle_cli;
 pub use crate::cli::{Cli, LogLevel};
 use crate::mcp::server::start_mcp_server;
 use crate::state::{get_config, get_rule_radix_tree};
+use crate::sync::server::start_sync_server;
 use tauri::{AppHandle, Manager, RunEvent};
 use tauri_plugin_autostart::MacosLauncher;
-use tmus_engine::{async_runtime, engine_start};
+use tmus_engine::{async_runtime, engine_start, storage};
 use tokio::sync::Mutex;
 use tracing::Level;
 use tracing::{error, info};
@@ -23,10 +26,24 @@ use tracing_subscriber::fmt::format::FmtSpan;
 use tracing_subscriber::FmtSubscriber;
 
 pub fn setup(app: &mut tauri::App, nw: bool) -> Result<(), Box<dyn std::error::Error>> {
+    // Before any state is loaded, since the restored files replace them.
+    match storage::snapshot::apply_staged_restore(data_dir()) {
+        Ok(true) => info!("Restored staged backup"),
+        Ok(false) => {}
+        Err(e) => error!("Failed to restore staged backup: {}", e),
+    }
     let config = { get_config().clone() };
-    engine_start(data_dir(), |app_path| {
+    if let Err(e) = engine_start(data_dir(), |app_path| {
         get_rule_radix_tree().lock().unwrap().filter(app_path)
-    });
+    }) {
+        // Still show the recorded data, the error is shown in the UI.
+        error!(
+            "Failed to start tracking, open the storage read-only: {}",
+            e
+        );
+        storage::init_read_only(data_dir())?;
+        set_storage_error(e.to_string());
+    }
 
     start_timer();
 
@@ -40,6 +57,15 @@ pub fn setup(app: &mut tauri::App, nw: bool) -> Result<(), Box<dyn std::error::E
         });
     }
 
+    if config.sync.host_server && !storage::get_storage().is_read_only() {
+        let sync = config.sync.clone();
+        tauri::async_runtime::spawn(async move {
+            if let Err(e) = start_sync_server(sync.host_addr, sync.host_token).await {
+                error!("Failed to start sync server: {}", e);
+            }
+        });
+    }
+
     app.manage(update::PendingUpdate(Mutex::new(None)));
     let app_handle = app.app_handle().clone();
     app::tray::tray(&app_handle).expect("Error while initializing tray");
@@ -72,7 +98,13 @@ pub fn init_tracing(level: Option<LogLevel>) {
 }
 
 pub fn run_cli_mode(cli: Cli) {
-    engine_start(data_dir(), |_app_path| Some(_app_path.to_string()));
+    if cli.command.as_ref().is_some_and(|x| x.need_storage()) {
+        // Read-only, so the CLI can run alongside the tracking tmus without writing anything.
+        if let Err(e) = storage::init_read_only(data_dir()) {
+            eprintln!("Failed to open storage: {}", e);
+            std::process::exit(1);
+        }
+    }
     handle_cli(cli);
 }
 
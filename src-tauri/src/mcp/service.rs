@@ -1,6 +1,7 @@
 #![allow(dead_code)]
 
 use crate::cmd::app_detail::get_all_app_detail;
+use crate::cmd::app_history;
 use crate::cmd::category;
 use crate::cmd::duration::get_duration_by_id;
 use crate::state::category::CategoryId;
@@ -11,6 +12,8 @@ use rmcp::{
     tool_handler, tool_router, ErrorData as McpError, RoleServer, ServerHandler,
 };
 use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::PathBuf;
 use tmus_engine::models::AppId;
 
 #[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
@@ -48,6 +51,17 @@ pub struct BatchSetAppCategoryCommand {
     category_id: CategoryId,
 }
 
+#[derive(Serialize, Deserialize, schemars::JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ForgetAppCommand {
+    app_id: AppId,
+    #[serde(default)]
+    confirm: bool,
+    /// Backups returned by the preview, which the user has confirmed to remove
+    #[serde(default)]
+    backups: Vec<String>,
+}
+
 #[derive(Serialize, Deserialize, schemars::JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct AddCategoryCommand {
@@ -206,6 +220,53 @@ impl McpService {
         )]))
     }
 
+    #[tool(
+        description = "Erase specified app and its whole usage history, including backups which may keep it. It can't be undone.
+    Call it without `confirm` first, which only returns the app and the backups to be removed. Show them to the user, and call it again with `confirm` and the same `backups` only if the user agrees. If the backups have changed since, nothing is erased and the new preview is returned with `backupsChanged`.
+    # Arguments
+
+    * `app_id` - The ID of the app to forget.
+    * `confirm` - Whether the user has confirmed to erase it.
+    * `backups` - The backups returned by the preview, which the user has confirmed to remove."
+    )]
+    async fn forget_app(
+        &self,
+        Parameters(payload): Parameters<ForgetAppCommand>,
+    ) -> Result<CallToolResult, McpError> {
+        use crate::cmd::app_history::ForgetAppRequest;
+        let storage = tmus_engine::storage::get_storage();
+        let preview = storage
+            .get_app_info(payload.app_id)
+            .map_err(|e| e.to_string())
+            .and_then(|app| Ok((app, crate::app::backup::all_backups(storage)?)));
+        // Backups are removed only if they're still the ones the user has confirmed.
+        let confirmed: HashSet<PathBuf> = payload.backups.iter().map(PathBuf::from).collect();
+        let changed = preview
+            .as_ref()
+            .is_ok_and(|(_, backups)| backups.iter().cloned().collect::<HashSet<_>>() != confirmed);
+        if !payload.confirm || changed {
+            let preview = preview.map(|(app, backups)| {
+                serde_json::json!({
+                    "confirmRequired": true,
+                    "appPath": app.path,
+                    "backups": backups,
+                    "backupsChanged": payload.confirm && changed,
+                })
+            });
+            return Ok(CallToolResult::success(vec![Content::text(
+                serde_json::to_string(&preview).unwrap(),
+            )]));
+        }
+        let request = ForgetAppRequest {
+            app_id: payload.app_id,
+            remove_backups: true,
+        };
+        let result = app_history::forget_app(request).await;
+        Ok(CallToolResult::success(vec![Content::text(
+            serde_json::to_string(&result).unwrap(),
+        )]))
+    }
+
     #[tool(description = "Add new category under specified parent category.
     # Arguments
 
@@ -195,6 +195,28 @@ pub fn remove_app_from_category(app_id: AppId) {
     get_state().category_changed.store(true, Ordering::Relaxed);
 }
 
+/// Move category assignments of merged apps to the target app
+/// If target app does not have a category, it takes the category of the first merged app
+/// Merged apps are removed from their categories
+pub fn merge_app_category(sources: &[AppId], target: AppId) {
+    let mut app_category_map = get_app_category_map();
+    let detail_map = get_category_detail_map();
+    for app_id in sources {
+        let Some(category_id) = app_category_map.remove(app_id) else {
+            continue;
+        };
+        if let Some(category_node) = detail_map.get(&category_id) {
+            let mut node = category_node.lock().unwrap();
+            node.app_ids.retain(|id| id != app_id);
+            if !app_category_map.contains_key(&target) {
+                node.app_ids.push(target);
+                app_category_map.insert(target, category_id);
+            }
+        }
+    }
+    get_state().category_changed.store(true, Ordering::Relaxed);
+}
+
 pub fn get_category_tree() -> Arc<Mutex<CategoryNode>> {
     get_state().category_root.clone()
 }
@@ -345,6 +367,27 @@ pub fn get_category_self_and_descendants_map() -> HashMap<CategoryId, HashSet<Ca
     descendants_map
 }
 
+/// Path of every category from the root, names joined by `/`, root is excluded
+pub fn get_category_path_map() -> HashMap<CategoryId, String> {
+    let root = get_category_tree();
+    let root = root.lock().unwrap();
+    let mut path_map = HashMap::from([(root.id, String::new())]);
+    fn dfs(node: &CategoryNode, path: &str, path_map: &mut HashMap<CategoryId, String>) {
+        for child in &node.children {
+            let child = child.lock().unwrap();
+            let child_path = if path.is_empty() {
+                child.name.clone()
+            } else {
+                format!("{}/{}", path, child.name)
+            };
+            dfs(&child, &child_path, path_map);
+            path_map.insert(child.id, child_path);
+        }
+    }
+    dfs(&root, "", &mut path_map);
+    path_map
+}
+
 /// Match app detail with keyword
 /// If app path contains keyword, return true
 /// If app file description contains keyword, return true
@@ -1,7 +1,11 @@
 use crate::app::constant::config_file_path;
 use crate::util::load_json;
+use chrono::{Months, Utc};
 use serde::{Deserialize, Serialize};
 use std::sync::{Mutex, MutexGuard, OnceLock};
+use tmus_engine::models::DeviceId;
+use tmus_engine::storage::retention::RetentionMode;
+use tmus_engine::util::Timestamp;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(rename_all = "camelCase")]
@@ -15,6 +19,79 @@ pub struct Config {
     pub auto_check_update: bool,
     pub auto_start_mcp_server: bool,
     pub mcp_server_port: u16,
+    #[serde(default)]
+    pub retention: RetentionConfig,
+    #[serde(default)]
+    pub backup: BackupConfig,
+    /// Show records of the device only, of all devices if none.
+    #[serde(default)]
+    pub device: Option<DeviceId>,
+    #[serde(default)]
+    pub sync: SyncConfig,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionConfig {
+    /// Keep raw records of the recent months, 0 means keep forever.
+    pub keep_months: u32,
+    pub mode: RetentionMode,
+    /// Compress record segments of the past months.
+    #[serde(default)]
+    pub compress_segments: bool,
+}
+
+impl RetentionConfig {
+    /// Records before the returned timestamp are aged out, `None` means keep forever.
+    pub fn before(&self) -> Option<Timestamp> {
+        if self.keep_months == 0 {
+            return None;
+        }
+        Utc::now()
+            .checked_sub_months(Months::new(self.keep_months))
+            .map(|x| x.timestamp_millis())
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupConfig {
+    /// Create a backup when the latest one is older than this many days, 0 means never.
+    pub interval_days: u32,
+    /// Keep the latest this many scheduled backups, 0 means keep all.
+    pub keep: u32,
+    /// Directory of backups, `backup` in data directory if none.
+    pub dir: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncConfig {
+    /// Push records to the sync server once every this many minutes, 0 means never.
+    pub interval_minutes: u32,
+    /// Address of the sync server, like `http://192.168.1.2:2372`.
+    pub server: String,
+    pub token: String,
+    /// Name of this device on the sync server, the computer name if empty.
+    pub device: String,
+    /// Host the sync server when tmus starts, for the records pushed by other devices.
+    #[serde(default)]
+    pub host_server: bool,
+    /// Address the hosted server listens on, `127.0.0.1:2372` if empty.
+    #[serde(default)]
+    pub host_addr: String,
+    /// Token which clients of the hosted server must send.
+    #[serde(default)]
+    pub host_token: String,
+}
+
+impl SyncConfig {
+    pub fn device_name(&self) -> String {
+        if !self.device.is_empty() {
+            return self.device.clone();
+        }
+        std::env::var("COMPUTERNAME").unwrap_or_else(|_| "unknown".to_string())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
@@ -49,6 +126,10 @@ impl Default for Config {
             auto_check_update: true,
             auto_start_mcp_server: false,
             mcp_server_port: 2371u16,
+            retention: RetentionConfig::default(),
+            backup: BackupConfig::default(),
+            device: None,
+            sync: SyncConfig::default(),
         }
     }
 }
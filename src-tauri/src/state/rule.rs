@@ -53,6 +53,19 @@ impl Default for Rule {
     }
 }
 
+impl Rule {
+    /// Every rule item as kind, path and the path merged to
+    pub fn items(&self) -> Vec<(&'static str, &str, Option<&str>)> {
+        let exclude = self.exclude.iter().map(|x| ("exclude", &*x.path, None));
+        let include = self.include.iter().map(|x| ("include", &*x.path, None));
+        let merge = self
+            .merge
+            .iter()
+            .map(|x| ("merge", &*x.path, Some(&*x.to_path)));
+        exclude.chain(include).chain(merge).collect()
+    }
+}
+
 #[derive(Debug)]
 pub struct RuleRadixTree {
     exclude: RadixTree<()>,
@@ -0,0 +1,116 @@
+use reqwest::RequestBuilder;
+use serde::de::DeserializeOwned;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tmus_engine::models::{FocusRecord, LOCAL_DEVICE};
+use tmus_engine::storage::{get_storage, Storage};
+use tmus_engine::util::{now_timestamp, Timestamp};
+use tracing::{error, info};
+
+use crate::state::{get_config, SyncConfig};
+use crate::sync::{OffsetQuery, OffsetResponse, PushRecord, PushRequest, PushResponse};
+
+/// Records pushed in one request
+const BATCH_SIZE: usize = 2000;
+
+static UPLOADING: AtomicBool = AtomicBool::new(false);
+
+/// Push records in the background once every configured minutes, called by the timer every minute
+pub fn scheduled_upload(ticks: u64) {
+    let config = get_config().sync.clone();
+    if config.interval_minutes == 0
+        || config.server.is_empty()
+        || !ticks.is_multiple_of(config.interval_minutes as u64)
+    {
+        return;
+    }
+    if UPLOADING.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        match upload(get_storage(), &config).await {
+            Ok(count) => info!("Pushed {} records to {}", count, config.server),
+            Err(e) => error!("Failed to push records to {}: {}", config.server, e),
+        }
+        UPLOADING.store(false, Ordering::SeqCst);
+    });
+}
+
+/// Push the local records after the offset acknowledged by the server, returns the count of
+/// pushed records. Records edited before the offset aren't pushed again.
+///
+/// The records are read on the blocking threads, so the caller's runtime isn't blocked by the storage.
+pub async fn upload(storage: &'static Storage, config: &SyncConfig) -> Result<usize, String> {
+    let server = config.server.trim_end_matches('/');
+    let device = config.device_name();
+    let client = reqwest::Client::new();
+    let OffsetResponse { mut offset } = send(
+        client
+            .get(format!("{}/sync/offset", server))
+            .bearer_auth(&config.token)
+            .query(&OffsetQuery {
+                device: device.clone(),
+            }),
+    )
+    .await?;
+    let mut count = 0;
+    loop {
+        let from = offset.unwrap_or(0);
+        let records = tauri::async_runtime::spawn_blocking(move || read_batch(storage, from))
+            .await
+            .map_err(|e| e.to_string())?;
+        let len = records.len();
+        if len == 0 {
+            break;
+        }
+        let response: PushResponse = send(
+            client
+                .post(format!("{}/sync/records", server))
+                .bearer_auth(&config.token)
+                .json(&PushRequest {
+                    device: device.clone(),
+                    records,
+                }),
+        )
+        .await?;
+        count += len;
+        // Stop if the offset isn't acknowledged, or the same records are pushed again.
+        if response.offset <= offset || len < BATCH_SIZE {
+            break;
+        }
+        offset = response.offset;
+    }
+    Ok(count)
+}
+
+/// Local records which end after the offset, trimmed to start from it
+fn read_batch(storage: &Storage, offset: Timestamp) -> Vec<PushRecord> {
+    let records: Vec<FocusRecord> = storage
+        .iter_by_timestamp(offset, now_timestamp())
+        .filter_map(|x| {
+            x.inspect_err(|e| error!("Failed to read records: {}", e))
+                .ok()
+        })
+        .filter(|x| x.device == LOCAL_DEVICE && x.blur_at > offset)
+        .take(BATCH_SIZE)
+        .collect();
+    // Read after the records, so apps of the records are registered.
+    let apps = storage.get_all_app();
+    records
+        .into_iter()
+        .map(|x| PushRecord {
+            path: apps.get(x.id).cloned().unwrap_or_default(),
+            focus_at: x.focus_at.max(offset),
+            blur_at: x.blur_at,
+        })
+        .collect()
+}
+
+async fn send<T: DeserializeOwned>(request: RequestBuilder) -> Result<T, String> {
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    let status = response.status();
+    if !status.is_success() {
+        let message = response.text().await.unwrap_or_default();
+        return Err(format!("{} {}", status, message));
+    }
+    response.json().await.map_err(|e| e.to_string())
+}
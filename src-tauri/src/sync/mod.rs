@@ -0,0 +1,93 @@
+//! Sync records of many tmus instances into one over HTTP, for the history of all devices of a
+//! person or a team, without any cloud service.
+//!
+//! The running tmus hosts the server if `sync.hostServer` is set in the config, or `tmus serve` runs
+//! it when tmus isn't running. The server writes pushed records tagged with the device name.
+//! Clients push their local records after the offset the server acknowledged last, which is
+//! the end of the latest record of the device on the server.
+
+pub mod client;
+pub mod server;
+
+use serde::{Deserialize, Serialize};
+use tmus_engine::util::Timestamp;
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OffsetQuery {
+    pub device: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OffsetResponse {
+    /// None if the server has no record of the device
+    pub offset: Option<Timestamp>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushRecord {
+    /// Empty if the app is forgotten, the record is skipped then
+    pub path: String,
+    pub focus_at: Timestamp,
+    pub blur_at: Timestamp,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushRequest {
+    pub device: String,
+    pub records: Vec<PushRecord>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PushResponse {
+    pub offset: Option<Timestamp>,
+    /// Records written, the time which is on the server already is skipped
+    pub imported: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::SyncConfig;
+    use tmus_engine::storage::test_util::temp_data_dir;
+    use tmus_engine::storage::Storage;
+    use tmus_engine::util::{d_as_ms, m_as_ms, now_day};
+
+    #[tokio::test]
+    async fn test_upload() {
+        let server_storage: &'static Storage =
+            Box::leak(Box::new(Storage::open(temp_data_dir("server")).unwrap()));
+        let storage: &'static Storage =
+            Box::leak(Box::new(Storage::open(temp_data_dir("client")).unwrap()));
+        let base = d_as_ms(now_day() - 1);
+        storage
+            .write_record("C:\\a.exe", base, base + m_as_ms(10))
+            .unwrap();
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let router = server::router(server_storage, "secret".to_string());
+        tokio::spawn(async move { axum::serve(listener, router).await });
+
+        let mut config = SyncConfig {
+            interval_minutes: 1,
+            server: format!("http://{}/", addr),
+            token: "wrong".to_string(),
+            device: "laptop".to_string(),
+            ..Default::default()
+        };
+        assert!(client::upload(storage, &config).await.is_err());
+        config.token = "secret".to_string();
+        assert_eq!(client::upload(storage, &config).await.unwrap(), 1);
+        // Only records after the acknowledged offset are pushed.
+        assert_eq!(client::upload(storage, &config).await.unwrap(), 0);
+        storage
+            .write_record("C:\\b.exe", base + m_as_ms(10), base + m_as_ms(20))
+            .unwrap();
+        assert_eq!(client::upload(storage, &config).await.unwrap(), 1);
+    }
+}
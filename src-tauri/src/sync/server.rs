@@ -0,0 +1,268 @@
+use axum::extract::{Query, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex, OnceLock};
+use tmus_engine::models::{DeviceId, LOCAL_DEVICE};
+use tmus_engine::storage::import::ImportSpan;
+use tmus_engine::storage::{get_storage, Storage};
+use tmus_engine::util::{now_timestamp, Timestamp};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::sync::{OffsetQuery, OffsetResponse, PushRequest, PushResponse};
+
+type ApiError = (StatusCode, String);
+
+/// Listen on loopback only by default, `0.0.0.0:2372` accepts other devices.
+pub const DEFAULT_ADDR: &str = "127.0.0.1:2372";
+
+struct ServerState {
+    storage: &'static Storage,
+    token: String,
+    /// The end of the latest record of each device, pushes are written one by one by the lock.
+    offsets: Mutex<HashMap<DeviceId, Timestamp>>,
+}
+
+/// Routes of the sync server, pushed records are written to the storage
+pub fn router(storage: &'static Storage, token: String) -> Router {
+    let offsets = device_offsets(storage);
+    let state = Arc::new(ServerState {
+        storage,
+        token,
+        offsets: Mutex::new(offsets),
+    });
+    Router::new()
+        .route("/sync/offset", get(get_offset))
+        .route("/sync/records", post(push_records))
+        .with_state(state)
+}
+
+/// Serve until the shutdown future is done, requests must have the token as the bearer token
+pub async fn serve(
+    storage: &'static Storage,
+    addr: &str,
+    token: String,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<(), String> {
+    axum::serve(
+        bind(addr, &token).await?,
+        build_router(storage, token).await?,
+    )
+    .with_graceful_shutdown(async {
+        shutdown.await;
+        info!("Sync server stopped");
+    })
+    .await
+    .map_err(|e| e.to_string())
+}
+
+async fn bind(addr: &str, token: &str) -> Result<tokio::net::TcpListener, String> {
+    if token.is_empty() {
+        return Err("Token is empty".to_string());
+    }
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| format!("Failed to bind address {}: {}", addr, e))?;
+    info!("Sync server started on {}", addr);
+    Ok(listener)
+}
+
+/// The offsets are read from all records, so the router is built on the blocking threads
+async fn build_router(storage: &'static Storage, token: String) -> Result<Router, String> {
+    tokio::task::spawn_blocking(move || router(storage, token))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[derive(Debug)]
+struct HostedServer {
+    addr: String,
+    ct: CancellationToken,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyncServerStatus {
+    running: bool,
+    addr: Option<String>,
+}
+
+static HOSTED_SERVER: OnceLock<tokio::sync::Mutex<Option<HostedServer>>> = OnceLock::new();
+
+/// Host the sync server in the running tmus, which writes pushed records to its own storage.
+/// Listen on [`DEFAULT_ADDR`] if the address is empty.
+#[tauri::command]
+#[tracing::instrument(skip(token))]
+pub async fn start_sync_server(addr: String, token: String) -> Result<(), String> {
+    let Ok(mut hosted) = HOSTED_SERVER
+        .get_or_init(|| tokio::sync::Mutex::new(None))
+        .try_lock()
+    else {
+        return Err("Another command is running".to_string());
+    };
+    if hosted.is_some() {
+        return Err("Server is running".to_string());
+    }
+    let addr = if addr.is_empty() {
+        DEFAULT_ADDR.to_string()
+    } else {
+        addr
+    };
+    let listener = bind(&addr, &token).await?;
+    let router = build_router(get_storage(), token).await?;
+
+    let ct = CancellationToken::new();
+    let shutdown_ct = ct.clone();
+    let server = axum::serve(listener, router).with_graceful_shutdown(async move {
+        shutdown_ct.cancelled().await;
+        info!("Sync server stopped");
+    });
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!(error = %e, "Sync server shutdown with error");
+        }
+    });
+    *hosted = Some(HostedServer { addr, ct });
+    Ok(())
+}
+
+#[tauri::command]
+#[tracing::instrument]
+pub async fn stop_sync_server() {
+    let mut hosted = HOSTED_SERVER
+        .get_or_init(|| tokio::sync::Mutex::new(None))
+        .lock()
+        .await;
+    let Some(server) = hosted.take() else {
+        info!("Sync server is not running");
+        return;
+    };
+    server.ct.cancel();
+}
+
+#[tauri::command]
+#[tracing::instrument]
+pub async fn get_sync_server_status() -> SyncServerStatus {
+    let hosted = HOSTED_SERVER
+        .get_or_init(|| tokio::sync::Mutex::new(None))
+        .lock()
+        .await;
+    SyncServerStatus {
+        running: hosted.is_some(),
+        addr: hosted.as_ref().map(|x| x.addr.clone()),
+    }
+}
+
+/// Offsets are read from the records, so they're right after the data is restored
+fn device_offsets(storage: &Storage) -> HashMap<DeviceId, Timestamp> {
+    let mut offsets = HashMap::new();
+    for record in storage
+        .iter_by_timestamp(0, now_timestamp())
+        .filter_map(Result::ok)
+        .filter(|x| x.device != LOCAL_DEVICE)
+    {
+        let offset = offsets.entry(record.device).or_insert(record.blur_at);
+        *offset = (*offset).max(record.blur_at);
+    }
+    offsets
+}
+
+async fn get_offset(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Query(query): Query<OffsetQuery>,
+) -> Result<Json<OffsetResponse>, ApiError> {
+    authorize(&state, &headers)?;
+    let devices = state.storage.devices().map_err(internal)?;
+    let offset = devices
+        .iter()
+        .position(|x| *x == query.device)
+        .and_then(|id| {
+            let offsets = state.offsets.lock().unwrap();
+            offsets.get(&(id as DeviceId)).copied()
+        });
+    Ok(Json(OffsetResponse { offset }))
+}
+
+async fn push_records(
+    State(state): State<Arc<ServerState>>,
+    headers: HeaderMap,
+    Json(request): Json<PushRequest>,
+) -> Result<Json<PushResponse>, ApiError> {
+    authorize(&state, &headers)?;
+    if request.device.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "Device name is empty".to_string()));
+    }
+    tokio::task::spawn_blocking(move || write_records(&state, request))
+        .await
+        .map_err(internal)?
+        .map(Json)
+}
+
+fn write_records(state: &ServerState, request: PushRequest) -> Result<PushResponse, ApiError> {
+    let mut offsets = state.offsets.lock().unwrap();
+    let device = state
+        .storage
+        .register_device(&request.device, false)
+        .map_err(internal)?;
+    if device == LOCAL_DEVICE {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            format!("Device name {} is taken by the server", request.device),
+        ));
+    }
+    let spans: Vec<ImportSpan> = request
+        .records
+        .into_iter()
+        .filter(|x| x.blur_at > x.focus_at)
+        .map(|x| ImportSpan {
+            path: x.path,
+            focus_at: x.focus_at,
+            blur_at: x.blur_at,
+            device,
+        })
+        .collect();
+    let report = state
+        .storage
+        .import_spans(&spans, false)
+        .map_err(internal)?;
+    if let Some(end) = spans.iter().map(|x| x.blur_at).max() {
+        let offset = offsets.entry(device).or_insert(end);
+        *offset = (*offset).max(end);
+    }
+    info!(
+        "Pushed {} records of {}, imported {}",
+        spans.len(),
+        request.device,
+        report.imported
+    );
+    Ok(PushResponse {
+        offset: offsets.get(&device).copied(),
+        imported: report.imported,
+    })
+}
+
+fn authorize(state: &ServerState, headers: &HeaderMap) -> Result<(), ApiError> {
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|x| x.to_str().ok())
+        .and_then(|x| x.strip_prefix("Bearer "));
+    if token.is_some_and(|x| constant_time_eq(x.as_bytes(), state.token.as_bytes())) {
+        Ok(())
+    } else {
+        Err((StatusCode::UNAUTHORIZED, "Invalid token".to_string()))
+    }
+}
+
+/// Compare every byte, so the token can't be guessed by the response time
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn internal(e: impl ToString) -> ApiError {
+    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string())
+}
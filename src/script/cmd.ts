@@ -12,6 +12,7 @@ import {
   FileDetail,
   FileIndexRecord,
   IdDuration, McpServerStatus,
+  SyncServerStatus,
   RuleConfig,
   TagConfig,
   UpdateMetadata,
@@ -34,7 +35,18 @@ import {
   StatisticScheme,
   AddSchemeItemRequest,
   AddSchemeItemResponse,
-  CategorySimple
+  CategorySimple,
+  MergeAppsRequest,
+  MergeAppsResponse,
+  ForgetAppRequest,
+  ForgetAppResponse,
+  ExportRecordsRequest,
+  ImportActivityWatchRequest,
+  ImportCsvRequest,
+  ImportResult,
+  MergeDataDirRequest,
+  MergeDirResult,
+  AppId
 } from "./models.ts"
 import { Config } from "@/script/state.ts"
 import { ElMessage } from "element-plus"
@@ -152,6 +164,21 @@ export async function getMcpServerStatus(): Promise<McpServerStatus> {
   return await invoke("get_mcp_server_status")
 }
 
+export async function startSyncServer(addr: string, token: string) {
+  return await ivk("start_sync_server", {
+    addr,
+    token,
+  })
+}
+
+export async function stopSyncServer() {
+  return await ivk("stop_sync_server")
+}
+
+export async function getSyncServerStatus(): Promise<SyncServerStatus> {
+  return await invoke("get_sync_server_status")
+}
+
 export async function getCategoryTree(): Promise<Category> {
   return await ivk("get_category_tree")
 }
@@ -189,6 +216,76 @@ export async function getCategoryApps(categoryId: number): Promise<FileDetail[]>
   return await ivk<FileDetail[]>("get_category_apps", { categoryId: categoryId })
 }
 
+export async function mergeApps(request: MergeAppsRequest): Promise<MergeAppsResponse> {
+  return await ivk("merge_apps", { request })
+}
+
+export async function forgetApp(request: ForgetAppRequest): Promise<ForgetAppResponse> {
+  return await ivk("forget_app", { request })
+}
+
+export async function insertRecord(
+  appPath: string,
+  focusAt: number,
+  blurAt: number
+): Promise<AppId> {
+  return await ivk("insert_record", { appPath, focusAt, blurAt })
+}
+
+export async function deleteRecordRange(
+  startTimestamp: number,
+  endTimestamp: number,
+  appId: AppId | null
+): Promise<number> {
+  return await ivk("delete_record_range", { startTimestamp, endTimestamp, appId })
+}
+
+export async function reattributeRecordRange(
+  startTimestamp: number,
+  endTimestamp: number,
+  fromAppId: AppId | null,
+  toAppId: AppId
+): Promise<number> {
+  return await ivk("reattribute_record_range", {
+    startTimestamp,
+    endTimestamp,
+    fromAppId,
+    toAppId,
+  })
+}
+
+export async function splitRecord(timestamp: number): Promise<boolean> {
+  return await ivk("split_record", { timestamp })
+}
+
+export async function exportRecords(request: ExportRecordsRequest): Promise<number> {
+  return await ivk("export_records", { request })
+}
+
+export async function importActivityWatch(request: ImportActivityWatchRequest): Promise<ImportResult> {
+  return await ivk("import_activity_watch", { request })
+}
+
+export async function importCsv(request: ImportCsvRequest): Promise<ImportResult> {
+  return await ivk("import_csv", { request })
+}
+
+export async function mergeDataDir(request: MergeDataDirRequest): Promise<MergeDirResult> {
+  return await ivk("merge_data_dir", { request })
+}
+
+export async function getDevices(): Promise<string[]> {
+  return await ivk("get_devices")
+}
+
+export async function createBackup(path: string | null): Promise<string> {
+  return await ivk("create_backup", { path })
+}
+
+export async function restoreBackup(path: string): Promise<void> {
+  await ivk("restore_backup", { path })
+}
+
 async function ivk<T>(
   cmd: string,
   args?: InvokeArgs,
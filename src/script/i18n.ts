@@ -12,6 +12,7 @@ type I18nMessageType = {
     pastWeek: string
     today: string
     copiedToClipboard: string
+    storageReadOnly: string
   }
   navigateMenu: {
     overview: string
@@ -238,6 +239,7 @@ const messages: Record<LanguageEnum, I18nMessageType> = {
       pastWeek: "Past Week",
       today: "Today",
       copiedToClipboard: "Copied to clipboard",
+      storageReadOnly: "Failed to open the data, tracking is stopped and the data is read-only",
     },
     navigateMenu: {
       overview: "Overview",
@@ -474,6 +476,7 @@ const messages: Record<LanguageEnum, I18nMessageType> = {
       pastWeek: "近一周",
       today: "今天",
       copiedToClipboard: "已复制到剪贴板",
+      storageReadOnly: "打开数据失败，记录已停止，数据只读",
     },
     navigateMenu: {
       overview: "概览",
@@ -32,6 +32,10 @@ export interface FocusRecord {
   id: AppId
   focusAt: number
   blurAt: number
+  /**
+   * Index of the device name, 0 is the local device
+   */
+  device: number
 }
 
 export interface IdDuration {
@@ -81,7 +85,13 @@ export interface TagConfig {
 
 export interface AppMeta {
   initialTimestamp: number
+  engineVersion: string
+  formatVersion: number
   tmusVersion: string
+  /**
+   * Set if the storage failed to open for tracking, records are read-only then
+   */
+  storageError?: string
 }
 
 export interface FileIndexRecord {
@@ -118,6 +128,11 @@ export interface McpServerStatus {
   port?: number
 }
 
+export interface SyncServerStatus {
+  running: boolean
+  addr?: string
+}
+
 export interface Category {
   id: CategoryId
   name: string
@@ -148,6 +163,91 @@ export interface RemoveAppCategoryRequest {
   appId: AppId
 }
 
+export interface MergeAppsRequest {
+  sourceIds: AppId[]
+  targetId: AppId
+}
+
+export interface MergeAppsResponse {
+  targetId: AppId
+  recordCount: number
+}
+
+export interface ForgetAppRequest {
+  appId: AppId
+  removeBackups?: boolean
+}
+
+export interface ForgetAppResponse {
+  appIds: AppId[]
+  recordCount: number
+  removedBackups: string[]
+}
+
+export interface ImportActivityWatchRequest {
+  path: string
+  dryRun: boolean
+}
+
+export interface CsvMapping {
+  app: string
+  start: string
+  end: string | null
+  duration: string | null
+  timeZone: string | null
+  timeFormat: string | null
+  delimiter: string | null
+  noHeader: boolean
+}
+
+export interface ImportCsvRequest {
+  path: string
+  mapping: CsvMapping
+  dryRun: boolean
+}
+
+export interface ImportResult {
+  total: number
+  invalid: number
+  unknown: number
+  excluded: number
+  merged: number
+  imported: number
+  trimmed: number
+  skipped: number
+  recordCount: number
+  errors: string[]
+  dryRun: boolean
+}
+
+export interface MergeDataDirRequest {
+  path: string
+  /**
+   * Tag the merged records with the device, or count them as local if null
+   */
+  device: string | null
+  dryRun: boolean
+}
+
+export interface MergeDirResult {
+  apps: number
+  mapped: number
+  imported: number
+  trimmed: number
+  skipped: number
+  recordCount: number
+  dryRun: boolean
+}
+
+export type ExportFormat = "csv" | "jsonl" | "sqlite"
+
+export interface ExportRecordsRequest {
+  path: string
+  format: ExportFormat
+  startTimestamp: number
+  endTimestamp: number
+}
+
 export type TimeSpan = "day" | "week"
 
 export interface AppStatisticDetail {
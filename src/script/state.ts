@@ -10,6 +10,7 @@ export type DateFormatEnum = "yyyy-MM-dd" | "yyyy/MM/dd"
 export type TimeFormatEnum = "H:mm:ss" | "HH:mm:ss"
 export type LanguageConfig = LanguageEnum | "system"
 export type ThemeConfig = ThemeEnum | "system"
+export type RetentionMode = "dailyTotal" | "archive"
 export type Config = {
   lang: LanguageConfig
   theme: ThemeConfig
@@ -20,6 +21,35 @@ export type Config = {
   autoCheckUpdate: boolean
   autoStartMcpServer: boolean
   mcpServerPort: number
+  retention: {
+    keepMonths: number
+    mode: RetentionMode
+    compressSegments: boolean
+  }
+  backup: {
+    intervalDays: number
+    keep: number
+    dir: string | null
+  }
+  /**
+   * Show records of the device only, of all devices if null
+   */
+  device: number | null
+  sync: {
+    intervalMinutes: number
+    server: string
+    token: string
+    device: string
+    /**
+     * Host the sync server when tmus starts
+     */
+    hostServer: boolean
+    /**
+     * 127.0.0.1:2372 if empty
+     */
+    hostAddr: string
+    hostToken: string
+  }
 }
 export type StatisticType = "AppDuration" | "AppDays" | "CategoryDuration" | "CategoryDays" | "CategoryRhythm"
 export const updateDialogStore = reactive<{
@@ -58,6 +88,26 @@ export const configStore = reactive<Config>({
   autoCheckUpdate: true,
   autoStartMcpServer: false,
   mcpServerPort: 2371,
+  retention: {
+    keepMonths: 0,
+    mode: "dailyTotal",
+    compressSegments: false,
+  },
+  backup: {
+    intervalDays: 0,
+    keep: 0,
+    dir: null,
+  },
+  device: null,
+  sync: {
+    intervalMinutes: 0,
+    server: "",
+    token: "",
+    device: "",
+    hostServer: false,
+    hostAddr: "",
+    hostToken: "",
+  },
 })
 
 export const passiveStore = reactive<{
@@ -1,17 +1,29 @@
+#[cfg(feature = "tracker")]
 pub mod async_runtime;
 pub mod models;
+#[cfg(feature = "storage")]
 pub mod storage;
-mod tracker;
+#[cfg(feature = "tracker")]
+pub mod tracker;
 pub mod util;
 
-use crate::storage::write_record;
-use crate::tracker::TrackingSpanEvent;
+#[cfg(all(feature = "tracker-windows", windows))]
+use crate::storage::get_storage;
+#[cfg(all(feature = "tracker-windows", windows))]
 use crate::util::Timestamp;
+#[cfg(all(feature = "tracker-windows", windows))]
 use std::path::Path;
-use tracing::debug;
+#[cfg(all(feature = "tracker-windows", windows))]
+use tracing::{debug, error};
 
-pub fn engine_start(data_dir: impl AsRef<Path>, filter: fn(&str) -> Option<String>) {
-    storage::init(data_dir);
+/// Start tracking foreground windows and write the spans to storage.
+/// Only supported on Windows, other platforms can still read and write storage directly.
+#[cfg(all(feature = "tracker-windows", windows))]
+pub fn engine_start(
+    data_dir: impl AsRef<Path>,
+    filter: fn(&str) -> Option<String>,
+) -> storage::Result<()> {
+    storage::init(data_dir)?;
     tracker::start_tracking(move |tracking_span_event| {
         if let Some(new_name) = filter(&tracking_span_event.name) {
             debug!(
@@ -21,17 +33,21 @@ pub fn engine_start(data_dir: impl AsRef<Path>, filter: fn(&str) -> Option<Strin
                 tracking_span_event.blur_at,
                 format_duration(tracking_span_event.blur_at - tracking_span_event.focus_at)
             );
-            write_record(TrackingSpanEvent {
-                name: new_name,
-                focus_at: tracking_span_event.focus_at,
-                blur_at: tracking_span_event.blur_at,
-            });
+            if let Err(e) = get_storage().write_record(
+                &new_name,
+                tracking_span_event.focus_at,
+                tracking_span_event.blur_at,
+            ) {
+                error!("Failed to write record: {}", e);
+            }
         } else {
             debug!("App is filtered out. {:?}", tracking_span_event);
         }
     });
+    Ok(())
 }
 
+#[cfg(all(feature = "tracker-windows", windows))]
 fn format_duration(duration: Timestamp) -> String {
     let seconds = duration / 1000;
     let millis = duration % 1000;
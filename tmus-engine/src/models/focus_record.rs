@@ -1,21 +1,41 @@
-use crate::models::AppId;
-use crate::util::{Timestamp, ms_as_d, ms_as_s, s_as_ms, start_of_next_d};
+#[cfg(feature = "storage")]
+use crate::models::LOCAL_DEVICE;
+use crate::models::{AppId, DeviceId};
+use crate::util::Timestamp;
+#[cfg(feature = "storage")]
+use crate::util::{ms_as_d, s_as_ms, start_of_next_d};
 use serde::Serialize;
 use std::fmt::Debug;
 
-const DURATION_MAX: Timestamp = s_as_ms(u16::MAX as i64);
+/// Record layout since format version 2.
+#[cfg(feature = "storage")]
+pub(crate) type RecordByte = [u8; 16];
 
-pub(crate) type RecordByte = [u8; 8];
+/// Record layout of format version 1 and before.
+#[cfg(feature = "storage")]
+pub(crate) type RecordV1Byte = [u8; 8];
 
-/// - app_id: 2^16, 8192 applications support.
-/// - focus_at: 2^32, The time when the focus starts. About 136 year from unix epoch.
-/// - duration: 2^16, Maximum time that can be represented is about 0.76 day,
+/// Bits of the app id in the first u32 of a record, the higher bits are the device.
+#[cfg(feature = "storage")]
+const APP_ID_BITS: u32 = 24;
+
+/// Count of app ids which fit in a record.
+#[cfg(feature = "storage")]
+pub(crate) const MAX_APPS: AppId = 1 << APP_ID_BITS;
+
+/// Stored in 16 bytes since format version 2:
+/// - app_id: u32, the higher 8 bits are the device since format version 5.
+/// - duration: u32 milliseconds, about 49 days, more than enough since records are split by day.
+/// - focus_at: i64 milliseconds from unix epoch.
+///
+/// Format version 1 stored 8 bytes: u16 app_id, u16 seconds duration and u32 seconds focus_at.
 #[derive(Clone, Copy, Serialize, PartialEq, Eq)]
 #[serde(rename_all = "camelCase")]
 pub struct FocusRecord {
     pub id: AppId,
     pub focus_at: Timestamp,
     pub blur_at: Timestamp,
+    pub device: DeviceId,
 }
 
 impl Debug for FocusRecord {
@@ -25,6 +45,7 @@ impl Debug for FocusRecord {
             .field("focus_at", &self.focus_at)
             .field("blur_at", &self.blur_at)
             .field("duration", &self.duration())
+            .field("device", &self.device)
             .finish()
     }
 }
@@ -33,39 +54,41 @@ impl FocusRecord {
     pub fn duration(&self) -> Timestamp {
         self.blur_at - self.focus_at
     }
+}
 
+#[cfg(feature = "storage")]
+impl FocusRecord {
     /// Convert to bytes. Use [`Self::split_record`] method ensure that the duration value is safe.
-    pub(crate) fn unsafe_to_byte(&self) -> RecordByte {
+    pub(crate) fn to_byte(self) -> RecordByte {
         let mut ret = RecordByte::default();
-        let id = self.id as u16;
-        let focus_at = ms_as_s(self.focus_at) as u32;
-        let duration = ms_as_s(self.duration()) as u16;
-        ret[..2].copy_from_slice(&id.to_le_bytes());
-        ret[2..4].copy_from_slice(&duration.to_le_bytes());
-        ret[4..].copy_from_slice(&focus_at.to_le_bytes());
+        debug_assert!(self.id < MAX_APPS);
+        let id = self.id as u32 | (self.device as u32) << APP_ID_BITS;
+        ret[..4].copy_from_slice(&id.to_le_bytes());
+        ret[4..8].copy_from_slice(&(self.duration() as u32).to_le_bytes());
+        ret[8..].copy_from_slice(&self.focus_at.to_le_bytes());
         ret
     }
 
     /// Split record into multiple records.
-    /// Ensure that the duration of each record is less than 0.76 day(The maximum time that can be represented)
-    /// and not span across one day which could make index easier.
+    /// Ensure that each record doesn't span across one day which could make index easier.
     pub(crate) fn split_record(&self) -> Vec<FocusRecord> {
         assert!(self.blur_at >= self.focus_at);
         self.split_by_not_across_day()
-            .into_iter()
-            .flat_map(|x| x.split_by_max_duration())
-            .collect()
     }
 
-    fn new(id: usize, focus_at: Timestamp, blur_at: Timestamp) -> FocusRecord {
-        FocusRecord {
-            id,
+    fn from_byte(bytes: RecordByte) -> Self {
+        let id = u32::from_le_bytes(bytes[..4].try_into().unwrap());
+        let duration = u32::from_le_bytes(bytes[4..8].try_into().unwrap()) as Timestamp;
+        let focus_at = Timestamp::from_le_bytes(bytes[8..].try_into().unwrap());
+        Self {
+            id: (id & ((1 << APP_ID_BITS) - 1)) as usize,
             focus_at,
-            blur_at,
+            blur_at: focus_at + duration,
+            device: (id >> APP_ID_BITS) as DeviceId,
         }
     }
 
-    fn from_byte(bytes: RecordByte) -> Self {
+    fn from_v1_byte(bytes: RecordV1Byte) -> Self {
         let id = u16::from_le_bytes(bytes[..2].try_into().unwrap()) as usize;
         let focus_at = s_as_ms(u32::from_le_bytes(bytes[4..].try_into().unwrap()) as i64);
         let blur_at =
@@ -74,39 +97,29 @@ impl FocusRecord {
             id,
             focus_at,
             blur_at,
+            device: LOCAL_DEVICE,
         }
     }
 
-    fn split_by_max_duration(&self) -> Vec<FocusRecord> {
-        let mut focus_at = self.focus_at;
-        let mut duration = self.duration();
-        let mut ret = Vec::new();
-        loop {
-            if duration > DURATION_MAX {
-                let blur_at = focus_at + DURATION_MAX;
-                ret.push(FocusRecord::new(self.id, focus_at, blur_at));
-                focus_at = blur_at;
-                duration -= DURATION_MAX;
-            } else {
-                let blur_at = focus_at + duration;
-                ret.push(FocusRecord::new(self.id, focus_at, blur_at));
-                break;
-            }
-        }
-        ret
-    }
-
     fn split_by_not_across_day(&self) -> Vec<FocusRecord> {
         let mut focus_at = self.focus_at;
         let blur_at = self.blur_at;
         let mut ret = Vec::new();
         loop {
             if ms_as_d(focus_at) == ms_as_d(blur_at) {
-                ret.push(FocusRecord::new(self.id, focus_at, blur_at));
+                ret.push(FocusRecord {
+                    focus_at,
+                    blur_at,
+                    ..*self
+                });
                 break;
             } else {
                 let blur_at = start_of_next_d(focus_at);
-                ret.push(FocusRecord::new(self.id, focus_at, blur_at));
+                ret.push(FocusRecord {
+                    focus_at,
+                    blur_at,
+                    ..*self
+                });
                 focus_at = blur_at;
             }
         }
@@ -114,8 +127,16 @@ impl FocusRecord {
     }
 }
 
+#[cfg(feature = "storage")]
 impl From<RecordByte> for FocusRecord {
     fn from(value: RecordByte) -> Self {
         FocusRecord::from_byte(value)
     }
 }
+
+#[cfg(feature = "storage")]
+impl From<RecordV1Byte> for FocusRecord {
+    fn from(value: RecordV1Byte) -> Self {
+        FocusRecord::from_v1_byte(value)
+    }
+}
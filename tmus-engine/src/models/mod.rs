@@ -5,17 +5,42 @@ use serde::{Deserialize, Serialize};
 
 pub type AppId = usize;
 
+/// The device a record is tracked on, records of other devices come from merged data
+/// directories. Device names are in device.json of the data directory.
+pub type DeviceId = u8;
+
+/// The device of the data directory itself.
+pub const LOCAL_DEVICE: DeviceId = 0;
+
 pub use focus_record::FocusRecord;
+#[cfg(feature = "storage")]
+pub(crate) use focus_record::MAX_APPS;
+
+/// An app of the registry, with the time range it's seen in records.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct AppInfo {
+    pub id: AppId,
+    pub path: String,
+    pub first_seen: Timestamp,
+    pub last_seen: Timestamp,
+    /// The app whose history this app is merged into, new records of the path go to it.
+    pub merged_into: Option<AppId>,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct EngineMeta {
     pub initial_timestamp: Timestamp,
     pub engine_version: String,
+    /// The on-disk format version of the storage files.
+    pub format_version: u32,
 }
 
-pub(crate) use focus_record::RecordByte;
+#[cfg(feature = "storage")]
+pub(crate) use focus_record::{RecordByte, RecordV1Byte};
 
+#[cfg(feature = "storage")]
 #[derive(Debug, PartialEq)]
 pub(crate) enum CursorPosition {
     Start,
@@ -0,0 +1,106 @@
+//! Merge touching records of the same app, which are left by the tracker poll and the day split.
+
+use crate::models::FocusRecord;
+use crate::storage::error::Result;
+use crate::storage::migration::{backup, migrate};
+use crate::storage::rewrite::{ensure_not_in_use, read_records, rewrite_records};
+use crate::util::{Timestamp, start_of_next_d};
+use std::path::Path;
+use tracing::info;
+
+#[derive(Debug)]
+pub struct CompactReport {
+    pub before: usize,
+    pub after: usize,
+}
+
+/// Merge records of the same app whose gap is at most `max_gap` milliseconds,
+/// and rewrite the segments and index.bin. The gap is counted as focus time of the merged record.
+/// Records are never merged across one day, so the day index keeps working.
+///
+/// Fails with [`crate::storage::StorageError::Locked`] if tmus is running with the data directory.
+pub fn compact(data_dir: impl AsRef<Path>, max_gap: Timestamp) -> Result<CompactReport> {
+    let data_dir = data_dir.as_ref();
+    ensure_not_in_use(data_dir)?;
+    migrate(data_dir)?;
+    let (_, records) = read_records(data_dir)?;
+    let before = records.len();
+    let records = merge_records(records, max_gap);
+    let report = CompactReport {
+        before,
+        after: records.len(),
+    };
+    if report.after < report.before {
+        let backup_dir = backup(data_dir, "compact")?;
+        info!(
+            "Compact {:?} from {} to {} records, backup in {:?}",
+            data_dir, report.before, report.after, backup_dir
+        );
+        rewrite_records(data_dir, &records)?;
+    }
+    Ok(report)
+}
+
+/// Records must be in time order, only adjacent records can be merged.
+pub(crate) fn merge_records(records: Vec<FocusRecord>, max_gap: Timestamp) -> Vec<FocusRecord> {
+    let mut ret: Vec<FocusRecord> = Vec::with_capacity(records.len());
+    for record in records {
+        if let Some(last) = ret.last_mut()
+            && last.id == record.id
+            && last.device == record.device
+            && record.focus_at - last.blur_at <= max_gap
+            && record.blur_at <= start_of_next_d(last.focus_at)
+        {
+            last.blur_at = last.blur_at.max(record.blur_at);
+            continue;
+        }
+        ret.push(record);
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, verify};
+    use crate::util::{d_as_ms, now_timestamp, s_as_ms, start_of_d};
+
+    #[test]
+    fn test_compact() {
+        let now = start_of_d(now_timestamp());
+        let dir = temp_data_dir("compact");
+        let storage = Storage::open(&dir).unwrap();
+        let spans = [
+            ("C:\\a.exe", 0, 30),
+            ("C:\\a.exe", 30, 60),
+            ("C:\\b.exe", 60, 90),
+            ("C:\\a.exe", 90, 120),
+            ("C:\\a.exe", 125, 150),
+        ];
+        for (app, focus_at, blur_at) in spans {
+            storage
+                .write_record(app, now + s_as_ms(focus_at), now + s_as_ms(blur_at))
+                .unwrap();
+        }
+        drop(storage);
+
+        let report = compact(&dir, s_as_ms(5)).unwrap();
+        assert_eq!((report.before, report.after), (5, 3));
+        assert!(verify::verify(&dir).unwrap().is_ok());
+        let storage = Storage::open(&dir).unwrap();
+        let records = storage.read_by_timestamp(0, now + d_as_ms(1));
+        let spans: Vec<_> = records
+            .iter()
+            .map(|x| (x.id, x.focus_at - now, x.blur_at - now))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 0, s_as_ms(60)),
+                (1, s_as_ms(60), s_as_ms(90)),
+                (0, s_as_ms(90), s_as_ms(150))
+            ]
+        );
+    }
+}
@@ -0,0 +1,80 @@
+//! Names of the devices whose records are in the data directory.
+//!
+//! device.json is a JSON array of names indexed by [`DeviceId`], the local device is the first.
+//! It's missing until records of another device are merged, see [`crate::storage::merge_dir`].
+
+use crate::models::DeviceId;
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{read_or_default, replace_file};
+use std::io;
+use std::path::Path;
+
+pub(crate) const DEVICE_FILE: &str = "device.json";
+/// Name of the local device while device.json is missing.
+const LOCAL_DEVICE_NAME: &str = "local";
+
+impl Storage {
+    /// Device names indexed by device id.
+    pub fn devices(&self) -> Result<Vec<String>> {
+        read_devices(&self.data_dir)
+    }
+
+    /// The id of the device, which is registered if it's new.
+    /// A dry run returns the id it would be registered as.
+    pub fn register_device(&self, name: &str, dry_run: bool) -> Result<DeviceId> {
+        Ok(self.register_devices(&[name], dry_run)?[0])
+    }
+
+    /// The ids of the devices, new ones are registered in order.
+    /// A dry run returns the ids they would be registered as, so new names get distinct ids.
+    pub fn register_devices(&self, names: &[&str], dry_run: bool) -> Result<Vec<DeviceId>> {
+        let mut devices = self.devices()?;
+        let registered = devices.len();
+        let mut ids = Vec::with_capacity(names.len());
+        for &name in names {
+            let id = match devices.iter().position(|x| x == name) {
+                Some(id) => id,
+                None => {
+                    devices.push(name.to_string());
+                    devices.len() - 1
+                }
+            };
+            let id = DeviceId::try_from(id)
+                .map_err(|_| StorageError::TooManyDevices(DeviceId::MAX as usize + 1))?;
+            ids.push(id);
+        }
+        if dry_run || devices.len() == registered {
+            return Ok(ids);
+        }
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        replace_file(
+            self.data_dir.join(DEVICE_FILE),
+            serde_json::to_string_pretty(&devices).unwrap().as_bytes(),
+        )?;
+        Ok(ids)
+    }
+}
+
+pub(crate) fn read_devices(data_dir: &Path) -> Result<Vec<String>> {
+    let path = data_dir.join(DEVICE_FILE);
+    let buf = read_or_default(&path)?;
+    if buf.is_empty() {
+        return Ok(vec![LOCAL_DEVICE_NAME.to_string()]);
+    }
+    parse_devices(&buf).ok_or_else(|| {
+        StorageError::io(&path)(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a list of device names",
+        ))
+    })
+}
+
+/// `None` if it isn't a list of at most [`DeviceId::MAX`] + 1 names.
+pub(crate) fn parse_devices(buf: &[u8]) -> Option<Vec<String>> {
+    serde_json::from_slice::<Vec<String>>(buf)
+        .ok()
+        .filter(|x| !x.is_empty() && x.len() <= DeviceId::MAX as usize + 1)
+}
@@ -0,0 +1,294 @@
+//! Edit records in arbitrary historical ranges, for manual entries and corrections.
+//!
+//! Every edit rewrites the records through [`Storage::rewrite_records`], which keeps index.bin
+//! consistent. Records never overlap after an edit, and spans across days are split.
+//! Only records of the local device are edited, merged records of other devices are kept.
+
+use crate::models::{AppId, FocusRecord, LOCAL_DEVICE};
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::util::{Timestamp, now_timestamp};
+use tracing::info;
+
+impl Storage {
+    /// Add a record of the app, which is registered if it's new, like an offline activity.
+    /// Local records inside the range are replaced, and records across its bounds are trimmed.
+    pub fn insert_record(
+        &self,
+        app_path: &str,
+        focus_at: Timestamp,
+        blur_at: Timestamp,
+    ) -> Result<AppId> {
+        check_range(focus_at, blur_at)?;
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let mut record = Err(StorageError::Closed);
+        self.rewrite_records(|records| {
+            // The app is registered only when the records are read and about to be rewritten.
+            let id = match self.app.touch(app_path, focus_at, blur_at) {
+                Ok(id) => id,
+                Err(e) => {
+                    record = Err(e);
+                    return None;
+                }
+            };
+            let inserted = FocusRecord {
+                id,
+                focus_at,
+                blur_at,
+                device: LOCAL_DEVICE,
+            };
+            record = Ok(inserted);
+            let mut records = edit_range(records, focus_at, blur_at, |_| None);
+            let position = records.partition_point(|x| x.focus_at < focus_at);
+            records.splice(position..position, inserted.split_record());
+            Some(records)
+        })?;
+        let record = record?;
+        info!("Insert record {:?}", record);
+        Ok(record.id)
+    }
+
+    /// Delete the time of local records inside the range, of one app or of all apps.
+    /// Returns the count of records which are deleted or trimmed.
+    pub fn delete_range(
+        &self,
+        start: Timestamp,
+        end: Timestamp,
+        app_id: Option<AppId>,
+    ) -> Result<usize> {
+        check_range(start, end)?;
+        let mut count = 0;
+        self.rewrite_records(|records| {
+            let ret = edit_range(records, start, end, |x| {
+                if app_id.is_none_or(|id| id == x.id) {
+                    count += 1;
+                    return None;
+                }
+                Some(x)
+            });
+            (count > 0).then_some(ret)
+        })?;
+        info!(
+            "Delete {} records of app {:?} in {}..{}",
+            count, app_id, start, end
+        );
+        Ok(count)
+    }
+
+    /// Move the time of local records inside the range to another app, from one app or from all
+    /// apps.
+    /// Returns the count of records which are moved, wholly or partly.
+    pub fn reattribute_range(
+        &self,
+        start: Timestamp,
+        end: Timestamp,
+        from: Option<AppId>,
+        to: AppId,
+    ) -> Result<usize> {
+        check_range(start, end)?;
+        self.app.get_app_info(to)?;
+        let mut count = 0;
+        self.rewrite_records(|records| {
+            let ret = edit_range(records, start, end, |mut x| {
+                if x.id != to && from.is_none_or(|id| id == x.id) {
+                    x.id = to;
+                    count += 1;
+                }
+                Some(x)
+            });
+            (count > 0).then_some(ret)
+        })?;
+        info!(
+            "Move {} records in {}..{} from app {:?} to {}",
+            count, start, end, from, to
+        );
+        Ok(count)
+    }
+
+    /// Split the local record which spans `at` into two records of the same app.
+    /// Returns false if no local record spans `at`.
+    pub fn split_record(&self, at: Timestamp) -> Result<bool> {
+        let mut split = false;
+        self.rewrite_records(|records| {
+            let position = records
+                .iter()
+                .position(|x| x.device == LOCAL_DEVICE && x.focus_at < at && at < x.blur_at)?;
+            let mut records = records.to_vec();
+            let record = records[position];
+            records[position].blur_at = at;
+            records.insert(
+                position + 1,
+                FocusRecord {
+                    focus_at: at,
+                    ..record
+                },
+            );
+            split = true;
+            Some(records)
+        })?;
+        Ok(split)
+    }
+}
+
+/// Edits can't reach the future, where the tracker writes next.
+fn check_range(start: Timestamp, end: Timestamp) -> Result<()> {
+    if start >= end || end > now_timestamp() {
+        return Err(StorageError::InvalidRange { start, end });
+    }
+    Ok(())
+}
+
+/// Apply `f` to the parts of local records inside `start..end`, records across the bounds are
+/// split if their part is changed. `f` returns `None` to drop the part. Records are kept in time
+/// order, records of other devices are kept as is.
+fn edit_range(
+    records: &[FocusRecord],
+    start: Timestamp,
+    end: Timestamp,
+    mut f: impl FnMut(FocusRecord) -> Option<FocusRecord>,
+) -> Vec<FocusRecord> {
+    let mut ret = Vec::with_capacity(records.len() + 2);
+    for record in records {
+        if record.device != LOCAL_DEVICE || record.blur_at <= start || record.focus_at >= end {
+            ret.push(*record);
+            continue;
+        }
+        let inner = FocusRecord {
+            focus_at: record.focus_at.max(start),
+            blur_at: record.blur_at.min(end),
+            ..*record
+        };
+        let edited = f(inner);
+        if edited == Some(inner) {
+            ret.push(*record);
+            continue;
+        }
+        if record.focus_at < start {
+            ret.push(FocusRecord {
+                blur_at: start,
+                ..*record
+            });
+        }
+        ret.extend(edited);
+        if record.blur_at > end {
+            ret.push(FocusRecord {
+                focus_at: end,
+                ..*record
+            });
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::FocusRecord;
+    use crate::storage::import::ImportSpan;
+    use crate::storage::test_util::{record, temp_data_dir};
+    use crate::storage::{Storage, StorageError, verify};
+    use crate::util::now_timestamp;
+
+    #[test]
+    fn test_edit_records() {
+        let dir = temp_data_dir("edit-records");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 10000, now - 6000)
+            .unwrap();
+        storage
+            .write_record("C:\\b.exe", now - 6000, now - 2000)
+            .unwrap();
+
+        let id = storage
+            .insert_record("Meeting", now - 7000, now - 5000)
+            .unwrap();
+        assert_eq!(id, 2);
+        assert_eq!(
+            storage.read_by_timestamp(0, now),
+            vec![
+                record(0, now - 10000, now - 7000),
+                record(2, now - 7000, now - 5000),
+                record(1, now - 5000, now - 2000),
+            ]
+        );
+        assert_eq!(
+            storage
+                .reattribute_range(now - 9000, now - 3000, Some(0), 1)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            storage.delete_range(now - 6000, now - 4000, None).unwrap(),
+            2
+        );
+        assert!(storage.split_record(now - 3000).unwrap());
+        assert!(!storage.split_record(now - 5000).unwrap());
+        assert_eq!(
+            storage.read_by_timestamp(0, now),
+            vec![
+                record(0, now - 10000, now - 9000),
+                record(1, now - 9000, now - 7000),
+                record(2, now - 7000, now - 6000),
+                record(1, now - 4000, now - 3000),
+                record(1, now - 3000, now - 2000),
+            ]
+        );
+        assert!(matches!(
+            storage.insert_record("Meeting", now, now + 1000),
+            Err(StorageError::InvalidRange { .. })
+        ));
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_edit_keeps_other_devices() {
+        let dir = temp_data_dir("edit-devices");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 10000, now - 6000)
+            .unwrap();
+        let span = ImportSpan {
+            path: "C:\\a.exe".to_string(),
+            focus_at: now - 8000,
+            blur_at: now - 4000,
+            device: 1,
+        };
+        storage.import_spans(&[span], false).unwrap();
+        let other = FocusRecord {
+            device: 1,
+            ..record(0, now - 8000, now - 4000)
+        };
+
+        storage
+            .insert_record("Meeting", now - 7000, now - 5000)
+            .unwrap();
+        assert_eq!(
+            storage.read_by_timestamp(0, now),
+            vec![
+                record(0, now - 10000, now - 7000),
+                other,
+                record(1, now - 7000, now - 5000),
+            ]
+        );
+        assert_eq!(
+            storage.delete_range(now - 9000, now - 3000, None).unwrap(),
+            2
+        );
+        assert_eq!(
+            storage
+                .reattribute_range(now - 9000, now - 3000, None, 1)
+                .unwrap(),
+            0
+        );
+        assert!(!storage.split_record(now - 6000).unwrap());
+        assert_eq!(
+            storage.read_by_timestamp(0, now),
+            vec![record(0, now - 10000, now - 9000), other]
+        );
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+}
@@ -0,0 +1,42 @@
+use crate::models::AppId;
+use crate::util::Timestamp;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum StorageError {
+    #[error("failed to access {path}: {source}")]
+    Io { path: PathBuf, source: io::Error },
+    #[error("{0} is used by another process")]
+    Locked(PathBuf),
+    #[error("app id {0} not found")]
+    AppNotFound(AppId),
+    #[error("{path} has unsupported format version {version}")]
+    UnsupportedVersion { path: PathBuf, version: u32 },
+    #[error("storage files are closed after a failed rewrite")]
+    Closed,
+    #[error("storage is opened read-only")]
+    ReadOnly,
+    #[error("app id {0} can't be merged into itself")]
+    MergeIntoSelf(AppId),
+    #[error("invalid time range {start}..{end}")]
+    InvalidRange { start: Timestamp, end: Timestamp },
+    #[error("{path} is not a valid snapshot: {reason}")]
+    InvalidSnapshot { path: PathBuf, reason: String },
+    #[error("too many devices, at most {0}")]
+    TooManyDevices(usize),
+    #[error("{0} backups may keep the history, confirm to remove them")]
+    BackupsExist(usize),
+    #[error("too many apps, at most {0}")]
+    TooManyApps(usize),
+}
+
+impl StorageError {
+    /// Attach the file path to an io error, use as `.map_err(StorageError::io(path))`.
+    pub(crate) fn io(path: impl AsRef<Path>) -> impl FnOnce(io::Error) -> Self {
+        let path = path.as_ref().to_path_buf();
+        move |source| Self::Io { path, source }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, StorageError>;
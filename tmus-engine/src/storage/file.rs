@@ -0,0 +1,70 @@
+use crate::storage::error::{Result, StorageError};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Only allow other process to read the file while it's opened.
+#[cfg(windows)]
+const FILE_SHARE_READ: u32 = 1u32;
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+/// Open a storage file which only this process can write.
+///
+/// - Windows: open with `FILE_SHARE_READ` share mode, the system rejects other writers.
+/// - Unix: take an exclusive advisory lock, other tmus writers fail to lock it.
+///   Readers never lock, so they can still read while the file is opened.
+pub(crate) fn open_exclusive(options: &mut OpenOptions, path: impl AsRef<Path>) -> Result<File> {
+    let path = path.as_ref();
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::OpenOptionsExt;
+        options.share_mode(FILE_SHARE_READ);
+    }
+    let file = match options.open(path) {
+        #[cfg(windows)]
+        Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => {
+            return Err(StorageError::Locked(path.to_path_buf()));
+        }
+        file => file.map_err(StorageError::io(path))?,
+    };
+    #[cfg(unix)]
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(std::fs::TryLockError::WouldBlock) => {
+            return Err(StorageError::Locked(path.to_path_buf()));
+        }
+        Err(std::fs::TryLockError::Error(e)) => return Err(StorageError::io(path)(e)),
+    }
+    Ok(file)
+}
+
+/// Replace the file content by writing a sibling temporary file and renaming it over the target,
+/// so the file is either fully old or fully new after a crash.
+pub(crate) fn replace_file(path: impl AsRef<Path>, content: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let tmp_path = write_temp(path, content)?;
+    std::fs::rename(&tmp_path, path).map_err(StorageError::io(path))
+}
+
+/// Write the content to a synced sibling temporary file, which is renamed to the path later.
+pub(crate) fn write_temp(path: impl AsRef<Path>, content: &[u8]) -> Result<PathBuf> {
+    let mut tmp_path = path.as_ref().as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    let mut file = File::create(&tmp_path).map_err(StorageError::io(&tmp_path))?;
+    file.write_all(content)
+        .and_then(|_| file.sync_all())
+        .map_err(StorageError::io(&tmp_path))?;
+    Ok(tmp_path)
+}
+
+/// Read the whole file, a missing file is read as empty.
+pub(crate) fn read_or_default(path: impl AsRef<Path>) -> Result<Vec<u8>> {
+    let path = path.as_ref();
+    match std::fs::read(path) {
+        Ok(buf) => Ok(buf),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(StorageError::io(path)(e)),
+    }
+}
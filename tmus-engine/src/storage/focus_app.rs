@@ -1,101 +1,718 @@
-use super::models::AppId;
-use crate::storage::FILE_SHARE_READ;
-use std::collections::HashMap;
-use std::fs::{File, OpenOptions};
+use super::models::{AppId, AppInfo, MAX_APPS};
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{open_exclusive, read_or_default, replace_file, write_temp};
+use crate::storage::header::{app_header_line, check_version, parse_app_header};
+use crate::util::{Timestamp, ms_as_d, now_timestamp};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
 use std::io::{Read, Write};
-use std::os::windows::prelude::OpenOptionsExt;
-use std::path::Path;
-use std::sync::{Mutex, OnceLock};
-use tracing::info;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::{info, warn};
 
-static STATE: OnceLock<State> = OnceLock::new();
+/// Compact app.jsonl on open if it has more entries than this many per app.
+const ENTRIES_PER_APP: usize = 8;
 
+/// The app registry in app.jsonl, one [`AppEntry`] per line after the header line.
+///
+/// Ids are stored in the entries, so a broken line only loses itself. Since format version 4,
+/// before which app.txt stored one path per line and the line number was the id.
 #[derive(Debug)]
-pub struct State {
-    file: Mutex<File>,
-    path_id_map: Mutex<HashMap<String, AppId>>,
-    id_path_map: Mutex<Vec<String>>,
+pub struct FocusAppFile {
+    file_path: PathBuf,
+    read_only: bool,
+    /// `None` if opened read-only, or closed after a failed replace.
+    file: Mutex<Option<File>>,
+    state: Mutex<State>,
 }
 
-fn get_state<'a>() -> &'a State {
-    STATE.get().unwrap()
+#[derive(Debug, Default)]
+struct State {
+    registry: Registry,
+    path_id_map: HashMap<String, AppId>,
+    /// The day of `last_seen` written to the file, indexed by app id.
+    saved_days: Vec<i64>,
+    /// Length of the file when read, only used in read-only mode.
+    file_len: u64,
 }
 
-pub fn init(data_dir: impl AsRef<Path>) {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .read(true)
-        .share_mode(FILE_SHARE_READ)
-        .open(data_dir.as_ref().join("app.txt"))
-        .expect("open app.txt failed.");
-    let id_path_map = read_apps(&mut file);
-    let mut app_count = 0;
-    let mut path_id_map = HashMap::new();
-    for app in &id_path_map {
-        path_id_map.insert(app.clone(), app_count);
-        app_count += 1;
-    }
-    STATE
-        .set(State {
-            file: Mutex::new(file),
-            path_id_map: Mutex::new(path_id_map),
-            id_path_map: Mutex::new(id_path_map),
-        })
-        .unwrap();
+impl State {
+    fn new(registry: Registry, file_len: u64) -> Self {
+        let mut path_id_map = HashMap::new();
+        let mut saved_days = Vec::with_capacity(registry.apps.len());
+        for app in &registry.apps {
+            if let Some(app) = app {
+                path_id_map
+                    .entry(app.path.clone())
+                    .or_insert(registry.resolve(app.id));
+            }
+            saved_days.push(app.as_ref().map_or(i64::MIN, |x| ms_as_d(x.last_seen)));
+        }
+        Self {
+            registry,
+            path_id_map,
+            saved_days,
+            file_len,
+        }
+    }
 }
 
-pub fn get_path_by_id(id: AppId) -> String {
-    get_state()
-        .id_path_map
-        .lock()
-        .unwrap()
-        .get(id)
-        .expect(&format!("id: {} not in app map.", id))
-        .to_string()
-}
+impl FocusAppFile {
+    pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let file_path = data_dir.as_ref().join("app.jsonl");
+        let mut file = open_file(&file_path)?;
+        let mut buf = String::new();
+        file.read_to_string(&mut buf)
+            .map_err(StorageError::io(&file_path))?;
+        if buf.is_empty() {
+            buf = app_header_line();
+            file.write_all(buf.as_bytes())
+                .map_err(StorageError::io(&file_path))?;
+        }
+        let mut registry = Registry::parse(&buf, &file_path)?;
+        // The id of a broken or torn registration may be in records already, so it's taken by an
+        // unknown app instead of being reused by the next app.
+        let lost: Vec<AppEntry> = registry
+            .lost_ids()
+            .into_iter()
+            .map(|id| {
+                warn!(
+                    "Registration of app {} is broken, register it as unknown",
+                    id
+                );
+                let now = now_timestamp();
+                let entry = AppEntry::register(&AppInfo {
+                    id,
+                    path: format!("unknown-app-{}", id),
+                    first_seen: now,
+                    last_seen: now,
+                    merged_into: None,
+                });
+                registry.apply(entry.clone());
+                registry.entries += 1;
+                entry
+            })
+            .collect();
+        if registry.entries > registry.apps.len().max(1) * ENTRIES_PER_APP {
+            info!("Compact {:?} from {} entries", file_path, registry.entries);
+            drop(file);
+            buf = registry.content();
+            replace_file(&file_path, buf.as_bytes())?;
+            file = open_file(&file_path)?;
+        } else {
+            // Start a new line after a torn write.
+            let mut lines = if buf.ends_with('\n') {
+                String::new()
+            } else {
+                "\n".to_string()
+            };
+            lines.extend(lost.iter().map(AppEntry::to_line));
+            file.write_all(lines.as_bytes())
+                .map_err(StorageError::io(&file_path))?;
+        }
+        Ok(Self {
+            file_path,
+            read_only: false,
+            file: Mutex::new(Some(file)),
+            state: Mutex::new(State::new(registry, 0)),
+        })
+    }
+
+    /// Open without writing or locking the file, which may be written by another process.
+    /// Apps appended later are read when an app isn't found.
+    pub fn open_read_only(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let ret = Self {
+            file_path: data_dir.as_ref().join("app.jsonl"),
+            read_only: true,
+            file: Mutex::new(None),
+            state: Mutex::new(State::default()),
+        };
+        ret.refresh()?;
+        Ok(ret)
+    }
+
+    /// Read the file again if opened read-only and it has changed.
+    pub fn refresh(&self) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        let file_len = self.file_path.metadata().map_or(0, |x| x.len());
+        if file_len == self.state.lock().unwrap().file_len {
+            return Ok(());
+        }
+        let buf = read_or_default(&self.file_path)?;
+        let registry = Registry::parse(&String::from_utf8_lossy(&buf), &self.file_path)?;
+        *self.state.lock().unwrap() = State::new(registry, buf.len() as u64);
+        Ok(())
+    }
+
+    pub fn get_path_by_id(&self, id: AppId) -> Result<String> {
+        Ok(self.get_app_info(id)?.path)
+    }
+
+    pub fn get_app_info(&self, id: AppId) -> Result<AppInfo> {
+        let find = || {
+            let state = self.state.lock().unwrap();
+            state.registry.apps.get(id).cloned().flatten()
+        };
+        if let Some(app) = find() {
+            return Ok(app);
+        }
+        self.refresh()?;
+        find().ok_or(StorageError::AppNotFound(id))
+    }
+
+    /// Register the app if it's new.
+    pub fn get_id_by_path(&self, name: &str) -> Result<AppId> {
+        let now = now_timestamp();
+        self.get_or_register(name, now, now)
+    }
+
+    /// Register the app if it's new, and extend the time it's last seen.
+    /// `last_seen` is written at most once a day for every app.
+    pub fn touch(&self, name: &str, focus_at: Timestamp, blur_at: Timestamp) -> Result<AppId> {
+        let id = self.get_or_register(name, focus_at, blur_at)?;
+        let mut state = self.state.lock().unwrap();
+        let Some(Some(app)) = state.registry.apps.get_mut(id) else {
+            return Ok(id);
+        };
+        if blur_at <= app.last_seen {
+            return Ok(id);
+        }
+        app.last_seen = blur_at;
+        if ms_as_d(blur_at) > state.saved_days[id] {
+            self.append(&AppEntry {
+                id,
+                last_seen: Some(blur_at),
+                ..Default::default()
+            })?;
+            state.saved_days[id] = ms_as_d(blur_at);
+        }
+        Ok(id)
+    }
+
+    /// Paths indexed by app id, apps whose registration is lost are empty.
+    pub fn get_all_app(&self) -> Vec<String> {
+        if let Err(e) = self.refresh() {
+            warn!("Failed to refresh apps: {}", e);
+        }
+        let state = self.state.lock().unwrap();
+        state
+            .registry
+            .apps
+            .iter()
+            .map(|x| x.as_ref().map_or(String::new(), |x| x.path.clone()))
+            .collect()
+    }
 
-pub fn get_id_by_path(name: &str) -> AppId {
-    let id = {
-        get_state()
-            .path_id_map
+    /// The content of app.jsonl with one entry per app.
+    pub fn content(&self) -> Result<String> {
+        self.refresh()?;
+        Ok(self.state.lock().unwrap().registry.content())
+    }
+
+    pub fn get_all_app_info(&self) -> Vec<AppInfo> {
+        if let Err(e) = self.refresh() {
+            warn!("Failed to refresh apps: {}", e);
+        }
+        let state = self.state.lock().unwrap();
+        state.registry.apps.iter().flatten().cloned().collect()
+    }
+
+    /// Merge the apps of `sources` into `target`, which is resolved if it's merged itself.
+    /// The paths of the sources are mapped to the target, and the time range of the target
+    /// covers the sources. Returns the resolved target.
+    pub fn merge(&self, sources: &[AppId], target: AppId) -> Result<AppId> {
+        let mut state = self.state.lock().unwrap();
+        let target = state.registry.resolve(target);
+        if sources.contains(&target) {
+            return Err(StorageError::MergeIntoSelf(target));
+        }
+        let mut first_seen = Timestamp::MAX;
+        let mut last_seen = Timestamp::MIN;
+        for &id in sources.iter().chain([&target]) {
+            let app = state.registry.apps.get(id).cloned().flatten();
+            let app = app.ok_or(StorageError::AppNotFound(id))?;
+            first_seen = first_seen.min(app.first_seen);
+            last_seen = last_seen.max(app.last_seen);
+        }
+        let mut entries: Vec<AppEntry> = sources
+            .iter()
+            .map(|&id| AppEntry {
+                id,
+                merged_into: Some(target),
+                ..Default::default()
+            })
+            .collect();
+        entries.push(AppEntry {
+            id: target,
+            first_seen: Some(first_seen),
+            last_seen: Some(last_seen),
+            ..Default::default()
+        });
+        for entry in entries {
+            self.append(&entry)?;
+            state.registry.entries += 1;
+            state.registry.apply(entry);
+        }
+        let file_len = state.file_len;
+        *state = State::new(std::mem::take(&mut state.registry), file_len);
+        Ok(target)
+    }
+
+    /// Forget the app and the apps merged into it. Their registrations are replaced by
+    /// tombstones in app.jsonl, so the paths are erased and the ids are never reused.
+    /// Returns the forgotten ids.
+    pub fn forget(&self, id: AppId) -> Result<Vec<AppId>> {
+        let mut state = self.state.lock().unwrap();
+        let mut registry = state.registry.clone();
+        if registry.apps.get(id).is_none_or(|x| x.is_none()) {
+            return Err(StorageError::AppNotFound(id));
+        }
+        let ids: Vec<AppId> = (0..registry.apps.len())
+            .filter(|&x| registry.apps[x].is_some() && registry.is_merged_into(x, id))
+            .collect();
+        for &x in &ids {
+            registry.apps[x] = None;
+            registry.forgotten.insert(x);
+        }
+        registry.entries = registry.apps.iter().flatten().count() + registry.forgotten.len();
+        self.replace(&registry)?;
+        let file_len = state.file_len;
+        *state = State::new(registry, file_len);
+        Ok(ids)
+    }
+
+    /// Whether `id` is merged into `target` directly or through other apps, or is `target`.
+    pub fn is_merged_into(&self, id: AppId, target: AppId) -> bool {
+        self.state
             .lock()
             .unwrap()
-            .get(name)
-            .map(|x| x.to_owned())
-    };
-    match id {
-        None => write_app(name),
-        Some(x) => x,
+            .registry
+            .is_merged_into(id, target)
+    }
+
+    fn get_or_register(
+        &self,
+        name: &str,
+        first_seen: Timestamp,
+        last_seen: Timestamp,
+    ) -> Result<AppId> {
+        if let Some(id) = self.state.lock().unwrap().path_id_map.get(name) {
+            return Ok(*id);
+        }
+        if self.read_only {
+            self.refresh()?;
+            let state = self.state.lock().unwrap();
+            return state
+                .path_id_map
+                .get(name)
+                .copied()
+                .ok_or(StorageError::ReadOnly);
+        }
+        self.write_app(name, first_seen, last_seen)
+    }
+
+    /// Returns the app id which was written.
+    /// The app is only added to the map after it's written, so a failed write doesn't leave an id
+    /// which would be reused by the next app.
+    fn write_app(&self, name: &str, first_seen: Timestamp, last_seen: Timestamp) -> Result<AppId> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(app_id) = state.path_id_map.get(name) {
+            return Ok(*app_id);
+        }
+        // Ids are stored in 24 bits of a record, see FocusRecord::to_byte.
+        if state.registry.apps.len() >= MAX_APPS {
+            return Err(StorageError::TooManyApps(MAX_APPS));
+        }
+        info!("New app found, write app: {}", name);
+        let app = AppInfo {
+            id: state.registry.apps.len(),
+            path: name.to_string(),
+            first_seen,
+            last_seen,
+            merged_into: None,
+        };
+        self.append(&AppEntry::register(&app))?;
+        state.path_id_map.insert(app.path.clone(), app.id);
+        state.saved_days.push(ms_as_d(app.last_seen));
+        state.registry.entries += 1;
+        state.registry.apps.push(Some(app));
+        Ok(state.registry.apps.len() - 1)
+    }
+
+    fn append(&self, entry: &AppEntry) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let file = file.as_mut().ok_or(self.closed_error())?;
+        file.write_all(entry.to_line().as_bytes())
+            .and_then(|_| file.flush())
+            .map_err(StorageError::io(&self.file_path))
+    }
+
+    /// Replace app.jsonl by the content of the registry, the file is reopened after replaced.
+    fn replace(&self, registry: &Registry) -> Result<()> {
+        let mut file = self.file.lock().unwrap();
+        if file.is_none() {
+            return Err(self.closed_error());
+        }
+        let tmp_path = write_temp(&self.file_path, registry.content().as_bytes())?;
+        // Close the file, which can't be replaced while opened on Windows.
+        *file = None;
+        let renamed =
+            fs::rename(&tmp_path, &self.file_path).map_err(StorageError::io(&self.file_path));
+        *file = Some(open_file(&self.file_path)?);
+        renamed
+    }
+
+    fn closed_error(&self) -> StorageError {
+        if self.read_only {
+            StorageError::ReadOnly
+        } else {
+            StorageError::Closed
+        }
     }
 }
 
-pub fn get_all_app() -> Vec<String> {
-    get_state().id_path_map.lock().unwrap().to_owned()
+/// The id of a line which may be broken or torn, an entry starts with its id.
+fn line_id(line: &str) -> Option<AppId> {
+    let rest = line.strip_prefix("{\"id\":")?;
+    let end = rest.find(|x: char| !x.is_ascii_digit())?;
+    if !rest[end..].starts_with([',', '}']) {
+        return None;
+    }
+    rest[..end].parse().ok()
 }
 
-/// Returns the app id which was written.
-fn write_app(name: &str) -> AppId {
-    info!("New app found, write app: {}", name);
-    let state = get_state();
-    let mut id_name_map = state.id_path_map.lock().unwrap();
-    let mut name_id_map = state.path_id_map.lock().unwrap();
-    let mut file = state.file.lock().unwrap();
-
-    let app_id = id_name_map.len() as AppId;
-    name_id_map.insert(name.to_string(), app_id);
-    id_name_map.push(name.to_string());
-    file.write(format!("{}\n", name).as_bytes())
-        .expect("can't write to app.txt");
-    file.flush().expect("can't flush app.txt");
-    app_id
+fn open_file(path: &Path) -> Result<File> {
+    open_exclusive(
+        OpenOptions::new().create(true).append(true).read(true),
+        path,
+    )
 }
 
-fn read_apps(file: &mut File) -> Vec<String> {
-    let mut buf = String::new();
-    file.read_to_string(&mut buf).unwrap();
+/// One line of app.jsonl. An entry with a path registers the app, and later entries of the
+/// same id extend the time it's seen, or merge it into another app.
+///
+/// `crc` is the CRC32 of the entry serialized with `crc` as 0. Lines which fail to parse or
+/// don't match the checksum are skipped.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct AppEntry {
+    pub id: AppId,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<Timestamp>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub merged_into: Option<AppId>,
+    /// A tombstone of a forgotten app, which keeps the id from being reused.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub forgotten: bool,
+    #[serde(default)]
+    pub crc: u32,
+}
+
+impl AppEntry {
+    pub fn register(app: &AppInfo) -> Self {
+        Self {
+            id: app.id,
+            path: Some(app.path.clone()),
+            first_seen: Some(app.first_seen),
+            last_seen: Some(app.last_seen),
+            merged_into: app.merged_into,
+            forgotten: false,
+            crc: 0,
+        }
+    }
+
+    /// The line with the checksum, including the line break.
+    pub fn to_line(&self) -> String {
+        let entry = Self {
+            crc: self.checksum(),
+            ..self.clone()
+        };
+        format!("{}\n", serde_json::to_string(&entry).unwrap())
+    }
+
+    fn parse(line: &str) -> Option<Self> {
+        let entry: Self = serde_json::from_str(line).ok()?;
+        (entry.crc == entry.checksum()).then_some(entry)
+    }
+
+    fn checksum(&self) -> u32 {
+        let entry = Self {
+            crc: 0,
+            ..self.clone()
+        };
+        crc32fast::hash(serde_json::to_string(&entry).unwrap().as_bytes())
+    }
+}
+
+/// Apps read from app.jsonl.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Registry {
+    /// Indexed by app id, `None` if the app is forgotten or its registration is lost.
+    pub apps: Vec<Option<AppInfo>>,
+    /// Ids of forgotten apps, which are never registered again.
+    pub forgotten: BTreeSet<AppId>,
+    /// Line numbers of broken entries, the header line is line 1.
+    pub broken: Vec<usize>,
+    /// Ids found in broken entries.
+    pub broken_ids: BTreeSet<AppId>,
+    /// Count of valid entries.
+    pub entries: usize,
+}
+
+impl Registry {
+    /// Parse the content of app.jsonl, the header line is checked if there is content.
+    pub fn parse(text: &str, path: &Path) -> Result<Self> {
+        let mut ret = Self::default();
+        let mut lines = text.split('\n');
+        if !text.is_empty() {
+            let version = lines.next().and_then(parse_app_header).unwrap_or(0);
+            check_version(path, version)?;
+        }
+        for (i, line) in lines.enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if AppEntry::parse(line).is_some_and(|entry| ret.apply(entry)) {
+                ret.entries += 1;
+            } else {
+                ret.broken.push(i + 2);
+                ret.broken_ids.extend(line_id(line));
+            }
+        }
+        Ok(ret)
+    }
+
+    /// Returns false if the entry registers an existing id, or updates an unknown id.
+    pub fn apply(&mut self, entry: AppEntry) -> bool {
+        if entry.id >= MAX_APPS {
+            return false;
+        }
+        if entry.forgotten {
+            if self.apps.len() <= entry.id {
+                self.apps.resize(entry.id + 1, None);
+            }
+            self.apps[entry.id] = None;
+            self.forgotten.insert(entry.id);
+            return true;
+        }
+        if self.forgotten.contains(&entry.id) {
+            return false;
+        }
+        match entry.path {
+            Some(path) => {
+                if self.apps.get(entry.id).is_some_and(|x| x.is_some()) {
+                    return false;
+                }
+                if self.apps.len() <= entry.id {
+                    self.apps.resize(entry.id + 1, None);
+                }
+                let first_seen = entry.first_seen.unwrap_or_default();
+                self.apps[entry.id] = Some(AppInfo {
+                    id: entry.id,
+                    path,
+                    first_seen,
+                    last_seen: entry.last_seen.unwrap_or(first_seen),
+                    merged_into: entry.merged_into,
+                });
+                true
+            }
+            None => match self.apps.get_mut(entry.id) {
+                Some(Some(app)) => {
+                    if let Some(first_seen) = entry.first_seen {
+                        app.first_seen = app.first_seen.min(first_seen);
+                    }
+                    if let Some(last_seen) = entry.last_seen {
+                        app.last_seen = app.last_seen.max(last_seen);
+                    }
+                    if entry.merged_into.is_some() {
+                        app.merged_into = entry.merged_into;
+                    }
+                    true
+                }
+                _ => false,
+            },
+        }
+    }
+
+    /// Ids of broken entries which aren't registered or forgotten.
+    /// Records may have them, so they must not be given to new apps.
+    pub fn lost_ids(&self) -> Vec<AppId> {
+        self.broken_ids
+            .iter()
+            .copied()
+            .filter(|&id| id < MAX_APPS && self.apps.get(id).is_none_or(|x| x.is_none()))
+            .filter(|id| !self.forgotten.contains(id))
+            .collect()
+    }
+
+    /// Follow `merged_into` to the app which the records of `id` go to.
+    pub fn resolve(&self, mut id: AppId) -> AppId {
+        // Bounded in case of a cycle written by a broken file.
+        for _ in 0..self.apps.len() {
+            match self
+                .apps
+                .get(id)
+                .cloned()
+                .flatten()
+                .and_then(|x| x.merged_into)
+            {
+                Some(target) => id = target,
+                None => break,
+            }
+        }
+        id
+    }
+
+    /// The content of app.jsonl with one entry per app.
+    pub fn content(&self) -> String {
+        let mut content = app_header_line();
+        for (id, app) in self.apps.iter().enumerate() {
+            let entry = match app {
+                Some(app) => AppEntry::register(app),
+                None if self.forgotten.contains(&id) => AppEntry {
+                    id,
+                    forgotten: true,
+                    ..Default::default()
+                },
+                None => continue,
+            };
+            content.push_str(&entry.to_line());
+        }
+        content
+    }
+
+    /// Whether `id` is `target`, or merged into `target` directly or through other apps.
+    pub fn is_merged_into(&self, mut id: AppId, target: AppId) -> bool {
+        for _ in 0..=self.apps.len() {
+            if id == target {
+                return true;
+            }
+            match self
+                .apps
+                .get(id)
+                .cloned()
+                .flatten()
+                .and_then(|x| x.merged_into)
+            {
+                Some(next) => id = next,
+                None => break,
+            }
+        }
+        false
+    }
+}
+
+/// Paths in app.txt before format version 4. Skip the header line, the rest lines are app paths.
+pub(crate) fn read_apps(buf: &str) -> Vec<String> {
     buf.split('\n')
+        .skip(1)
         .filter(|x| !x.is_empty())
         .map(|x| x.to_owned())
         .collect::<Vec<String>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, verify};
+    use crate::util::now_timestamp;
+    use std::fs;
+
+    #[test]
+    fn test_app_registry() {
+        let dir = temp_data_dir("app-registry");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 5000, now - 4000)
+            .unwrap();
+        storage
+            .write_record("C:\\b.exe", now - 3000, now - 2000)
+            .unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 2000, now - 1000)
+            .unwrap();
+        assert_eq!(storage.get_app_info(0).unwrap().last_seen, now - 1000);
+        drop(storage);
+
+        // Break the registration of a.exe, b.exe keeps its id.
+        let path = dir.join("app.jsonl");
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, text.replacen("a.exe", "x.exe", 1)).unwrap();
+        let report = verify::verify(&dir).unwrap();
+        assert!(
+            report
+                .issues
+                .contains(&verify::Issue::BrokenAppEntry { line: 2 })
+        );
+        verify::repair(&dir).unwrap();
+        assert!(verify::verify(&dir).unwrap().is_ok());
+
+        // The time of app 0 is kept by an unknown app.
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.get_all_app(), vec!["unknown-app-0", "C:\\b.exe"]);
+        assert_eq!(storage.read_by_timestamp(0, now).len(), 3);
+        let app = storage.get_app_info(0).unwrap();
+        assert_eq!((app.first_seen, app.last_seen), (now - 5000, now - 1000));
+        let app = storage.get_app_info(1).unwrap();
+        assert_eq!((app.first_seen, app.last_seen), (now - 3000, now - 2000));
+        assert_eq!(storage.get_id_by_path("C:\\a.exe").unwrap(), 2);
+        drop(storage);
+
+        // The id of a torn registration isn't reused, its records keep the app.
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &text[..text.len() - 10]).unwrap();
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.get_path_by_id(2).unwrap(), "unknown-app-2");
+        assert_eq!(storage.get_id_by_path("C:\\c.exe").unwrap(), 3);
+        drop(storage);
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(
+            storage.get_all_app(),
+            ["unknown-app-0", "C:\\b.exe", "unknown-app-2", "C:\\c.exe"]
+        );
+    }
+
+    #[test]
+    fn test_broken_app_entry() {
+        let dir = temp_data_dir("broken-app-entry");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 3000, now - 2000)
+            .unwrap();
+        storage
+            .write_record("C:\\b.exe", now - 2000, now - 1000)
+            .unwrap();
+        drop(storage);
+
+        // A complete line of b.exe whose checksum fails.
+        let path = dir.join("app.jsonl");
+        let text = fs::read_to_string(&path).unwrap();
+        fs::write(&path, text.replacen("b.exe", "x.exe", 1)).unwrap();
+
+        // The id of b.exe isn't reused, its records keep the app.
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.get_path_by_id(1).unwrap(), "unknown-app-1");
+        assert_eq!(storage.get_id_by_path("C:\\c.exe").unwrap(), 2);
+        drop(storage);
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(
+            storage.get_all_app(),
+            ["C:\\a.exe", "unknown-app-1", "C:\\c.exe"]
+        );
+        let apps: Vec<_> = storage
+            .read_by_timestamp(0, now)
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(apps, [0, 1]);
+    }
+}
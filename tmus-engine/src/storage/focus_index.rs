@@ -1,113 +1,189 @@
 use super::models::CursorPosition;
-use crate::storage::FILE_SHARE_READ;
-use crate::util::{Timestamp, d_as_ms, now_day};
+use crate::models::FocusRecord;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{open_exclusive, read_or_default};
+use crate::storage::header::{FileHeader, HEADER_SIZE, INDEX_MAGIC};
+use crate::util::{Timestamp, d_as_ms, ms_as_d, now_day};
 use serde::{Deserialize, Serialize};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
-use std::os::windows::prelude::OpenOptionsExt;
-use std::path::Path;
-use std::sync::{Mutex, OnceLock};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 type IndexUnitByte = [u8; 8];
 type IndexUnit = u64;
 
-static STATE: OnceLock<State> = OnceLock::new();
-
 /// 2.85kB one year.
 ///
-/// The first 8 bytes in file means the first day from `UNIX_EPOCH`.
-/// After which, every 8 bytes represent the starting index of the corresponding day in record.bin.
-/// Each value is the record file index of specific day.
+/// The file starts with a [`FileHeader`], whose `extra` is the first day from `UNIX_EPOCH`.
+/// After which, every 8 bytes represent the starting position of the corresponding day in the records,
+/// starting from the day after the first day. The first day always starts at 0.
 #[derive(Debug)]
-struct State {
-    file: Mutex<File>,
+pub struct FocusIndexFile {
+    file_path: PathBuf,
+    /// `None` if opened read-only.
+    file: Option<Mutex<File>>,
     /// Initial data recording date from `UNIX_EPOCH`.
-    base_day: IndexUnit,
+    base_day: AtomicU64,
     record_index_vec: Mutex<Vec<IndexUnit>>,
 }
 
-fn get_state<'a>() -> &'a State {
-    STATE.get().unwrap()
-}
-
-pub fn init(data_dir: impl AsRef<Path>) {
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .read(true)
-        .share_mode(FILE_SHARE_READ)
-        .open(data_dir.as_ref().join("index.bin"))
-        .expect("open index.bin failed.");
-    let mut index = read_index(&mut file);
-    let base_day = if index.is_empty() {
-        let today = now_day() as IndexUnit;
-        file.write(&today.to_le_bytes()).unwrap();
-        index.push(0);
-        today
-    } else {
-        let ret = index[0];
-        index[0] = 0;
-        ret
-    };
-    STATE
-        .set(State {
-            file: Mutex::new(file),
-            base_day,
+impl FocusIndexFile {
+    pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let file_path = data_dir.as_ref().join("index.bin");
+        let mut file = open_exclusive(
+            OpenOptions::new().create(true).append(true).read(true),
+            &file_path,
+        )?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)
+            .map_err(StorageError::io(&file_path))?;
+        if buf.is_empty() {
+            let header = FileHeader::new(INDEX_MAGIC, now_day() as IndexUnit);
+            file.write_all(&header.to_bytes())
+                .map_err(StorageError::io(&file_path))?;
+            buf.extend_from_slice(&header.to_bytes());
+        }
+        let (base_day, index) = parse_index(&buf, &file_path)?;
+        Ok(Self {
+            file_path,
+            file: Some(Mutex::new(file)),
+            base_day: AtomicU64::new(base_day),
             record_index_vec: Mutex::new(index),
         })
-        .unwrap();
-}
+    }
 
-pub(crate) fn query_index(day: IndexUnit) -> CursorPosition {
-    let state = get_state();
-    let base_day = state.base_day;
-    let size = state.record_index_vec.lock().unwrap().len();
-    day.checked_sub(base_day)
-        .map(|n| {
-            if n >= size as IndexUnit {
-                CursorPosition::End
-            } else {
-                CursorPosition::Middle(state.record_index_vec.lock().unwrap()[n as usize] as usize)
-            }
-        })
-        .unwrap_or(CursorPosition::Start)
-}
+    /// Open without writing or locking the file, which may be written by another process.
+    /// Call [`Self::refresh`] to see the appended index.
+    pub fn open_read_only(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let ret = Self {
+            file_path: data_dir.as_ref().join("index.bin"),
+            file: None,
+            base_day: AtomicU64::new(now_day() as IndexUnit),
+            record_index_vec: Mutex::new(vec![0]),
+        };
+        ret.refresh()?;
+        Ok(ret)
+    }
 
-/// If the record start time is later than the last day, write the index to the file.
-pub fn update_index(day: IndexUnit, index: IndexUnit) {
-    let last_day = last_day();
-    if day <= last_day {
-        return;
+    /// Reread the file if opened read-only. A missing file is read as no index.
+    pub fn refresh(&self) -> Result<()> {
+        if self.file.is_some() {
+            return Ok(());
+        }
+        let buf = read_or_default(&self.file_path)?;
+        if buf.is_empty() {
+            return Ok(());
+        }
+        let (base_day, index) = parse_index(&buf, &self.file_path)?;
+        let mut record_index_vec = self.record_index_vec.lock().unwrap();
+        self.base_day.store(base_day, Ordering::Relaxed);
+        *record_index_vec = index;
+        Ok(())
     }
-    let state = get_state();
-    for _ in last_day..day {
-        write_index(index, state);
+
+    pub(crate) fn query_index(&self, day: IndexUnit) -> CursorPosition {
+        let index = self.record_index_vec.lock().unwrap();
+        day.checked_sub(self.base_day.load(Ordering::Relaxed))
+            .map(|n| {
+                if n >= index.len() as IndexUnit {
+                    CursorPosition::End
+                } else {
+                    CursorPosition::Middle(index[n as usize] as usize)
+                }
+            })
+            .unwrap_or(CursorPosition::Start)
+    }
+
+    /// If the record start time is later than the last day, write the index to the file.
+    /// Earlier days are ignored, records of them are merged by [`super::Storage::write_records_batch`].
+    pub fn update_index(&self, day: IndexUnit, index: IndexUnit) -> Result<()> {
+        let last_day = self.last_day();
+        if day <= last_day {
+            return Ok(());
+        }
+        for _ in last_day..day {
+            self.write_index(index)?;
+        }
+        Ok(())
+    }
+
+    pub fn start_day(&self) -> IndexUnit {
+        self.base_day.load(Ordering::Relaxed)
     }
-}
 
-/// Write the index to the file. The index is the starting index of the corresponding day in record.bin.
-fn write_index(value: IndexUnit, state: &State) {
-    let mut index = state.record_index_vec.lock().unwrap();
-    let mut file = state.file.lock().unwrap();
-    index.push(value);
-    file.write(&value.to_le_bytes()).unwrap();
+    /// The index of the days from the base day to `day`, fewer if the index ends before it.
+    pub(crate) fn days_until(&self, day: IndexUnit) -> Vec<IndexUnit> {
+        let index = self.record_index_vec.lock().unwrap();
+        let len = (day + 1).saturating_sub(self.start_day()) as usize;
+        index[..len.min(index.len())].to_vec()
+    }
+
+    pub fn all_record(&self) -> Vec<FileIndexRecord> {
+        let index_vec = self.record_index_vec.lock().unwrap().clone();
+        let base_day = self.start_day();
+        index_vec
+            .iter()
+            .enumerate()
+            .map(|(i, &start_index)| FileIndexRecord {
+                date_time: d_as_ms((base_day + i as IndexUnit) as i64),
+                start_index,
+            })
+            .collect()
+    }
+
+    /// Write the index to the file. The index is the starting position of the corresponding day in the records.
+    fn write_index(&self, value: IndexUnit) -> Result<()> {
+        let mut index = self.record_index_vec.lock().unwrap();
+        let mut file = self
+            .file
+            .as_ref()
+            .ok_or(StorageError::ReadOnly)?
+            .lock()
+            .unwrap();
+        file.write_all(&value.to_le_bytes())
+            .map_err(StorageError::io(&self.file_path))?;
+        index.push(value);
+        Ok(())
+    }
+
+    fn last_day(&self) -> IndexUnit {
+        self.start_day() + self.record_index_vec.lock().unwrap().len() as IndexUnit - 1
+    }
 }
 
-fn last_day() -> IndexUnit {
-    get_state().base_day + get_state().record_index_vec.lock().unwrap().len() as IndexUnit - 1
+/// The base day in the header and the index of every day from the base day.
+fn parse_index(buf: &[u8], path: &Path) -> Result<(IndexUnit, Vec<IndexUnit>)> {
+    let header =
+        FileHeader::parse(INDEX_MAGIC, buf).ok_or_else(|| StorageError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version: 0,
+        })?;
+    header.check_version(path)?;
+    let mut index = vec![0];
+    index.extend(read_index(&buf[HEADER_SIZE..]));
+    Ok((header.extra, index))
 }
 
-pub fn start_day() -> IndexUnit {
-    get_state().base_day
+/// A trailing incomplete unit is a torn write, it's ignored.
+pub(crate) fn read_index(buf: &[u8]) -> impl Iterator<Item = IndexUnit> + '_ {
+    buf.chunks_exact(size_of::<IndexUnitByte>())
+        .map(|chunk| IndexUnit::from_le_bytes(chunk.try_into().unwrap()))
 }
 
-fn read_index(file: &mut File) -> Vec<IndexUnit> {
-    let mut buf: IndexUnitByte = IndexUnitByte::default();
-    let mut ret = Vec::new();
-    while file.read(&mut buf).unwrap() != 0 {
-        ret.push(IndexUnit::from_le_bytes(buf));
-    }
-    ret
+/// The index of every day after the base day until the day of the last record.
+/// Days without records point to the first record after them, same as [`FocusIndexFile::update_index`].
+pub(crate) fn build_index(base_day: IndexUnit, records: &[FocusRecord]) -> Vec<IndexUnit> {
+    let Some(last) = records.last() else {
+        return vec![];
+    };
+    let last_day = ms_as_d(last.focus_at) as IndexUnit;
+    (base_day + 1..=last_day)
+        .map(|day| {
+            records.partition_point(|x| (ms_as_d(x.focus_at) as IndexUnit) < day) as IndexUnit
+        })
+        .collect()
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -116,21 +192,3 @@ pub struct FileIndexRecord {
     pub date_time: Timestamp,
     pub start_index: IndexUnit,
 }
-
-pub fn all_record() -> Vec<FileIndexRecord> {
-    let (base_day, index_vec) = {
-        let state = get_state();
-        (
-            state.base_day,
-            state.record_index_vec.lock().unwrap().clone(),
-        )
-    };
-    index_vec
-        .iter()
-        .enumerate()
-        .map(|(i, &start_index)| FileIndexRecord {
-            date_time: d_as_ms((base_day + i as IndexUnit) as i64),
-            start_index,
-        })
-        .collect()
-}
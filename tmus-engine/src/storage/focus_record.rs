@@ -1,102 +1,199 @@
-use crate::models::RecordByte;
+use crate::models::{FocusRecord, RecordByte, RecordV1Byte};
+use crate::storage::error::{Result, StorageError};
+use crate::storage::header::{FORMAT_VERSION, FileHeader, HEADER_SIZE, RECORD_MAGIC};
 use memmap2::MmapMut;
 use std::fs::OpenOptions;
 use std::mem::size_of;
 use std::path::{Path, PathBuf};
-use std::sync::{Mutex, MutexGuard, OnceLock};
+use std::sync::Mutex;
 use tracing::debug;
 
 const RECORD_SIZE: usize = size_of::<RecordByte>();
 /// The size use for mmap expand every time.
 const EXPAND_SIZE: usize = 4 * 1024;
 
-static STATE: OnceLock<Mutex<State>> = OnceLock::new();
+/// Records of a record file, mapped to memory and appended in time order after the [`FileHeader`].
+///
+/// The record layout depends on the version in the header. Version 1 files can be read,
+/// but only files of the current layout can be written.
+///
+/// The `extra` of the header is the count of committed records, which is updated after every
+/// write. 0 means unknown, then the length is found by scanning for the first all-zero record.
+#[derive(Debug)]
+pub struct FocusRecordFile {
+    state: Mutex<State>,
+}
 
 #[derive(Debug)]
-pub struct State {
+struct State {
     file_path: PathBuf,
+    version: u32,
+    record_size: usize,
     mmap: MmapMut,
+    /// Bytes used in the file, including the header.
     len: usize,
     size: usize,
 }
 
-fn get_state<'a>() -> MutexGuard<'a, State> {
-    STATE.get().unwrap().lock().unwrap()
+impl FocusRecordFile {
+    pub fn open(file_path: impl AsRef<Path>) -> Result<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let mut mmap = map_file(&file_path, None)?;
+        if mmap.is_empty() {
+            mmap = map_file(&file_path, Some(EXPAND_SIZE))?;
+            mmap[..HEADER_SIZE].copy_from_slice(&FileHeader::new(RECORD_MAGIC, 0).to_bytes());
+            mmap.flush_range(0, HEADER_SIZE)
+                .map_err(StorageError::io(&file_path))?;
+        }
+        let header = FileHeader::parse(RECORD_MAGIC, &mmap).ok_or_else(|| {
+            StorageError::UnsupportedVersion {
+                path: file_path.clone(),
+                version: 0,
+            }
+        })?;
+        header.check_version(&file_path)?;
+        let record_size = record_size(header.version);
+        let size = HEADER_SIZE + (mmap.len() - HEADER_SIZE) / record_size * record_size;
+        let search_start = size
+            .saturating_sub(EXPAND_SIZE / record_size * record_size)
+            .max(HEADER_SIZE);
+        let scanned_len = search_start + find_really_len(&mmap[search_start..size], record_size);
+        let committed_len = HEADER_SIZE + header.extra as usize * record_size;
+        let len = scanned_len.max(committed_len.min(size));
+        Ok(Self {
+            state: Mutex::new(State {
+                file_path,
+                version: header.version,
+                record_size,
+                mmap,
+                len,
+                size,
+            }),
+        })
+    }
+
+    /// Returns the position of the written record.
+    pub fn write(&self, record: RecordByte) -> Result<u64> {
+        let mut state = self.state.lock().unwrap();
+        if state.record_size != RECORD_SIZE {
+            return Err(StorageError::UnsupportedVersion {
+                path: state.file_path.clone(),
+                version: state.version,
+            });
+        }
+        if state.size <= state.len {
+            expand_size(&mut state)?;
+        }
+        let range = state.len..state.len + RECORD_SIZE;
+        state.mmap[range].copy_from_slice(&record);
+        state.len += RECORD_SIZE;
+        let count = ((state.len - HEADER_SIZE) / RECORD_SIZE) as u64;
+        state.mmap[8..HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+        debug!(
+            "Write record:{}",
+            record
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect::<String>()
+        );
+        Ok(count - 1)
+    }
+
+    /// The count of records in the file.
+    pub fn count(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        (state.len - HEADER_SIZE) / state.record_size
+    }
+
+    pub fn read(&self, start: Option<usize>, end: Option<usize>) -> Vec<FocusRecord> {
+        let state = self.state.lock().unwrap();
+        let record_size = state.record_size;
+        let start = HEADER_SIZE + start.map_or(0, |start| start * record_size);
+        let end = end.map_or(state.len, |end| {
+            debug_assert!(HEADER_SIZE + end * record_size <= state.len);
+            HEADER_SIZE + end * record_size
+        });
+        debug_assert!(start <= end, "Start must less than or equal to end.");
+        decode_records(&state.mmap[start..end], state.version)
+    }
 }
 
-pub fn init(data_dir: impl AsRef<Path>) {
-    let file_path = data_dir.as_ref().join("record.bin");
-    let mmap = map_file(&file_path, None);
-    let size = mmap.len() / 8 * 8;
-    let search_start = size.saturating_sub(4 * 1024);
-    let len = search_start + find_really_len(&mmap[search_start..size]);
-    STATE
-        .set(Mutex::new(State {
-            file_path,
-            mmap,
-            len,
-            size,
-        }))
-        .unwrap();
+pub(crate) fn record_size(version: u32) -> usize {
+    if version < 2 {
+        size_of::<RecordV1Byte>()
+    } else {
+        RECORD_SIZE
+    }
 }
 
-pub fn write(record: RecordByte) -> u64 {
-    let mut state = get_state();
-    if state.size <= state.len {
-        expand_size(&mut state);
+/// Read the version and the records of a whole record file.
+/// Both the committed count and the scanned length are trusted, same as [`FocusRecordFile::open`].
+pub(crate) fn decode_record_file(buf: &[u8], path: &Path) -> Result<(u32, Vec<FocusRecord>)> {
+    if buf.is_empty() {
+        return Ok((FORMAT_VERSION, vec![]));
     }
-    let range = state.len..state.len + RECORD_SIZE;
-    state.mmap[range].copy_from_slice(&record);
-    state.len += 8;
-    debug!(
-        "Write record:{}",
-        record
-            .iter()
-            .map(|byte| format!("{:02x}", byte))
-            .collect::<String>()
-    );
-    (state.len / RECORD_SIZE) as u64
+    let header =
+        FileHeader::parse(RECORD_MAGIC, buf).ok_or_else(|| StorageError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version: 0,
+        })?;
+    header.check_version(path)?;
+    let size = record_size(header.version);
+    let body = &buf[HEADER_SIZE..];
+    let len = (find_really_len(body, size) / size)
+        .max(header.extra as usize)
+        .min(body.len() / size);
+    Ok((
+        header.version,
+        decode_records(&body[..len * size], header.version),
+    ))
 }
 
-pub fn read(start: Option<usize>, end: Option<usize>) -> Vec<RecordByte> {
-    let state = get_state();
-    let start = start.map_or(0, |start| start * RECORD_SIZE);
-    let end = end.map_or(state.len, |end| {
-        debug_assert!(end * RECORD_SIZE <= state.len);
-        end * RECORD_SIZE
-    });
-    debug_assert!(start <= end, "Start must less than or equal to end.");
-    state.mmap[start..end]
-        .chunks(RECORD_SIZE)
-        .map(|chunk| chunk.try_into().unwrap())
+/// Decode records in the layout of the format version.
+pub(crate) fn decode_records(buf: &[u8], version: u32) -> Vec<FocusRecord> {
+    buf.chunks_exact(record_size(version))
+        .map(|chunk| decode_record(chunk, version))
         .collect()
 }
 
-/// Find first all zero 8 bytes, and return the index of it.
-fn find_really_len(arr: &[u8]) -> usize {
-    for (index, chunk) in arr.chunks(8).enumerate() {
+/// Decode one record, the chunk must be of [`record_size`] of the version.
+pub(crate) fn decode_record(chunk: &[u8], version: u32) -> FocusRecord {
+    if version < 2 {
+        RecordV1Byte::try_from(chunk).unwrap().into()
+    } else {
+        RecordByte::try_from(chunk).unwrap().into()
+    }
+}
+
+/// Find first all zero record, and return the index of it.
+pub(crate) fn find_really_len(arr: &[u8], record_size: usize) -> usize {
+    for (index, chunk) in arr.chunks(record_size).enumerate() {
         if chunk.iter().all(|byte| *byte == 0) {
-            return index * 8;
+            return index * record_size;
         }
     }
     arr.len()
 }
 
-fn expand_size(state: &mut State) {
+fn expand_size(state: &mut State) -> Result<()> {
     let new_size = state.size + EXPAND_SIZE;
-    state.mmap = map_file(&state.file_path, Some(new_size));
+    state.mmap = map_file(&state.file_path, Some(new_size))?;
     state.size = new_size;
+    Ok(())
 }
 
-fn map_file<T: AsRef<Path>>(file_path: T, size: Option<usize>) -> MmapMut {
+fn map_file<T: AsRef<Path>>(file_path: T, size: Option<usize>) -> Result<MmapMut> {
+    let file_path = file_path.as_ref();
     let file = OpenOptions::new()
         .create(true)
+        .truncate(false)
         .read(true)
         .write(true)
         .open(file_path)
-        .expect("open record.bin failed.");
+        .map_err(StorageError::io(file_path))?;
     if let Some(size) = size {
-        file.set_len(size as u64).expect("Resize file failed.");
+        file.set_len(size as u64)
+            .map_err(StorageError::io(file_path))?;
     }
-    unsafe { MmapMut::map_mut(&file).expect("Error mapping record.bin file") }
+    unsafe { MmapMut::map_mut(&file) }.map_err(StorageError::io(file_path))
 }
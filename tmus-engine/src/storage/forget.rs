@@ -0,0 +1,168 @@
+//! Erase an app and its whole history, for privacy.
+
+use crate::models::AppId;
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::replace_file;
+use crate::storage::rewrite::{read_record_file, record_content};
+use std::fs;
+use std::path::PathBuf;
+use tracing::info;
+
+#[derive(Debug)]
+pub struct ForgetReport {
+    /// The app and the apps merged into it.
+    pub apps: Vec<AppId>,
+    /// Count of removed records, including archived records.
+    pub records: usize,
+    /// Removed backups, see [`Storage::backups`].
+    pub backups: Vec<PathBuf>,
+}
+
+impl Storage {
+    /// Remove the records of the app from the segments and archive.bin, and replace its
+    /// registration in app.jsonl by a tombstone, so the path is erased and the id is never reused.
+    /// Apps merged into it are forgotten too.
+    ///
+    /// The app is registered again with a new id if it's tracked later, exclude it by rule to
+    /// avoid that.
+    ///
+    /// Backups in the data directory may keep the history, they're removed at last if
+    /// `remove_backups`, otherwise it fails with [`StorageError::BackupsExist`] before anything
+    /// is changed.
+    pub fn forget_app(&self, id: AppId, remove_backups: bool) -> Result<ForgetReport> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let info = self.app.get_app_info(id)?;
+        let backups = self.backups()?;
+        if !backups.is_empty() && !remove_backups {
+            return Err(StorageError::BackupsExist(backups.len()));
+        }
+        let apps: Vec<AppId> = self
+            .app
+            .get_all_app_info()
+            .into_iter()
+            .filter(|x| x.id == info.id || self.app.is_merged_into(x.id, info.id))
+            .map(|x| x.id)
+            .collect();
+        // Records are removed before the registration, so forgetting again works if it fails.
+        let mut count = 0;
+        self.rewrite_records(|records| {
+            let kept: Vec<_> = records
+                .iter()
+                .filter(|x| !apps.contains(&x.id))
+                .copied()
+                .collect();
+            count = records.len() - kept.len();
+            (count > 0).then_some(kept)
+        })?;
+        let archive_path = self.data_dir.join("archive.bin");
+        if archive_path.exists() {
+            let (_, archive) = read_record_file(&archive_path)?;
+            let kept: Vec<_> = archive
+                .iter()
+                .filter(|x| !apps.contains(&x.id))
+                .copied()
+                .collect();
+            if kept.len() < archive.len() {
+                count += archive.len() - kept.len();
+                replace_file(&archive_path, &record_content(&kept))?;
+            }
+        }
+        let apps = self.app.forget(id)?;
+        for path in &backups {
+            if path.is_dir() {
+                fs::remove_dir_all(path)
+            } else {
+                fs::remove_file(path)
+            }
+            .map_err(StorageError::io(path))?;
+        }
+        info!(
+            "Forget apps {:?}, {} records and {} backups removed",
+            apps,
+            count,
+            backups.len()
+        );
+        Ok(ForgetReport {
+            apps,
+            records: count,
+            backups,
+        })
+    }
+
+    /// Copies in `backup` of the data directory, taken before migrations, repairs and
+    /// restores, and snapshot archives in it or in the data directory, like a staged restore.
+    pub fn backups(&self) -> Result<Vec<PathBuf>> {
+        let mut ret = Vec::new();
+        let backup_dir = self.data_dir.join("backup");
+        for dir in [&backup_dir, &self.data_dir] {
+            let entries = match fs::read_dir(dir) {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(StorageError::io(dir)(e)),
+            };
+            for entry in entries {
+                let path = entry.map_err(StorageError::io(dir))?.path();
+                let snapshot = path.extension().is_some_and(|x| x == "snapshot");
+                if snapshot || (dir == &backup_dir && path.is_dir()) {
+                    ret.push(path);
+                }
+            }
+        }
+        ret.sort();
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, StorageError, migration};
+    use crate::util::now_timestamp;
+    use std::fs;
+
+    #[test]
+    fn test_forget_app() {
+        let dir = temp_data_dir("forget-app");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        let paths = ["C:\\a.exe", "C:\\b.exe", "C:\\c.exe"];
+        for (i, path) in paths.iter().enumerate() {
+            let start = now - 10000 + i as i64 * 2000;
+            storage.write_record(path, start, start + 1000).unwrap();
+        }
+        storage.merge_apps(&[2], 0).unwrap();
+        let backup_dir = migration::backup(&dir, "test").unwrap();
+        fs::write(dir.join("restore.snapshot"), b"").unwrap();
+
+        // Backups are only removed with the confirmation, nothing is forgotten without it.
+        assert!(matches!(
+            storage.forget_app(0, false),
+            Err(StorageError::BackupsExist(2))
+        ));
+        assert_eq!(storage.read_by_timestamp(0, now).len(), 3);
+        let report = storage.forget_app(0, true).unwrap();
+        assert_eq!((report.apps, report.records), (vec![0, 2], 2));
+        assert_eq!(report.backups, [backup_dir, dir.join("restore.snapshot")]);
+        assert!(storage.backups().unwrap().is_empty());
+        let ids: Vec<_> = storage
+            .read_by_timestamp(0, now)
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![1]);
+        assert!(matches!(
+            storage.forget_app(0, false),
+            Err(StorageError::AppNotFound(0))
+        ));
+        drop(storage);
+
+        let text = fs::read_to_string(dir.join("app.jsonl")).unwrap();
+        assert!(!text.contains("a.exe") && !text.contains("c.exe"));
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.get_all_app(), vec!["", "C:\\b.exe", ""]);
+        assert_eq!(storage.get_id_by_path(paths[0]).unwrap(), 3);
+    }
+}
@@ -0,0 +1,86 @@
+use crate::storage::error::{Result, StorageError};
+use std::path::Path;
+
+/// The format version written by this engine.
+///
+/// - 1: Add headers.
+/// - 2: 16 bytes records with millisecond precision, see [`crate::models::FocusRecord`].
+/// - 3: Records split into monthly segments, see [`crate::storage::segment`].
+/// - 4: app.txt replaced by app.jsonl, see [`crate::storage::focus_app::FocusAppFile`].
+/// - 5: Records tagged with the device, see [`crate::storage::device`].
+pub const FORMAT_VERSION: u32 = 5;
+/// The oldest format version which can be read without migration.
+pub const MIN_FORMAT_VERSION: u32 = 1;
+
+pub(crate) const HEADER_SIZE: usize = 16;
+pub(crate) const RECORD_MAGIC: [u8; 4] = *b"TMRC";
+pub(crate) const INDEX_MAGIC: [u8; 4] = *b"TMIX";
+/// The first line of app.jsonl and app.txt, followed by the format version.
+pub(crate) const APP_HEADER_PREFIX: &str = "#tmus-app:";
+
+/// The 16 bytes header of binary storage files.
+///
+/// | magic: 4 bytes | version: u32 | extra: u64 |
+///
+/// `extra` is the base day for index.bin, and the committed record count for record files.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct FileHeader {
+    pub magic: [u8; 4],
+    pub version: u32,
+    pub extra: u64,
+}
+
+impl FileHeader {
+    pub fn new(magic: [u8; 4], extra: u64) -> Self {
+        Self {
+            magic,
+            version: FORMAT_VERSION,
+            extra,
+        }
+    }
+
+    pub fn to_bytes(self) -> [u8; HEADER_SIZE] {
+        let mut buf = [0; HEADER_SIZE];
+        buf[0..4].copy_from_slice(&self.magic);
+        buf[4..8].copy_from_slice(&self.version.to_le_bytes());
+        buf[8..16].copy_from_slice(&self.extra.to_le_bytes());
+        buf
+    }
+
+    /// Returns `None` if the bytes don't start with the magic, which means the file is
+    /// written before headers were introduced (format version 0).
+    pub fn parse(magic: [u8; 4], bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < HEADER_SIZE || bytes[0..4] != magic {
+            return None;
+        }
+        Some(Self {
+            magic,
+            version: u32::from_le_bytes(bytes[4..8].try_into().unwrap()),
+            extra: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        })
+    }
+
+    pub fn check_version(&self, path: &Path) -> Result<()> {
+        check_version(path, self.version)
+    }
+}
+
+/// Files without headers (version 0) or written by a newer engine can't be read.
+pub(crate) fn check_version(path: &Path, version: u32) -> Result<()> {
+    if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+        return Err(StorageError::UnsupportedVersion {
+            path: path.to_path_buf(),
+            version,
+        });
+    }
+    Ok(())
+}
+
+pub(crate) fn app_header_line() -> String {
+    format!("{}{}\n", APP_HEADER_PREFIX, FORMAT_VERSION)
+}
+
+/// Returns the version in the header line of app.jsonl or app.txt, `None` if there is no header line.
+pub(crate) fn parse_app_header(line: &str) -> Option<u32> {
+    line.strip_prefix(APP_HEADER_PREFIX)?.trim().parse().ok()
+}
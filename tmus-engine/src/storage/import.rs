@@ -0,0 +1,226 @@
+//! Import history recorded by other trackers, as spans of app paths.
+//!
+//! The imported time never overlaps the existing records of the same device, spans are trimmed to
+//! the time which isn't recorded yet, so importing the same history twice doesn't count it twice.
+//! Devices are used at the same time, so the time of other devices is kept.
+
+use crate::models::{DeviceId, FocusRecord, LOCAL_DEVICE};
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::util::{Timestamp, s_as_ms};
+use std::collections::HashMap;
+use tracing::info;
+
+/// The time an app is focused, the path should be filtered by the rules already.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportSpan {
+    pub path: String,
+    pub focus_at: Timestamp,
+    pub blur_at: Timestamp,
+    pub device: DeviceId,
+}
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ImportReport {
+    /// Spans written, or to be written by a dry run, including trimmed ones.
+    pub imported: usize,
+    /// Spans written partly, as some of their time is recorded already.
+    pub trimmed: usize,
+    /// Spans skipped, as their time is recorded already or they're shorter than a second.
+    pub skipped: usize,
+    /// Count of written records, spans across days are split.
+    pub records: usize,
+}
+
+impl Storage {
+    /// Write the spans as records, registering their apps if new.
+    /// A dry run only reports what would be imported, which works with a read-only storage.
+    pub fn import_spans(&self, spans: &[ImportSpan], dry_run: bool) -> Result<ImportReport> {
+        if self.read_only && !dry_run {
+            return Err(StorageError::ReadOnly);
+        }
+        let mut spans: Vec<&ImportSpan> = spans.iter().filter(|x| !x.path.is_empty()).collect();
+        spans.sort_by_key(|x| (x.focus_at, x.blur_at));
+        let mut report = ImportReport {
+            skipped: spans.len(),
+            ..Default::default()
+        };
+        let (Some(first), Some(last_blur_at)) =
+            (spans.first(), spans.iter().map(|x| x.blur_at).max())
+        else {
+            return Ok(report);
+        };
+        let records = self.read_by_timestamp(first.focus_at, last_blur_at);
+        let mut devices: HashMap<DeviceId, DeviceState> = HashMap::new();
+
+        let mut parts: Vec<(&ImportSpan, Timestamp, Timestamp)> = Vec::new();
+        for span in spans {
+            let state = devices.entry(span.device).or_insert_with(|| DeviceState {
+                covered: covered_ranges(records.iter().filter(|x| x.device == span.device)),
+                next: 0,
+                imported_until: Timestamp::MIN,
+            });
+            let start = span.focus_at.max(state.imported_until);
+            state.imported_until = state.imported_until.max(span.blur_at);
+            while state.covered.get(state.next).is_some_and(|x| x.1 <= start) {
+                state.next += 1;
+            }
+            let mut span_parts = Vec::new();
+            let mut cursor = start;
+            for &(covered_start, covered_end) in state.covered[state.next..]
+                .iter()
+                .take_while(|x| x.0 < span.blur_at)
+            {
+                if covered_start > cursor {
+                    span_parts.push((cursor, covered_start));
+                }
+                cursor = cursor.max(covered_end);
+            }
+            if cursor < span.blur_at {
+                span_parts.push((cursor, span.blur_at));
+            }
+            span_parts.retain(|x| x.1 - x.0 >= s_as_ms(1));
+            if span_parts.is_empty() {
+                continue;
+            }
+            report.skipped -= 1;
+            report.imported += 1;
+            if span_parts != [(span.focus_at, span.blur_at)] {
+                report.trimmed += 1;
+            }
+            parts.extend(
+                span_parts
+                    .into_iter()
+                    .map(|(focus_at, blur_at)| (span, focus_at, blur_at)),
+            );
+        }
+
+        if dry_run {
+            report.records = parts
+                .iter()
+                .map(|&(_, focus_at, blur_at)| {
+                    FocusRecord {
+                        id: 0,
+                        focus_at,
+                        blur_at,
+                        device: LOCAL_DEVICE,
+                    }
+                    .split_record()
+                    .len()
+                })
+                .sum();
+            return Ok(report);
+        }
+
+        let mut app_ranges: HashMap<&str, (Timestamp, Timestamp)> = HashMap::new();
+        for &(span, focus_at, blur_at) in &parts {
+            let range = app_ranges.entry(&span.path).or_insert((focus_at, blur_at));
+            range.0 = range.0.min(focus_at);
+            range.1 = range.1.max(blur_at);
+        }
+        // New apps are registered in the order they're first seen.
+        let mut ids = HashMap::new();
+        for &(span, _, _) in &parts {
+            let path = span.path.as_str();
+            if !ids.contains_key(path) {
+                let (focus_at, blur_at) = app_ranges[path];
+                ids.insert(path, self.app.touch(path, focus_at, blur_at)?);
+            }
+        }
+        let records: Vec<FocusRecord> = parts
+            .into_iter()
+            .map(|(span, focus_at, blur_at)| FocusRecord {
+                id: ids[span.path.as_str()],
+                focus_at,
+                blur_at,
+                device: span.device,
+            })
+            .collect();
+        report.records = self.write_records_batch(&records)?;
+        info!("Import spans {:?}", report);
+        Ok(report)
+    }
+}
+
+/// Spans of each device are trimmed separately, by the records and spans of the device.
+struct DeviceState {
+    /// Time ranges covered by the records.
+    covered: Vec<(Timestamp, Timestamp)>,
+    /// The first covered range which may overlap the next span.
+    next: usize,
+    /// The end of the spans imported so far.
+    imported_until: Timestamp,
+}
+
+/// The time ranges covered by the records, merged and in time order.
+fn covered_ranges<'a>(
+    records: impl Iterator<Item = &'a FocusRecord>,
+) -> Vec<(Timestamp, Timestamp)> {
+    let mut ranges: Vec<(Timestamp, Timestamp)> =
+        records.map(|x| (x.focus_at, x.blur_at)).collect();
+    ranges.sort_unstable();
+    let mut merged: Vec<(Timestamp, Timestamp)> = Vec::with_capacity(ranges.len());
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::{record, temp_data_dir};
+    use crate::storage::verify;
+    use crate::util::{d_as_ms, m_as_ms, now_day};
+
+    #[test]
+    fn test_import_spans() {
+        let dir = temp_data_dir("import");
+        let base = d_as_ms(now_day() - 10);
+        let at = |minutes| base + m_as_ms(minutes);
+        let storage = Storage::open(&dir).unwrap();
+        storage.write_record("C:\\a.exe", at(10), at(20)).unwrap();
+        let span = |path: &str, focus_at, blur_at| ImportSpan {
+            path: path.to_string(),
+            focus_at: at(focus_at),
+            blur_at: at(blur_at),
+            device: LOCAL_DEVICE,
+        };
+
+        // Spans are trimmed to the time which isn't recorded or imported by earlier spans.
+        let spans = [
+            span("C:\\c.exe", 25, 35),
+            span("C:\\b.exe", 5, 15),
+            span("C:\\b.exe", 12, 18),
+            span("C:\\c.exe", 20, 30),
+        ];
+        let report = storage.import_spans(&spans, true).unwrap();
+        assert_eq!(
+            report,
+            ImportReport {
+                imported: 3,
+                trimmed: 2,
+                skipped: 1,
+                records: 3,
+            }
+        );
+        assert_eq!(storage.read_by_timestamp(base, base + d_as_ms(1)).len(), 1);
+        assert_eq!(storage.import_spans(&spans, false).unwrap(), report);
+        assert_eq!(storage.import_spans(&spans, false).unwrap().skipped, 4);
+        assert_eq!(
+            storage.read_by_timestamp(base, base + d_as_ms(1)),
+            [
+                record(1, at(5), at(10)),
+                record(0, at(10), at(20)),
+                record(2, at(20), at(30)),
+                record(2, at(30), at(35)),
+            ]
+        );
+        let app = storage.get_app_info(1).unwrap();
+        assert_eq!((app.first_seen, app.last_seen), (at(5), at(10)));
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+}
@@ -0,0 +1,150 @@
+//! Merge the history of apps into another app, for apps which are tracked as different paths,
+//! like the versioned install directories of one app.
+
+use crate::models::AppId;
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::replace_file;
+use crate::storage::rewrite::{read_record_file, record_content};
+use std::collections::HashSet;
+use tracing::info;
+
+#[derive(Debug)]
+pub struct MergeReport {
+    /// The app which is merged into, after following earlier merges.
+    pub target: AppId,
+    /// Count of records moved to the target, including archived records.
+    pub records: usize,
+}
+
+impl Storage {
+    /// Move the records of `sources` in the segments and archive.bin to `target`, and map the paths of `sources` to `target`
+    /// in the app registry, so new records of the paths go to `target` too.
+    /// The ids of the sources stay registered, and the merge can't be undone.
+    pub fn merge_apps(&self, sources: &[AppId], target: AppId) -> Result<MergeReport> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        // Register the merge first, so records written meanwhile already go to the target.
+        let target = self.app.merge(sources, target)?;
+        let sources: HashSet<AppId> = sources.iter().copied().collect();
+        let mut count = 0;
+        self.rewrite_records(|records| {
+            let records: Vec<_> = records
+                .iter()
+                .map(|x| {
+                    let mut x = *x;
+                    if sources.contains(&x.id) {
+                        x.id = target;
+                        count += 1;
+                    }
+                    x
+                })
+                .collect();
+            (count > 0).then_some(records)
+        })?;
+        let archive_path = self.data_dir.join("archive.bin");
+        if archive_path.exists() {
+            let (_, mut archive) = read_record_file(&archive_path)?;
+            let moved = archive
+                .iter_mut()
+                .filter(|x| sources.contains(&x.id))
+                .map(|x| x.id = target)
+                .count();
+            if moved > 0 {
+                count += moved;
+                replace_file(&archive_path, &record_content(&archive))?;
+            }
+        }
+        info!(
+            "Merge apps {:?} into {}, {} records moved",
+            sources, target, count
+        );
+        Ok(MergeReport {
+            target,
+            records: count,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::retention::{RetentionMode, read_archive};
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, StorageError};
+    use crate::util::{d_as_ms, ms_as_d, now_timestamp};
+    use std::fs;
+
+    #[test]
+    fn test_merge_apps() {
+        let dir = temp_data_dir("merge-apps");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        let paths = ["C:\\app-1.0\\a.exe", "C:\\app-1.1\\a.exe", "C:\\b.exe"];
+        for (i, path) in paths.iter().enumerate() {
+            let start = now - 10000 + i as i64 * 2000;
+            storage.write_record(path, start, start + 1000).unwrap();
+        }
+        assert!(matches!(
+            storage.merge_apps(&[1], 1),
+            Err(StorageError::MergeIntoSelf(1))
+        ));
+
+        let report = storage.merge_apps(&[0], 1).unwrap();
+        assert_eq!((report.target, report.records), (1, 1));
+        let ids: Vec<_> = storage
+            .read_by_timestamp(0, now)
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![1, 1, 2]);
+        let app = storage.get_app_info(1).unwrap();
+        assert_eq!((app.first_seen, app.last_seen), (now - 10000, now - 7000));
+        drop(storage);
+
+        // The merge is kept after reopened, and follows a later merge of the target.
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(storage.get_id_by_path(paths[0]).unwrap(), 1);
+        assert_eq!(storage.merge_apps(&[1], 2).unwrap().records, 2);
+        assert_eq!(
+            storage.merge_apps(&[2], 0).unwrap_err().to_string(),
+            "app id 2 can't be merged into itself"
+        );
+        storage
+            .write_record(paths[0], now - 3000, now - 2000)
+            .unwrap();
+        let ids: Vec<_> = storage
+            .read_by_timestamp(0, now)
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![2, 2, 2, 2]);
+    }
+
+    #[test]
+    fn test_merge_archived_apps() {
+        let dir = temp_data_dir("merge-archived");
+        let day = ms_as_d(now_timestamp()) - 100;
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("index.bin"), (day as u64).to_le_bytes()).unwrap();
+        let storage = Storage::open(&dir).unwrap();
+        for (i, path) in ["C:\\a.exe", "C:\\b.exe", "C:\\a.exe"].iter().enumerate() {
+            let start = d_as_ms(day + i as i64);
+            storage.write_record(path, start, start + 1000).unwrap();
+        }
+        storage
+            .apply_retention(d_as_ms(day + 2), RetentionMode::Archive)
+            .unwrap();
+
+        // Archived records are moved too, records of later days stay in the segments.
+        assert_eq!(storage.merge_apps(&[0], 1).unwrap().records, 2);
+        let ids: Vec<_> = read_archive(&dir).unwrap().iter().map(|x| x.id).collect();
+        assert_eq!(ids, vec![1, 1]);
+        let ids: Vec<_> = storage
+            .read_by_timestamp(0, now_timestamp())
+            .iter()
+            .map(|x| x.id)
+            .collect();
+        assert_eq!(ids, vec![1]);
+    }
+}
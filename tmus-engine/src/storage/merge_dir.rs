@@ -0,0 +1,215 @@
+//! Merge the history of another data directory, like the one of a second machine.
+
+use crate::models::{DeviceId, LOCAL_DEVICE};
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::import::{ImportReport, ImportSpan};
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+use tracing::{info, warn};
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct MergeDirReport {
+    /// Apps of the other directory which have records.
+    pub apps: usize,
+    /// Apps whose path is changed by `map_path`.
+    pub mapped: usize,
+    pub import: ImportReport,
+}
+
+impl Storage {
+    /// Import the records of another data directory, which is opened read-only, so it can be
+    /// a copy or the directory of a running tmus. It must be of the current format version.
+    ///
+    /// Apps are matched by path after `map_path`, which can reconcile an app installed in
+    /// different directories. Time which is recorded already of the same device is skipped as
+    /// [`Self::import_spans`], so merging the same directory again writes nothing.
+    ///
+    /// The local records of the other directory are tagged with `device`, or of the local
+    /// device if none. Records of other devices keep their device names, devices missing from
+    /// its device.json are registered as `unknown-device-<id>`.
+    pub fn merge_data_dir(
+        &self,
+        other_dir: impl AsRef<Path>,
+        device: Option<&str>,
+        map_path: impl Fn(&str) -> String,
+        dry_run: bool,
+    ) -> Result<MergeDirReport> {
+        if self.read_only && !dry_run {
+            return Err(StorageError::ReadOnly);
+        }
+        let other = Storage::open_read_only(other_dir)?;
+        let records = {
+            let files = other.files.read().unwrap();
+            let files = files.as_ref().ok_or(StorageError::Closed)?;
+            files.segments.read(None, None)?
+        };
+        let other_apps = other.get_all_app();
+        let other_devices = other.devices()?;
+
+        let mut report = MergeDirReport::default();
+        let mut paths: HashMap<usize, String> = HashMap::new();
+        let mut source_devices = BTreeSet::new();
+        let mut spans = Vec::with_capacity(records.len());
+        for record in records {
+            let Some(path) = other_apps.get(record.id).filter(|x| !x.is_empty()) else {
+                continue;
+            };
+            let path = paths.entry(record.id).or_insert_with(|| {
+                let mapped = map_path(path);
+                report.apps += 1;
+                if mapped != *path {
+                    report.mapped += 1;
+                }
+                mapped
+            });
+            source_devices.insert(record.device);
+            spans.push(ImportSpan {
+                path: path.clone(),
+                focus_at: record.focus_at,
+                blur_at: record.blur_at,
+                device: record.device,
+            });
+        }
+
+        // Devices of the other directory, which are registered at once with their names.
+        let mut names = Vec::new();
+        let mut devices: HashMap<DeviceId, DeviceId> = HashMap::new();
+        for &id in &source_devices {
+            let name = match (id, device) {
+                (LOCAL_DEVICE, None) => {
+                    devices.insert(id, LOCAL_DEVICE);
+                    continue;
+                }
+                (LOCAL_DEVICE, Some(name)) => name.to_string(),
+                (id, _) => other_devices.get(id as usize).cloned().unwrap_or_else(|| {
+                    warn!("Device {} isn't in device.json of the other directory", id);
+                    format!("unknown-device-{}", id)
+                }),
+            };
+            names.push((id, name));
+        }
+        let name_refs: Vec<&str> = names.iter().map(|x| x.1.as_str()).collect();
+        let ids = self.register_devices(&name_refs, dry_run)?;
+        devices.extend(names.iter().map(|x| x.0).zip(ids));
+        for span in &mut spans {
+            span.device = devices[&span.device];
+        }
+        report.import = self.import_spans(&spans, dry_run)?;
+        info!("Merge data directory {:?}", report);
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::models::FocusRecord;
+    use crate::storage::import::ImportSpan;
+    use crate::storage::test_util::{record, temp_data_dir};
+    use crate::storage::{Storage, verify};
+    use crate::util::{d_as_ms, m_as_ms, now_day};
+
+    #[test]
+    fn test_merge_data_dir() {
+        let dir = temp_data_dir("merge_dir");
+        let other_dir = temp_data_dir("merge_dir_other");
+        let base = d_as_ms(now_day() - 10);
+        let at = |minutes| base + m_as_ms(minutes);
+        let storage = Storage::open(&dir).unwrap();
+        storage.write_record("C:\\a.exe", at(10), at(20)).unwrap();
+        {
+            let other = Storage::open(&other_dir).unwrap();
+            other.write_record("D:\\a.exe", at(15), at(30)).unwrap();
+            other.write_record("D:\\b.exe", at(30), at(40)).unwrap();
+        }
+
+        let map_path = |path: &str| path.replace("D:\\a.exe", "C:\\a.exe");
+        let report = storage
+            .merge_data_dir(&other_dir, Some("laptop"), map_path, true)
+            .unwrap();
+        assert_eq!((report.apps, report.mapped), (2, 1));
+        assert_eq!((report.import.imported, report.import.trimmed), (2, 0));
+        assert_eq!(storage.devices().unwrap(), ["local"]);
+        assert_eq!(
+            storage
+                .merge_data_dir(&other_dir, Some("laptop"), map_path, false)
+                .unwrap(),
+            report
+        );
+        assert_eq!(storage.devices().unwrap(), ["local", "laptop"]);
+        assert_eq!(
+            storage.read_by_timestamp(base, base + d_as_ms(1)),
+            [
+                record(0, at(10), at(20)),
+                FocusRecord {
+                    device: 1,
+                    ..record(0, at(15), at(30))
+                },
+                FocusRecord {
+                    device: 1,
+                    ..record(1, at(30), at(40))
+                },
+            ]
+        );
+        // Records counted as local are trimmed by the local ones.
+        let report = storage
+            .merge_data_dir(&other_dir, None, map_path, true)
+            .unwrap();
+        assert_eq!((report.import.imported, report.import.trimmed), (2, 1));
+        // Merging again writes nothing.
+        let report = storage
+            .merge_data_dir(&other_dir, Some("laptop"), map_path, false)
+            .unwrap();
+        assert_eq!(report.import.skipped, 2);
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_merge_devices() {
+        let dir = temp_data_dir("merge_devices");
+        let other_dir = temp_data_dir("merge_devices_other");
+        let base = d_as_ms(now_day() - 10);
+        let span = |device| ImportSpan {
+            path: "C:\\a.exe".to_string(),
+            focus_at: base,
+            blur_at: base + m_as_ms(10),
+            device,
+        };
+        {
+            let other = Storage::open(&other_dir).unwrap();
+            assert_eq!(
+                other
+                    .register_devices(&["desktop", "tablet"], false)
+                    .unwrap(),
+                [1, 2]
+            );
+            // Device 5 isn't registered, like device.json is lost.
+            other
+                .import_spans(&[span(1), span(2), span(5)], false)
+                .unwrap();
+        }
+        let storage = Storage::open(&dir).unwrap();
+
+        // New devices of a dry run get distinct ids, so their records aren't trimmed.
+        let report = storage
+            .merge_data_dir(&other_dir, None, str::to_string, true)
+            .unwrap();
+        assert_eq!((report.import.imported, report.import.trimmed), (3, 0));
+        assert_eq!(storage.devices().unwrap(), ["local"]);
+        let report = storage
+            .merge_data_dir(&other_dir, None, str::to_string, false)
+            .unwrap();
+        assert_eq!(report.import.imported, 3);
+        assert_eq!(
+            storage.devices().unwrap(),
+            ["local", "desktop", "tablet", "unknown-device-5"]
+        );
+        let devices: Vec<_> = storage
+            .read_by_timestamp(base, base + d_as_ms(1))
+            .iter()
+            .map(|x| x.device)
+            .collect();
+        assert_eq!(devices, [1, 2, 3]);
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+}
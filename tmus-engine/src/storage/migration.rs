@@ -0,0 +1,356 @@
+//! Upgrade the files of a data directory to [`FORMAT_VERSION`] in place.
+//!
+//! The version of a data directory is the version in the header of index.bin, files written
+//! before headers were introduced are version 0. Every step upgrades one version, and index.bin
+//! is always the last file to be rewritten, so an interrupted migration runs again on next open.
+//! Each step must skip files which are already upgraded.
+
+use crate::models::{AppId, AppInfo};
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{read_or_default, replace_file};
+use crate::storage::focus_app::{AppEntry, read_apps};
+use crate::storage::focus_record::{decode_records, find_really_len, record_size};
+use crate::storage::header::{
+    APP_HEADER_PREFIX, FORMAT_VERSION, FileHeader, HEADER_SIZE, INDEX_MAGIC, RECORD_MAGIC,
+    parse_app_header,
+};
+use crate::storage::rewrite::{index_content, read_record_file, read_records};
+use crate::storage::segment::{RECORD_DIR, SegmentFile, segment_files, split_segments};
+use crate::util::{Timestamp, now_day, now_timestamp};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Files of a data directory, in the order they are migrated.
+const DATA_FILES: [&str; 6] = [
+    "app.txt",
+    "app.jsonl",
+    "device.json",
+    "record.bin",
+    "index.bin",
+    "archive.bin",
+];
+
+struct Migration {
+    from: u32,
+    run: fn(&Path) -> Result<()>,
+}
+
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        from: 0,
+        run: v0_to_v1,
+    },
+    Migration {
+        from: 1,
+        run: v1_to_v2,
+    },
+    Migration {
+        from: 2,
+        run: v2_to_v3,
+    },
+    Migration {
+        from: 3,
+        run: v3_to_v4,
+    },
+    Migration {
+        from: 4,
+        run: v4_to_v5,
+    },
+];
+
+/// Returns `None` if the data directory hasn't been written yet.
+pub fn data_version(data_dir: impl AsRef<Path>) -> Result<Option<u32>> {
+    let path = data_dir.as_ref().join("index.bin");
+    let buf = match fs::read(&path) {
+        Ok(buf) => buf,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(StorageError::io(&path)(e)),
+    };
+    if buf.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(
+        FileHeader::parse(INDEX_MAGIC, &buf).map_or(0, |header| header.version),
+    ))
+}
+
+/// Upgrade the data directory to [`FORMAT_VERSION`].
+/// The files are copied to `backup/v{version}-{timestamp}` in the data directory first.
+pub fn migrate(data_dir: impl AsRef<Path>) -> Result<()> {
+    let data_dir = data_dir.as_ref();
+    let Some(mut version) = data_version(data_dir)? else {
+        return Ok(());
+    };
+    if version > FORMAT_VERSION {
+        return Err(StorageError::UnsupportedVersion {
+            path: data_dir.join("index.bin"),
+            version,
+        });
+    }
+    if version == FORMAT_VERSION {
+        return Ok(());
+    }
+    let backup_dir = backup(data_dir, &format!("v{}", version))?;
+    info!(
+        "Migrating {:?} from format version {} to {}, backup in {:?}",
+        data_dir, version, FORMAT_VERSION, backup_dir
+    );
+    while version < FORMAT_VERSION {
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.from == version)
+            .expect("migration step missing");
+        (migration.run)(data_dir)?;
+        version += 1;
+    }
+    Ok(())
+}
+
+/// Copy the data files and the segments to `backup/{name}-{timestamp}` in the data directory.
+pub(crate) fn backup(data_dir: &Path, name: &str) -> Result<PathBuf> {
+    let backup_dir = data_dir
+        .join("backup")
+        .join(format!("{}-{}", name, now_timestamp()));
+    fs::create_dir_all(&backup_dir).map_err(StorageError::io(&backup_dir))?;
+    for name in DATA_FILES {
+        let path = data_dir.join(name);
+        if path.is_file() {
+            fs::copy(&path, backup_dir.join(name)).map_err(StorageError::io(&path))?;
+        }
+    }
+    let segments = segment_files(data_dir)?;
+    if !segments.is_empty() {
+        let record_dir = backup_dir.join(RECORD_DIR);
+        fs::create_dir_all(&record_dir).map_err(StorageError::io(&record_dir))?;
+        for file in segments {
+            fs::copy(&file.path, record_dir.join(file.name()))
+                .map_err(StorageError::io(&file.path))?;
+        }
+    }
+    Ok(backup_dir)
+}
+
+/// Add headers. The base day at the start of index.bin moves into its header.
+/// Index of version 0 points one past the first record of the day, so it's rebuilt from the records.
+fn v0_to_v1(data_dir: &Path) -> Result<()> {
+    let path = data_dir.join("app.txt");
+    let buf = read_or_default(&path)?;
+    let text = String::from_utf8_lossy(&buf);
+    if text.lines().next().and_then(parse_app_header).is_none() {
+        replace_file(&path, format!("{}{}", header_line(1), text).as_bytes())?;
+    }
+
+    let path = data_dir.join("record.bin");
+    let buf = read_or_default(&path)?;
+    if FileHeader::parse(RECORD_MAGIC, &buf).is_none() {
+        let mut content = header(RECORD_MAGIC, 1, 0);
+        content.extend_from_slice(&buf[..buf.len() / 8 * 8]);
+        replace_file(&path, &content)?;
+    }
+
+    let path = data_dir.join("index.bin");
+    let buf = read_or_default(&path)?;
+    if FileHeader::parse(INDEX_MAGIC, &buf).is_none() {
+        let base_day = buf.get(..8).map_or(now_day() as u64, |x| {
+            u64::from_le_bytes(x.try_into().unwrap())
+        });
+        let (_, records) = read_record_file(&data_dir.join("record.bin"))?;
+        replace_file(&path, &index_content(1, base_day, &records))?;
+    }
+    Ok(())
+}
+
+/// Widen records to 16 bytes and count them in the header, app.txt and index.bin only bump
+/// the version.
+fn v1_to_v2(data_dir: &Path) -> Result<()> {
+    bump_app_header(data_dir, 2)?;
+
+    let path = data_dir.join("record.bin");
+    let buf = read_or_default(&path)?;
+    if let Some(old) = FileHeader::parse(RECORD_MAGIC, &buf)
+        && old.version == 1
+    {
+        let body = &buf[HEADER_SIZE..];
+        let body = &body[..find_really_len(body, record_size(1))];
+        let records = decode_records(body, 1);
+        let mut content = header(RECORD_MAGIC, 2, records.len() as u64);
+        for record in records {
+            content.extend_from_slice(&record.to_byte());
+        }
+        replace_file(&path, &content)?;
+    }
+
+    bump_index_header(data_dir, 2)
+}
+
+/// Split record.bin into monthly segments, app.txt and index.bin only bump the version.
+/// Positions of records don't change, so index.bin still points to them.
+fn v2_to_v3(data_dir: &Path) -> Result<()> {
+    bump_app_header(data_dir, 3)?;
+
+    let path = data_dir.join("record.bin");
+    if path.exists() {
+        let (_, records) = read_record_file(&path)?;
+        let record_dir = data_dir.join(RECORD_DIR);
+        fs::create_dir_all(&record_dir).map_err(StorageError::io(&record_dir))?;
+        for (id, records) in split_segments(&records) {
+            let mut content = header(RECORD_MAGIC, 3, records.len() as u64);
+            for record in records {
+                content.extend_from_slice(&record.to_byte());
+            }
+            replace_file(SegmentFile::new(data_dir, id, false).path, &content)?;
+        }
+        fs::remove_file(&path).map_err(StorageError::io(&path))?;
+    }
+
+    bump_index_header(data_dir, 3)
+}
+
+/// Replace app.txt by app.jsonl, the first and last seen time of apps are found in the records.
+/// The layout of record files doesn't change, index.bin only bumps the version.
+fn v3_to_v4(data_dir: &Path) -> Result<()> {
+    let path = data_dir.join("app.txt");
+    if path.exists() {
+        let text = String::from_utf8_lossy(&read_or_default(&path)?).into_owned();
+        let (_, records) = read_records(data_dir)?;
+        let mut seen: HashMap<AppId, (Timestamp, Timestamp)> = HashMap::new();
+        for record in records {
+            let (first_seen, last_seen) = seen
+                .entry(record.id)
+                .or_insert((record.focus_at, record.blur_at));
+            *first_seen = (*first_seen).min(record.focus_at);
+            *last_seen = (*last_seen).max(record.blur_at);
+        }
+        let mut content = header_line(4);
+        for (id, path) in read_apps(&text).into_iter().enumerate() {
+            let (first_seen, last_seen) = seen.get(&id).copied().unwrap_or_default();
+            content.push_str(
+                &AppEntry::register(&AppInfo {
+                    id,
+                    path,
+                    first_seen,
+                    last_seen,
+                    merged_into: None,
+                })
+                .to_line(),
+            );
+        }
+        replace_file(data_dir.join("app.jsonl"), content.as_bytes())?;
+        fs::remove_file(&path).map_err(StorageError::io(&path))?;
+    }
+
+    bump_index_header(data_dir, 4)
+}
+
+/// Records of the local device keep their bytes, since its device id is 0, index.bin only bumps
+/// the version so older engines don't read the device as part of the app id.
+fn v4_to_v5(data_dir: &Path) -> Result<()> {
+    bump_index_header(data_dir, 5)
+}
+
+/// Set the version of app.txt if it's the previous version.
+fn bump_app_header(data_dir: &Path, version: u32) -> Result<()> {
+    let path = data_dir.join("app.txt");
+    let text = String::from_utf8_lossy(&read_or_default(&path)?).into_owned();
+    if let Some((first_line, apps)) = text.split_once('\n')
+        && parse_app_header(first_line) == Some(version - 1)
+    {
+        replace_file(
+            &path,
+            format!("{}{}", header_line(version), apps).as_bytes(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Set the version of index.bin if it's the previous version.
+fn bump_index_header(data_dir: &Path, version: u32) -> Result<()> {
+    let path = data_dir.join("index.bin");
+    let mut buf = read_or_default(&path)?;
+    if let Some(old) = FileHeader::parse(INDEX_MAGIC, &buf)
+        && old.version == version - 1
+    {
+        buf[..HEADER_SIZE].copy_from_slice(&header(INDEX_MAGIC, version, old.extra));
+        replace_file(&path, &buf)?;
+    }
+    Ok(())
+}
+
+/// Header bytes of a specific version, which may not be the current version.
+fn header(magic: [u8; 4], version: u32, extra: u64) -> Vec<u8> {
+    FileHeader {
+        magic,
+        version,
+        extra,
+    }
+    .to_bytes()
+    .to_vec()
+}
+
+fn header_line(version: u32) -> String {
+    format!("{}{}\n", APP_HEADER_PREFIX, version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::{record, temp_data_dir};
+    use crate::storage::{Storage, verify};
+    use crate::util::{d_as_ms, m_as_ms, ms_as_s};
+
+    #[test]
+    fn test_migrate_v0() {
+        let dir = temp_data_dir("migrate-v0");
+        fs::create_dir_all(&dir).unwrap();
+        let day = 20000;
+        let at = |day, minutes| d_as_ms(day) + m_as_ms(minutes);
+        let records = [
+            record(1, at(day, 1), at(day, 2)),
+            record(0, at(day, 3), at(day, 5)),
+            record(1, at(day + 1, 1), at(day + 1, 2)),
+        ];
+        fs::write(dir.join("app.txt"), "C:\\a.exe\nC:\\b.exe\n").unwrap();
+        let mut v0_records = Vec::new();
+        for record in records {
+            v0_records.extend_from_slice(&(record.id as u16).to_le_bytes());
+            v0_records.extend_from_slice(&(ms_as_s(record.duration()) as u16).to_le_bytes());
+            v0_records.extend_from_slice(&(ms_as_s(record.focus_at) as u32).to_le_bytes());
+        }
+        fs::write(dir.join("record.bin"), v0_records).unwrap();
+        // Version 0 points one past the first record of the day.
+        let mut v0_index = (day as u64).to_le_bytes().to_vec();
+        v0_index.extend_from_slice(&3u64.to_le_bytes());
+        fs::write(dir.join("index.bin"), v0_index).unwrap();
+        assert_eq!(data_version(&dir).unwrap(), Some(0));
+
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(data_version(&dir).unwrap(), Some(FORMAT_VERSION));
+        assert_eq!(storage.get_all_app(), vec!["C:\\a.exe", "C:\\b.exe"]);
+        assert_eq!(storage.meta().initial_timestamp, d_as_ms(day));
+        assert_eq!(storage.read_by_timestamp(0, d_as_ms(day + 2)), records);
+        assert_eq!(
+            storage.read_by_timestamp(d_as_ms(day + 1), d_as_ms(day + 2)),
+            records[2..]
+        );
+        assert_eq!(fs::read_dir(dir.join("backup")).unwrap().count(), 1);
+        assert!(!dir.join("record.bin").exists());
+        assert!(dir.join("record").join("2024-10.bin").exists());
+        assert!(!dir.join("app.txt").exists());
+        let app = storage.get_app_info(1).unwrap();
+        assert_eq!(
+            (app.first_seen, app.last_seen),
+            (records[0].focus_at, records[2].blur_at)
+        );
+
+        let now = now_timestamp();
+        storage
+            .write_record("C:\\b.exe", now - 1500, now - 123)
+            .unwrap();
+        let last = *storage.read_by_timestamp(0, now).last().unwrap();
+        assert_eq!((last.focus_at, last.blur_at), (now - 1500, now - 123));
+        drop(storage);
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+}
@@ -1,72 +1,556 @@
+pub mod compact;
+pub mod device;
+pub mod edit;
+mod error;
+mod file;
 pub mod focus_app;
 pub mod focus_index;
 pub mod focus_record;
+pub mod forget;
+mod header;
+pub mod import;
+pub mod merge;
+pub mod merge_dir;
+pub mod migration;
+pub mod retention;
+mod rewrite;
+pub mod segment;
+pub mod snapshot;
+#[cfg(any(test, feature = "test-util"))]
+pub mod test_util;
+pub mod verify;
 
 use super::models;
-use crate::models::{CursorPosition, EngineMeta, FocusRecord};
-use crate::storage::focus_app::get_id_by_path;
-use crate::tracker::TrackingSpanEvent;
+use crate::models::{AppId, AppInfo, CursorPosition, EngineMeta, FocusRecord, LOCAL_DEVICE};
+use crate::storage::focus_app::FocusAppFile;
+use crate::storage::focus_index::{FileIndexRecord, FocusIndexFile};
+use crate::storage::rewrite::KeptRecords;
+use crate::storage::segment::{RecordSegments, SegmentId, SegmentRecords};
 use crate::util::{Timestamp, d_as_ms, ms_as_d, s_as_ms};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard};
+use tracing::{error, warn};
 
-const FILE_SHARE_READ: u32 = 1u32;
+pub use error::{Result, StorageError};
+pub use header::FORMAT_VERSION;
 
-pub fn init(data_dir: impl AsRef<Path>) {
-    if !data_dir.as_ref().is_dir() {
-        fs::create_dir_all(&data_dir).expect("create date directory failed.");
-    }
-    focus_app::init(&data_dir);
-    focus_index::init(&data_dir);
-    focus_record::init(&data_dir);
+static STORAGE: OnceLock<Storage> = OnceLock::new();
+
+/// All files of one data directory. Several directories can be opened in the same process.
+#[derive(Debug)]
+pub struct Storage {
+    data_dir: PathBuf,
+    app: FocusAppFile,
+    /// `None` only if reopening failed after [`Self::rewrite_records`].
+    files: RwLock<Option<RecordFiles>>,
+    read_only: bool,
 }
 
-/// Read records. Include records which blur_at >= start and focus_at <= end,
-/// which means if only need records focus_at >= start and blur_at <= end,
-/// you need to crop the return data.
-pub fn read_by_timestamp(start: Timestamp, end: Timestamp) -> Vec<FocusRecord> {
-    let start_index: CursorPosition = focus_index::query_index(ms_as_d(start) as u64);
-    let end_index = focus_index::query_index((ms_as_d(end) + 1) as u64);
-    if start_index == CursorPosition::End || end_index == CursorPosition::Start {
-        return vec![];
-    }
-    let start = match start_index {
-        CursorPosition::Start => None,
-        CursorPosition::Middle(start) => Some(start),
-        CursorPosition::End => panic!("start_index should not be CursorPosition::End"),
-    };
-    let end = match end_index {
-        CursorPosition::Start => panic!("end_index should not be CursorPosition::Start"),
-        CursorPosition::Middle(end) => Some(end),
-        CursorPosition::End => None,
-    };
-    focus_record::read(start, end)
-        .into_iter()
-        .map(|x| x.into())
-        .collect()
+/// The record segments and index.bin, which are always replaced together.
+#[derive(Debug)]
+struct RecordFiles {
+    index: FocusIndexFile,
+    segments: RecordSegments,
 }
 
-pub fn write_record(event: TrackingSpanEvent) {
-    if event.name == String::default() || event.blur_at - event.focus_at < s_as_ms(1) {
-        return;
+impl RecordFiles {
+    fn open(data_dir: &Path) -> Result<Self> {
+        if !data_dir.join("index.bin").exists() && rewrite::has_records(data_dir)? {
+            warn!("index.bin is missing, rebuild it from the records");
+            rewrite::rebuild_index_file(data_dir)?;
+        }
+        Ok(Self {
+            index: FocusIndexFile::open(data_dir)?,
+            segments: RecordSegments::open(data_dir)?,
+        })
     }
 
-    let app_id = get_id_by_path(&event.name);
-    let record = FocusRecord {
-        id: app_id,
-        focus_at: event.focus_at,
-        blur_at: event.blur_at,
-    };
+    fn open_read_only(data_dir: &Path) -> Result<Self> {
+        if !data_dir.join("index.bin").exists() && rewrite::has_records(data_dir)? {
+            warn!("index.bin is missing, open the storage for writing to rebuild it");
+        }
+        Ok(Self {
+            index: FocusIndexFile::open_read_only(data_dir)?,
+            segments: RecordSegments::open_read_only(data_dir)?,
+        })
+    }
+
+    /// The records before the segment of the month, which a rewrite from the month keeps.
+    /// Nothing is kept if the index doesn't cover them, so it's rebuilt by the rewrite.
+    fn kept_before(&self, month: SegmentId) -> Result<KeptRecords> {
+        let len = self.segments.start_of(month);
+        let Some(last) = len
+            .checked_sub(1)
+            .map(|x| self.segments.read(Some(x), Some(len)))
+            .transpose()?
+            .and_then(|mut x| x.pop())
+        else {
+            return Ok(KeptRecords::default());
+        };
+        let base_day = self.index.start_day();
+        let last_day = ms_as_d(last.focus_at) as u64;
+        let index = self.index.days_until(last_day);
+        if last_day < base_day || index.len() as u64 != last_day - base_day + 1 {
+            return Ok(KeptRecords::default());
+        }
+        Ok(KeptRecords {
+            from: Some(month),
+            len,
+            base_day,
+            index,
+        })
+    }
 
-    for sub_record in record.split_record() {
-        let index = focus_record::write(sub_record.unsafe_to_byte());
-        focus_index::update_index(ms_as_d(sub_record.focus_at) as u64, index)
+    /// Read what's appended by the writer process if opened read-only.
+    fn refresh(&self) {
+        if let Err(e) = self.index.refresh().and_then(|_| self.segments.refresh()) {
+            warn!("Failed to refresh record files: {}", e);
+        }
     }
 }
 
-pub fn get_tmus_meta() -> EngineMeta {
-    EngineMeta {
-        initial_timestamp: d_as_ms(focus_index::start_day() as i64),
-        engine_version: env!("CARGO_PKG_VERSION").to_string(),
+impl Storage {
+    pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        if !data_dir.is_dir() {
+            fs::create_dir_all(data_dir).map_err(StorageError::io(data_dir))?;
+        }
+        if !data_dir.join("index.bin").exists() {
+            // Rebuild before migration, which reads the version from index.bin.
+            RecordFiles::open(data_dir).map(drop)?;
+        }
+        migration::migrate(data_dir)?;
+        Ok(Self {
+            data_dir: data_dir.to_path_buf(),
+            app: FocusAppFile::open(data_dir)?,
+            files: RwLock::new(Some(RecordFiles::open(data_dir)?)),
+            read_only: false,
+        })
+    }
+
+    /// Open the data directory written by another process, usually the tracking tmus.
+    /// Nothing is locked or written, and records appended by the writer are seen on every read.
+    /// Writes fail with [`StorageError::ReadOnly`].
+    ///
+    /// The data directory must be of the current [`FORMAT_VERSION`], since it can't be migrated.
+    pub fn open_read_only(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        if let Some(version) = migration::data_version(data_dir)?
+            && version != FORMAT_VERSION
+        {
+            return Err(StorageError::UnsupportedVersion {
+                path: data_dir.join("index.bin"),
+                version,
+            });
+        }
+        Ok(Self {
+            data_dir: data_dir.to_path_buf(),
+            app: FocusAppFile::open_read_only(data_dir)?,
+            files: RwLock::new(Some(RecordFiles::open_read_only(data_dir)?)),
+            read_only: true,
+        })
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn data_dir(&self) -> &Path {
+        &self.data_dir
+    }
+
+    /// Read records. Include records which blur_at >= start and focus_at <= end,
+    /// which means if only need records focus_at >= start and blur_at <= end,
+    /// you need to crop the return data.
+    pub fn read_by_timestamp(&self, start: Timestamp, end: Timestamp) -> Vec<FocusRecord> {
+        self.iter_by_timestamp(start, end)
+            .filter_map(|x| {
+                x.inspect_err(|e| error!("Failed to read records: {}", e))
+                    .ok()
+            })
+            .collect()
+    }
+
+    /// Iterate the same records as [`Self::read_by_timestamp`], decoded lazily.
+    /// Writes aren't blocked while iterating, but rewrites like retention wait until the
+    /// iterator is dropped.
+    pub fn iter_by_timestamp(&self, start: Timestamp, end: Timestamp) -> RecordIter<'_> {
+        let files = self.files.read().unwrap();
+        let records = files.as_ref().and_then(|files| {
+            files.refresh();
+            let start_index = files.index.query_index(ms_as_d(start) as u64);
+            let end_index = files.index.query_index((ms_as_d(end) + 1) as u64);
+            let start = match start_index {
+                CursorPosition::Start => None,
+                CursorPosition::Middle(start) => Some(start),
+                CursorPosition::End => return None,
+            };
+            let end = match end_index {
+                CursorPosition::Start => return None,
+                CursorPosition::Middle(end) => Some(end),
+                CursorPosition::End => None,
+            };
+            Some(files.segments.iter(start, end))
+        });
+        RecordIter {
+            _files: files,
+            records,
+        }
+    }
+
+    pub fn write_record(
+        &self,
+        app_path: &str,
+        focus_at: Timestamp,
+        blur_at: Timestamp,
+    ) -> Result<()> {
+        if app_path.is_empty() || blur_at - focus_at < s_as_ms(1) {
+            return Ok(());
+        }
+
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let app_id = self.app.touch(app_path, focus_at, blur_at)?;
+        let record = FocusRecord {
+            id: app_id,
+            focus_at,
+            blur_at,
+            device: LOCAL_DEVICE,
+        };
+
+        let guard = self.files.read().unwrap();
+        let files = guard.as_ref().ok_or(StorageError::Closed)?;
+        if (ms_as_d(focus_at) as u64) < files.index.start_day()
+            || files
+                .segments
+                .last()?
+                .is_some_and(|last| focus_at < last.focus_at)
+        {
+            // The day index only grows at the end, earlier records are merged by a rewrite.
+            drop(guard);
+            self.write_records_batch(&[record])?;
+            return Ok(());
+        }
+        for sub_record in record.split_record() {
+            let index = files.segments.write(sub_record)?;
+            files
+                .index
+                .update_index(ms_as_d(sub_record.focus_at) as u64, index)?;
+        }
+        Ok(())
+    }
+
+    /// Write records of registered apps in any order, like imported or synced records.
+    /// Records are appended if they all start from the last record and the first day of the
+    /// index, otherwise they're merged into their positions by rewriting the records from the
+    /// month of the earliest one, which keeps the day index consistent.
+    ///
+    /// Records equal to a written record are skipped, so a batch can be written again safely.
+    /// Returns the count of written records, spans across days are split and counted apart.
+    pub fn write_records_batch(&self, records: &[FocusRecord]) -> Result<usize> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let mut batch: Vec<FocusRecord> = records
+            .iter()
+            .filter(|x| x.blur_at > x.focus_at)
+            .flat_map(|x| x.split_record())
+            .collect();
+        batch.sort_by_key(|x| (x.focus_at, x.blur_at, x.id));
+        batch.dedup();
+        let mut ids: Vec<AppId> = batch.iter().map(|x| x.id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        for id in ids {
+            self.app.get_app_info(id)?;
+        }
+        let Some(first) = batch.first() else {
+            return Ok(0);
+        };
+
+        let guard = self.files.read().unwrap();
+        let files = guard.as_ref().ok_or(StorageError::Closed)?;
+        let last = files.segments.last()?;
+        if ms_as_d(first.focus_at) as u64 >= files.index.start_day()
+            && last.is_none_or(|last| first.focus_at >= last.focus_at)
+        {
+            let mut count = 0;
+            for record in batch.into_iter().filter(|x| Some(*x) != last) {
+                let index = files.segments.write(record)?;
+                files
+                    .index
+                    .update_index(ms_as_d(record.focus_at) as u64, index)?;
+                count += 1;
+            }
+            return Ok(count);
+        }
+        drop(guard);
+
+        let mut count = 0;
+        self.rewrite_records_from(Some(first.focus_at), |records| {
+            let start = records.partition_point(|x| x.focus_at < first.focus_at);
+            let mut merged = records[start..].to_vec();
+            let existing = merged.len();
+            merged.extend_from_slice(&batch);
+            merged.sort_by_key(|x| (x.focus_at, x.blur_at, x.id));
+            merged.dedup();
+            count = merged.len() - existing;
+            (count > 0).then(|| [&records[..start], &merged].concat())
+        })?;
+        Ok(count)
+    }
+
+    /// Replace all records with the result of `f`, which gets all records in time order
+    /// and returns records in time order, or `None` if nothing changes.
+    /// The segments and index.bin are reopened after replaced, writes wait until it's done.
+    pub(crate) fn rewrite_records(
+        &self,
+        f: impl FnOnce(&[FocusRecord]) -> Option<Vec<FocusRecord>>,
+    ) -> Result<()> {
+        self.rewrite_records_from(None, f)
+    }
+
+    /// Same as [`Self::rewrite_records`], but only the records from the segment of the month of
+    /// `from` are passed to `f` and replaced. Earlier segments and their days in index.bin are
+    /// kept, so `f` must not return records before that month.
+    pub(crate) fn rewrite_records_from(
+        &self,
+        from: Option<Timestamp>,
+        f: impl FnOnce(&[FocusRecord]) -> Option<Vec<FocusRecord>>,
+    ) -> Result<()> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let mut files = self.files.write().unwrap();
+        let opened = files.as_ref().ok_or(StorageError::Closed)?;
+        let kept = match from {
+            Some(from) => opened.kept_before(SegmentId::of_timestamp(from))?,
+            None => KeptRecords::default(),
+        };
+        let records = opened.segments.read(Some(kept.len), None)?;
+        let Some(records) = f(&records) else {
+            return Ok(());
+        };
+        let pending = rewrite::prepare_rewrite_from(&self.data_dir, &kept, &records)?;
+        // Close the files, which can't be replaced while opened on Windows.
+        *files = None;
+        let committed = pending.commit();
+        *files = Some(RecordFiles::open(&self.data_dir)?);
+        committed
+    }
+
+    /// Compress the closed segments, returns the count of newly compressed segments.
+    /// Waits until the iterators of records are dropped, which may read the segments.
+    pub fn compress_segments(&self) -> Result<usize> {
+        let mut files = self.files.write().unwrap();
+        files
+            .as_mut()
+            .ok_or(StorageError::Closed)?
+            .segments
+            .compress()
+    }
+
+    pub fn get_all_app(&self) -> Vec<String> {
+        self.app.get_all_app()
+    }
+
+    pub fn get_path_by_id(&self, id: AppId) -> Result<String> {
+        self.app.get_path_by_id(id)
+    }
+
+    pub fn get_id_by_path(&self, path: &str) -> Result<AppId> {
+        self.app.get_id_by_path(path)
+    }
+
+    pub fn get_app_info(&self, id: AppId) -> Result<AppInfo> {
+        self.app.get_app_info(id)
+    }
+
+    pub fn get_all_app_info(&self) -> Vec<AppInfo> {
+        self.app.get_all_app_info()
+    }
+
+    pub fn all_index_record(&self) -> Vec<FileIndexRecord> {
+        let files = self.files.read().unwrap();
+        files.as_ref().map_or(vec![], |files| {
+            files.refresh();
+            files.index.all_record()
+        })
+    }
+
+    pub fn meta(&self) -> EngineMeta {
+        let files = self.files.read().unwrap();
+        let start_day = files.as_ref().map_or(0, |files| {
+            files.refresh();
+            files.index.start_day()
+        });
+        EngineMeta {
+            initial_timestamp: d_as_ms(start_day as i64),
+            engine_version: env!("CARGO_PKG_VERSION").to_string(),
+            format_version: FORMAT_VERSION,
+        }
+    }
+}
+
+/// Records returned by [`Storage::iter_by_timestamp`].
+pub struct RecordIter<'a> {
+    /// Keep the files from being replaced while iterating.
+    _files: RwLockReadGuard<'a, Option<RecordFiles>>,
+    records: Option<SegmentRecords>,
+}
+
+impl Iterator for RecordIter<'_> {
+    type Item = Result<FocusRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.records.as_mut()?.next()
+    }
+}
+
+/// Open the data directory as the storage shared by the whole process.
+pub fn init(data_dir: impl AsRef<Path>) -> Result<()> {
+    STORAGE
+        .set(Storage::open(data_dir)?)
+        .expect("storage already initialized");
+    Ok(())
+}
+
+/// Open the data directory read-only as the storage shared by the whole process,
+/// see [`Storage::open_read_only`].
+pub fn init_read_only(data_dir: impl AsRef<Path>) -> Result<()> {
+    STORAGE
+        .set(Storage::open_read_only(data_dir)?)
+        .expect("storage already initialized");
+    Ok(())
+}
+
+pub fn get_storage<'a>() -> &'a Storage {
+    STORAGE.get().expect("storage not initialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::{record, temp_data_dir};
+    use crate::util::{m_as_ms, now_timestamp};
+
+    #[test]
+    fn test_open_two_storage() {
+        let now = now_timestamp();
+        let dir_a = temp_data_dir("storage-a");
+        let dir_b = temp_data_dir("storage-b");
+        let storage_a = Storage::open(&dir_a).unwrap();
+        let storage_b = Storage::open(&dir_b).unwrap();
+
+        storage_a
+            .write_record("C:\\a.exe", now - m_as_ms(10), now - m_as_ms(5))
+            .unwrap();
+        storage_b
+            .write_record("C:\\b.exe", now - m_as_ms(5), now - m_as_ms(1))
+            .unwrap();
+        storage_b
+            .write_record("C:\\a.exe", now - m_as_ms(1), now)
+            .unwrap();
+
+        assert_eq!(storage_a.get_all_app(), vec!["C:\\a.exe"]);
+        assert_eq!(storage_b.get_all_app(), vec!["C:\\b.exe", "C:\\a.exe"]);
+        let records_a = storage_a.read_by_timestamp(0, now);
+        let records_b = storage_b.read_by_timestamp(0, now);
+        assert_eq!(records_a.len(), 1);
+        assert_eq!(records_b.len(), 2);
+        assert_eq!(
+            records_b[1].id,
+            storage_b.get_id_by_path("C:\\a.exe").unwrap()
+        );
+        assert!(matches!(
+            storage_b.get_path_by_id(2),
+            Err(StorageError::AppNotFound(2))
+        ));
+
+        drop(storage_a);
+        let reopened_a = Storage::open(&dir_a).unwrap();
+        assert_eq!(reopened_a.read_by_timestamp(0, now), records_a);
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let now = now_timestamp();
+        let dir = temp_data_dir("read-only");
+        let writer = Storage::open(&dir).unwrap();
+        writer
+            .write_record("C:\\a.exe", now - m_as_ms(10), now - m_as_ms(5))
+            .unwrap();
+
+        let reader = Storage::open_read_only(&dir).unwrap();
+        assert!(reader.is_read_only());
+        assert_eq!(reader.read_by_timestamp(0, now).len(), 1);
+        writer
+            .write_record("C:\\b.exe", now - m_as_ms(5), now - m_as_ms(1))
+            .unwrap();
+        assert_eq!(
+            reader.read_by_timestamp(0, now),
+            writer.read_by_timestamp(0, now)
+        );
+        assert_eq!(reader.get_path_by_id(1).unwrap(), "C:\\b.exe");
+        assert_eq!(reader.get_all_app(), writer.get_all_app());
+
+        assert!(matches!(
+            reader.write_record("C:\\a.exe", now - m_as_ms(1), now),
+            Err(StorageError::ReadOnly)
+        ));
+        assert!(matches!(
+            reader.write_record("C:\\c.exe", now - m_as_ms(1), now),
+            Err(StorageError::ReadOnly)
+        ));
+        assert!(matches!(
+            reader.apply_retention(now, retention::RetentionMode::Archive),
+            Err(StorageError::ReadOnly)
+        ));
+        assert_eq!(writer.read_by_timestamp(0, now).len(), 2);
+        assert_eq!(writer.get_all_app().len(), 2);
+    }
+
+    #[test]
+    fn test_write_records_batch() {
+        let dir = temp_data_dir("write-batch");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 30000, now - 20000)
+            .unwrap();
+        storage
+            .write_record("C:\\b.exe", now - 15000, now - 10000)
+            .unwrap();
+
+        // Earlier records are merged into place, even across days.
+        let batch = [
+            record(1, now - 9000, now - 8000),
+            record(0, now - 20000, now - 15000),
+            record(1, now - d_as_ms(3), now - d_as_ms(3) + 1000),
+        ];
+        assert_eq!(storage.write_records_batch(&batch).unwrap(), 3);
+        assert_eq!(storage.write_records_batch(&batch).unwrap(), 0);
+        assert!(matches!(
+            storage.write_records_batch(&[record(5, now - 7000, now - 6000)]),
+            Err(StorageError::AppNotFound(5))
+        ));
+        storage
+            .write_record("C:\\a.exe", now - d_as_ms(2), now - d_as_ms(2) + 1000)
+            .unwrap();
+        let records = storage.read_by_timestamp(0, now);
+        assert_eq!(records.len(), 6);
+        assert!(records.is_sorted_by_key(|x| x.focus_at));
+        assert_eq!(
+            records[2..],
+            [
+                record(0, now - 30000, now - 20000),
+                record(0, now - 20000, now - 15000),
+                record(1, now - 15000, now - 10000),
+                record(1, now - 9000, now - 8000),
+            ]
+        );
+        assert!(verify::verify(&dir).unwrap().is_ok());
     }
 }
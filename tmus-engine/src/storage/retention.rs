@@ -0,0 +1,214 @@
+//! Age out old records, either to daily totals or to an archive file.
+//!
+//! The base day of index.bin is kept, so the day index keeps working across the pruned range.
+
+use crate::models::{AppId, DeviceId, FocusRecord};
+use crate::storage::Storage;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::replace_file;
+use crate::storage::rewrite::{read_record_file, record_content};
+use crate::util::{Timestamp, d_as_ms, ms_as_d};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tracing::info;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum RetentionMode {
+    /// Replace the records of a day by the total duration of each app,
+    /// packed one after another from the start of the day.
+    #[default]
+    DailyTotal,
+    /// Move the records to archive.bin in the data directory.
+    Archive,
+}
+
+#[derive(Debug)]
+pub struct RetentionReport {
+    /// Count of records before the cutoff day, before and after the retention.
+    pub before: usize,
+    pub after: usize,
+}
+
+impl Storage {
+    /// Apply the retention to records of the days before the day of `before`.
+    /// Only the records before the cutoff are read to find the first change, and the segments
+    /// are rewritten from its month, nothing is rewritten if no record changes.
+    pub fn apply_retention(
+        &self,
+        before: Timestamp,
+        mode: RetentionMode,
+    ) -> Result<RetentionReport> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let before = d_as_ms(ms_as_d(before));
+        let old: Vec<_> = self
+            .read_by_timestamp(0, before - 1)
+            .into_iter()
+            .filter(|x| x.focus_at < before)
+            .collect();
+        let retained = retain(&old, mode);
+        let report = RetentionReport {
+            before: old.len(),
+            after: retained.len(),
+        };
+        // Days before the first change are already retained, their months are kept.
+        let first = old
+            .iter()
+            .zip(&retained)
+            .take_while(|(x, y)| x == y)
+            .count();
+        let Some(from) = old.get(first).map(|x| x.focus_at) else {
+            return Ok(report);
+        };
+        let archive_path = self.data_dir.join("archive.bin");
+        let mut archive_result = Ok(());
+        self.rewrite_records_from(Some(from), |records| {
+            let split = records.partition_point(|x| x.focus_at < before);
+            let (old, new) = records.split_at(split);
+            if mode == RetentionMode::Archive && !old.is_empty() {
+                archive_result = append_archive(&archive_path, old);
+                if archive_result.is_err() {
+                    return None;
+                }
+            }
+            let ret = [retain(old, mode), new.to_vec()].concat();
+            (ret != records).then_some(ret)
+        })?;
+        archive_result?;
+        if report.before != report.after {
+            info!(
+                "Retention {:?} before {}, records {} -> {}",
+                mode, before, report.before, report.after
+            );
+        }
+        Ok(report)
+    }
+}
+
+/// Read the records moved by [`RetentionMode::Archive`].
+pub fn read_archive(data_dir: impl AsRef<Path>) -> Result<Vec<FocusRecord>> {
+    Ok(read_record_file(&data_dir.as_ref().join("archive.bin"))?.1)
+}
+
+/// Records already archived are skipped, in case the last retention failed after archiving.
+fn append_archive(path: &Path, records: &[FocusRecord]) -> Result<()> {
+    let (_, mut archive) = read_record_file(path)?;
+    archive.extend_from_slice(records);
+    archive.sort_by_key(|x| (x.focus_at, x.id, x.blur_at));
+    archive.dedup();
+    replace_file(path, &record_content(&archive))
+}
+
+fn retain(records: &[FocusRecord], mode: RetentionMode) -> Vec<FocusRecord> {
+    match mode {
+        RetentionMode::DailyTotal => daily_total(records),
+        RetentionMode::Archive => vec![],
+    }
+}
+
+/// Records of already packed days stay the same.
+fn daily_total(records: &[FocusRecord]) -> Vec<FocusRecord> {
+    let mut totals: BTreeMap<(Timestamp, AppId, DeviceId), Timestamp> = BTreeMap::new();
+    for record in records {
+        *totals
+            .entry((ms_as_d(record.focus_at), record.id, record.device))
+            .or_default() += record.duration();
+    }
+    let mut ret = Vec::with_capacity(totals.len());
+    let mut cursor = (Timestamp::MIN, Timestamp::MIN);
+    for ((day, id, device), total) in totals {
+        if cursor.0 != day {
+            cursor = (day, d_as_ms(day));
+        }
+        let focus_at = cursor.1;
+        let blur_at = (focus_at + total).min(d_as_ms(day + 1));
+        if blur_at > focus_at {
+            ret.push(FocusRecord {
+                id,
+                focus_at,
+                blur_at,
+                device,
+            });
+        }
+        cursor.1 = blur_at;
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Storage;
+    use crate::storage::test_util::temp_data_dir;
+    use crate::util::{d_as_ms, m_as_ms, ms_as_d, now_timestamp};
+    use std::fs;
+
+    #[test]
+    fn test_retention() {
+        let day = ms_as_d(now_timestamp()) - 100;
+        let spans = [(0, 0, 10), (1, 10, 20), (0, 30, 40), (0, 1440, 1450)];
+        let storages: Vec<_> = ["retention-total", "retention-archive"]
+            .into_iter()
+            .map(|name| {
+                let dir = temp_data_dir(name);
+                fs::create_dir_all(&dir).unwrap();
+                fs::write(dir.join("index.bin"), (day as u64).to_le_bytes()).unwrap();
+                let storage = Storage::open(&dir).unwrap();
+                for (app, focus_at, blur_at) in spans {
+                    storage
+                        .write_record(
+                            &format!("C:\\{}.exe", app),
+                            d_as_ms(day) + m_as_ms(focus_at),
+                            d_as_ms(day) + m_as_ms(blur_at),
+                        )
+                        .unwrap();
+                }
+                storage
+            })
+            .collect();
+        let before = d_as_ms(day + 1) + 1;
+
+        let total = &storages[0];
+        let report = total
+            .apply_retention(before, RetentionMode::DailyTotal)
+            .unwrap();
+        assert_eq!((report.before, report.after), (3, 2));
+        let spans: Vec<_> = total
+            .read_by_timestamp(0, d_as_ms(day + 2))
+            .iter()
+            .map(|x| (x.id, x.focus_at - d_as_ms(day), x.blur_at - d_as_ms(day)))
+            .collect();
+        assert_eq!(
+            spans,
+            vec![
+                (0, 0, m_as_ms(20)),
+                (1, m_as_ms(20), m_as_ms(30)),
+                (0, m_as_ms(1440), m_as_ms(1450))
+            ]
+        );
+        let report = total
+            .apply_retention(before, RetentionMode::DailyTotal)
+            .unwrap();
+        assert_eq!((report.before, report.after), (2, 2));
+
+        let archive = &storages[1];
+        archive
+            .apply_retention(before, RetentionMode::Archive)
+            .unwrap();
+        let records = archive.read_by_timestamp(0, d_as_ms(day + 2));
+        assert_eq!(records.len(), 1);
+        assert_eq!(archive.read_by_timestamp(0, d_as_ms(day)), vec![]);
+        assert_eq!(read_archive(archive.data_dir()).unwrap().len(), 3);
+        let report = archive
+            .apply_retention(before, RetentionMode::Archive)
+            .unwrap();
+        assert_eq!((report.before, report.after), (0, 0));
+        archive
+            .write_record("C:\\0.exe", now_timestamp() - m_as_ms(1), now_timestamp())
+            .unwrap();
+        assert_eq!(archive.read_by_timestamp(0, now_timestamp()).len(), 2);
+    }
+}
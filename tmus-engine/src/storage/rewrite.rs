@@ -0,0 +1,275 @@
+//! Offline rewrite of the record files, shared by repair, compaction and retention.
+//!
+//! tmus must not be running with the data directory, which is checked by [`ensure_not_in_use`].
+
+use crate::models::FocusRecord;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{open_exclusive, read_or_default, replace_file, write_temp};
+use crate::storage::focus_index::build_index;
+use crate::storage::focus_record::decode_record_file;
+use crate::storage::header::{FORMAT_VERSION, FileHeader, INDEX_MAGIC, RECORD_MAGIC};
+use crate::storage::segment::{
+    RECORD_DIR, SegmentFile, SegmentId, compressed_content, list_segments, segment_files,
+    split_segments,
+};
+use crate::util::{ms_as_d, now_day};
+use std::fs;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+/// Take the write lock of every data file, which fails if tmus is running with them.
+/// The locks are released before returning, so the files can be replaced.
+pub(crate) fn ensure_not_in_use(data_dir: &Path) -> Result<()> {
+    for name in ["app.jsonl", "app.txt", "record.bin", "index.bin"] {
+        let path = data_dir.join(name);
+        if path.is_file() {
+            open_exclusive(OpenOptions::new().read(true).write(true), &path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Read the version and all records, from record.bin before format version 3,
+/// or from the segments.
+pub(crate) fn read_records(data_dir: &Path) -> Result<(u32, Vec<FocusRecord>)> {
+    let path = data_dir.join("record.bin");
+    if path.exists() {
+        return read_record_file(&path);
+    }
+    let mut records = Vec::new();
+    for file in list_segments(data_dir)? {
+        records.extend(file.read_records()?);
+    }
+    Ok((FORMAT_VERSION, records))
+}
+
+/// Whether any record file exists, in either layout.
+pub(crate) fn has_records(data_dir: &Path) -> Result<bool> {
+    Ok(data_dir
+        .join("record.bin")
+        .metadata()
+        .is_ok_and(|x| x.len() > 0)
+        || !list_segments(data_dir)?.is_empty())
+}
+
+/// Read the version and all records of a file in the format of record.bin.
+pub(crate) fn read_record_file(path: &Path) -> Result<(u32, Vec<FocusRecord>)> {
+    decode_record_file(&read_or_default(path)?, path)
+}
+
+/// The content of a record file for the records.
+pub(crate) fn record_content(records: &[FocusRecord]) -> Vec<u8> {
+    let mut content = FileHeader::new(RECORD_MAGIC, records.len() as u64)
+        .to_bytes()
+        .to_vec();
+    for record in records {
+        content.extend_from_slice(&record.to_byte());
+    }
+    content
+}
+
+/// Replace the segments and index.bin with the records in time order.
+pub(crate) fn rewrite_records(data_dir: &Path, records: &[FocusRecord]) -> Result<()> {
+    prepare_rewrite(data_dir, records)?.commit()
+}
+
+/// Records of the months before a rewrite, whose segments and days in index.bin are kept.
+#[derive(Debug, Default)]
+pub(crate) struct KeptRecords {
+    /// Segments of this month and later months are rewritten, all segments if `None`.
+    pub from: Option<SegmentId>,
+    /// Count of records in the kept segments.
+    pub len: usize,
+    pub base_day: u64,
+    /// The index of the days from the base day to the day of the last kept record.
+    pub index: Vec<u64>,
+}
+
+/// New segments and index.bin written to temporary files, which replace the old ones on commit.
+pub(crate) struct PendingRewrite {
+    data_dir: PathBuf,
+    /// Segments of earlier months are kept.
+    from: Option<SegmentId>,
+    /// The temporary file and the segment file it replaces.
+    segments: Vec<(PathBuf, PathBuf)>,
+    /// Segment files whose records don't change, which are kept as is.
+    unchanged: Vec<PathBuf>,
+    index_tmp: PathBuf,
+    index_path: PathBuf,
+}
+
+/// Months whose records don't change are skipped. Closed months stay compressed if they were,
+/// the last month is always plain since it's written next.
+pub(crate) fn prepare_rewrite(data_dir: &Path, records: &[FocusRecord]) -> Result<PendingRewrite> {
+    prepare_rewrite_from(data_dir, &KeptRecords::default(), records)
+}
+
+/// Same as [`prepare_rewrite`], but `records` only replace the records after `kept`.
+pub(crate) fn prepare_rewrite_from(
+    data_dir: &Path,
+    kept: &KeptRecords,
+    records: &[FocusRecord],
+) -> Result<PendingRewrite> {
+    debug_assert!(records.is_sorted_by_key(|x| x.focus_at));
+    debug_assert!(kept.len == 0 || kept.from.is_some());
+    let index = if kept.len == 0 {
+        index_content(
+            FORMAT_VERSION,
+            resolve_base_day(data_dir, records)?,
+            records,
+        )
+    } else {
+        tail_index_content(kept, records)
+    };
+    let record_dir = data_dir.join(RECORD_DIR);
+    fs::create_dir_all(&record_dir).map_err(StorageError::io(&record_dir))?;
+    let existing: Vec<SegmentFile> = list_segments(data_dir)?
+        .into_iter()
+        .filter(|x| kept.from.is_none_or(|from| x.id >= from))
+        .collect();
+    let split = split_segments(records);
+    let mut segments = Vec::new();
+    let mut unchanged = Vec::new();
+    for (i, &(id, records)) in split.iter().enumerate() {
+        let old = existing.iter().find(|x| x.id == id);
+        let compressed = old.is_some_and(|x| x.compressed) && i + 1 < split.len();
+        let content = record_content(records);
+        if let Some(old) = old
+            && old.compressed == compressed
+            && old.read()? == content
+        {
+            unchanged.push(old.path.clone());
+            continue;
+        }
+        let content = if compressed {
+            compressed_content(records)
+        } else {
+            content
+        };
+        let path = SegmentFile::new(data_dir, id, compressed).path;
+        segments.push((write_temp(&path, &content)?, path));
+    }
+    let index_path = data_dir.join("index.bin");
+    Ok(PendingRewrite {
+        data_dir: data_dir.to_path_buf(),
+        from: kept.from,
+        segments,
+        unchanged,
+        index_tmp: write_temp(&index_path, &index)?,
+        index_path,
+    })
+}
+
+impl PendingRewrite {
+    /// index.bin is removed before the segments are replaced, so after a crash in between,
+    /// index.bin is missing and rebuilt on next open instead of pointing to the old records.
+    /// Segments of other months or replaced by the other layout, and record.bin left by an older
+    /// version, are removed last.
+    /// The files must not be opened, or the rename fails on Windows.
+    pub(crate) fn commit(self) -> Result<()> {
+        if self.index_path.exists() {
+            fs::remove_file(&self.index_path).map_err(StorageError::io(&self.index_path))?;
+        }
+        for (tmp, path) in &self.segments {
+            fs::rename(tmp, path).map_err(StorageError::io(path))?;
+        }
+        let stale = segment_files(&self.data_dir)?
+            .into_iter()
+            .filter(|x| self.from.is_none_or(|from| x.id >= from))
+            .map(|x| x.path)
+            .filter(|path| {
+                self.segments.iter().all(|(_, x)| x != path) && !self.unchanged.contains(path)
+            })
+            .chain(Some(self.data_dir.join("record.bin")).filter(|x| x.exists()));
+        for path in stale {
+            fs::remove_file(&path).map_err(StorageError::io(&path))?;
+        }
+        fs::rename(&self.index_tmp, &self.index_path).map_err(StorageError::io(&self.index_path))
+    }
+}
+
+/// Rebuild index.bin from the records without checking whether the files are in use.
+pub(crate) fn rebuild_index_file(data_dir: &Path) -> Result<()> {
+    let (version, records) = read_records(data_dir)?;
+    if !records.is_sorted_by_key(|x| x.focus_at) {
+        warn!("Records in {:?} are not in time order", data_dir);
+    }
+    let base_day = resolve_base_day(data_dir, &records)?;
+    info!(
+        "Rebuild index of {:?} from {} records",
+        data_dir,
+        records.len()
+    );
+    write_index(data_dir, version, base_day, &records)
+}
+
+/// Rewrite index.bin for the records, the version follows the record files.
+pub(crate) fn write_index(
+    data_dir: &Path,
+    version: u32,
+    base_day: u64,
+    records: &[FocusRecord],
+) -> Result<()> {
+    replace_file(
+        data_dir.join("index.bin"),
+        &index_content(version, base_day, records),
+    )
+}
+
+/// Keep the base day in index.bin unless records start before it.
+pub(crate) fn resolve_base_day(data_dir: &Path, records: &[FocusRecord]) -> Result<u64> {
+    let first_day = records.first().map(|x| ms_as_d(x.focus_at) as u64);
+    Ok(read_base_day(data_dir)?
+        .into_iter()
+        .chain(first_day)
+        .min()
+        .unwrap_or(now_day() as u64))
+}
+
+/// The base day in the header of index.bin, `None` if index.bin is missing or broken.
+pub(crate) fn read_base_day(data_dir: &Path) -> Result<Option<u64>> {
+    let path = data_dir.join("index.bin");
+    let buf = read_or_default(&path)?;
+    Ok(FileHeader::parse(INDEX_MAGIC, &buf)
+        .filter(|header| header.check_version(&path).is_ok())
+        .map(|header| header.extra))
+}
+
+pub(crate) fn parse_header(magic: [u8; 4], buf: &[u8], path: &Path) -> Result<FileHeader> {
+    let header = FileHeader::parse(magic, buf).ok_or_else(|| StorageError::UnsupportedVersion {
+        path: path.to_path_buf(),
+        version: 0,
+    })?;
+    header.check_version(path)?;
+    Ok(header)
+}
+
+pub(crate) fn index_content(version: u32, base_day: u64, records: &[FocusRecord]) -> Vec<u8> {
+    index_bytes(version, base_day, build_index(base_day, records))
+}
+
+/// index.bin with the kept index, and the index of later days for the records after them.
+fn tail_index_content(kept: &KeptRecords, records: &[FocusRecord]) -> Vec<u8> {
+    let mut index = kept.index[1..].to_vec();
+    if let Some(last) = records.last() {
+        let first_day = kept.base_day + kept.index.len() as u64;
+        index.extend((first_day..=ms_as_d(last.focus_at) as u64).map(|day| {
+            (kept.len + records.partition_point(|x| (ms_as_d(x.focus_at) as u64) < day)) as u64
+        }));
+    }
+    index_bytes(FORMAT_VERSION, kept.base_day, index)
+}
+
+fn index_bytes(version: u32, base_day: u64, index: Vec<u64>) -> Vec<u8> {
+    let header = FileHeader {
+        magic: INDEX_MAGIC,
+        version,
+        extra: base_day,
+    };
+    let mut content = header.to_bytes().to_vec();
+    for unit in index {
+        content.extend_from_slice(&unit.to_le_bytes());
+    }
+    content
+}
@@ -0,0 +1,598 @@
+//! Records split into one file per month, in the `record` directory of the data directory.
+//!
+//! A segment is named by the month of its first record, like `2025-01.bin`, and holds records
+//! until the first record of a later month. Only the last segment is written, the others are
+//! closed and can be compressed to `{month}.bin.z`: the same header followed by the zlib
+//! compressed records. Positions in index.bin count records across all segments in order.
+
+use crate::models::FocusRecord;
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{read_or_default, replace_file};
+use crate::storage::focus_record::{
+    FocusRecordFile, decode_record, decode_record_file, record_size,
+};
+use crate::storage::header::{FileHeader, HEADER_SIZE, RECORD_MAGIC};
+use crate::storage::rewrite::record_content;
+use crate::util::{Timestamp, ms_as_d};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use memmap2::Mmap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tracing::info;
+
+pub(crate) const RECORD_DIR: &str = "record";
+const COMPRESSED_SUFFIX: &str = ".bin.z";
+const PLAIN_SUFFIX: &str = ".bin";
+
+/// The month of a segment in UTC, same as the days of index.bin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SegmentId {
+    pub year: i32,
+    pub month: u32,
+}
+
+impl SegmentId {
+    /// The month of the day from `UNIX_EPOCH`.
+    pub fn of_day(day: i64) -> Self {
+        // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+        let z = day + 719468;
+        let era = z.div_euclid(146097);
+        let doe = z.rem_euclid(146097);
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + i64::from(month <= 2);
+        Self {
+            year: year as i32,
+            month: month as u32,
+        }
+    }
+
+    pub fn of_timestamp(timestamp: Timestamp) -> Self {
+        Self::of_day(ms_as_d(timestamp))
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        let (year, month) = name.split_once('-')?;
+        let id = Self {
+            year: year.parse().ok()?,
+            month: month.parse().ok()?,
+        };
+        (1..=12).contains(&id.month).then_some(id)
+    }
+}
+
+impl Display for SegmentId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}-{:02}", self.year, self.month)
+    }
+}
+
+/// A segment file in the record directory.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct SegmentFile {
+    pub id: SegmentId,
+    pub path: PathBuf,
+    pub compressed: bool,
+}
+
+impl SegmentFile {
+    pub fn new(data_dir: &Path, id: SegmentId, compressed: bool) -> Self {
+        let suffix = if compressed {
+            COMPRESSED_SUFFIX
+        } else {
+            PLAIN_SUFFIX
+        };
+        Self {
+            id,
+            path: data_dir.join(RECORD_DIR).join(format!("{}{}", id, suffix)),
+            compressed,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        self.path
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The header and the records of the file, decompressed if needed.
+    pub fn read(&self) -> Result<Vec<u8>> {
+        let buf = read_or_default(&self.path)?;
+        if !self.compressed || buf.len() < HEADER_SIZE {
+            return Ok(buf);
+        }
+        let mut content = buf[..HEADER_SIZE].to_vec();
+        ZlibDecoder::new(&buf[HEADER_SIZE..])
+            .read_to_end(&mut content)
+            .map_err(StorageError::io(&self.path))?;
+        Ok(content)
+    }
+
+    pub fn read_records(&self) -> Result<Vec<FocusRecord>> {
+        Ok(decode_record_file(&self.read()?, &self.path)?.1)
+    }
+}
+
+/// Every segment file in the record directory, in the order of months.
+/// A month may have both files if a compression is interrupted, the plain one comes first.
+pub(crate) fn segment_files(data_dir: &Path) -> Result<Vec<SegmentFile>> {
+    let dir = data_dir.join(RECORD_DIR);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(StorageError::io(&dir)(e)),
+    };
+    let mut files = Vec::new();
+    for entry in entries {
+        let name = entry.map_err(StorageError::io(&dir))?.file_name();
+        let name = name.to_string_lossy();
+        let (stem, compressed) = if let Some(stem) = name.strip_suffix(COMPRESSED_SUFFIX) {
+            (stem, true)
+        } else if let Some(stem) = name.strip_suffix(PLAIN_SUFFIX) {
+            (stem, false)
+        } else {
+            continue;
+        };
+        if let Some(id) = SegmentId::parse(stem) {
+            files.push(SegmentFile::new(data_dir, id, compressed));
+        }
+    }
+    files.sort_by_key(|x| (x.id, x.compressed));
+    Ok(files)
+}
+
+/// One file of every segment, the plain file is used if a month has both.
+pub(crate) fn list_segments(data_dir: &Path) -> Result<Vec<SegmentFile>> {
+    let mut files = segment_files(data_dir)?;
+    files.dedup_by_key(|x| x.id);
+    Ok(files)
+}
+
+/// Group records in time order into segments.
+/// A segment ends before the first record of a later month, so records out of order stay in place.
+pub(crate) fn split_segments(records: &[FocusRecord]) -> Vec<(SegmentId, &[FocusRecord])> {
+    let mut ret: Vec<(SegmentId, &[FocusRecord])> = Vec::new();
+    let mut start = 0;
+    for (i, record) in records.iter().enumerate() {
+        let id = SegmentId::of_timestamp(record.focus_at);
+        match ret.last_mut() {
+            Some((last, slice)) if id <= *last => *slice = &records[start..=i],
+            _ => {
+                start = i;
+                ret.push((id, &records[i..=i]));
+            }
+        }
+    }
+    ret
+}
+
+/// The content of a compressed segment for the records.
+pub(crate) fn compressed_content(records: &[FocusRecord]) -> Vec<u8> {
+    let plain = record_content(records);
+    let mut encoder = ZlibEncoder::new(plain[..HEADER_SIZE].to_vec(), Compression::default());
+    encoder
+        .write_all(&plain[HEADER_SIZE..])
+        .and_then(|_| encoder.finish())
+        .expect("compress to memory")
+}
+
+/// The segments of an opened data directory. Positions are counted across all segments.
+#[derive(Debug)]
+pub(crate) struct RecordSegments {
+    data_dir: PathBuf,
+    /// Opened read-only, all segments are closed and read into memory instead of mapped.
+    read_only: bool,
+    state: Mutex<State>,
+}
+
+#[derive(Debug)]
+struct State {
+    closed: Vec<ClosedSegment>,
+    /// The last segment, `None` until the first record is written.
+    hot: Option<HotSegment>,
+}
+
+#[derive(Debug)]
+struct ClosedSegment {
+    file: SegmentFile,
+    start: usize,
+    len: usize,
+}
+
+#[derive(Debug)]
+struct HotSegment {
+    id: SegmentId,
+    start: usize,
+    file: FocusRecordFile,
+}
+
+impl RecordSegments {
+    /// The last segment is decompressed if it was compressed, since it may still be written.
+    pub fn open(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let data_dir = data_dir.as_ref();
+        let mut files = list_segments(data_dir)?;
+        let last = files.pop();
+        let mut closed = Vec::with_capacity(files.len());
+        let mut start = 0;
+        for file in files {
+            let len = file.read_records()?.len();
+            closed.push(ClosedSegment { file, start, len });
+            start += len;
+        }
+        let hot = match last {
+            Some(file) => {
+                if file.compressed {
+                    let plain = SegmentFile::new(data_dir, file.id, false);
+                    replace_file(&plain.path, &record_content(&file.read_records()?))?;
+                    fs::remove_file(&file.path).map_err(StorageError::io(&file.path))?;
+                }
+                Some(HotSegment {
+                    id: file.id,
+                    start,
+                    file: FocusRecordFile::open(SegmentFile::new(data_dir, file.id, false).path)?,
+                })
+            }
+            None => None,
+        };
+        Ok(Self {
+            data_dir: data_dir.to_path_buf(),
+            read_only: false,
+            state: Mutex::new(State { closed, hot }),
+        })
+    }
+
+    /// Open without writing the files, which may be written by another process.
+    /// Call [`Self::refresh`] to see the appended records.
+    pub fn open_read_only(data_dir: impl AsRef<Path>) -> Result<Self> {
+        let ret = Self {
+            data_dir: data_dir.as_ref().to_path_buf(),
+            read_only: true,
+            state: Mutex::new(State {
+                closed: vec![],
+                hot: None,
+            }),
+        };
+        ret.refresh()?;
+        Ok(ret)
+    }
+
+    /// List the segments again if opened read-only. Only the length of the last segment
+    /// and of the changed segments are read, since only the last segment is appended.
+    pub fn refresh(&self) -> Result<()> {
+        if !self.read_only {
+            return Ok(());
+        }
+        let files = list_segments(&self.data_dir)?;
+        let mut state = self.state.lock().unwrap();
+        let known = state.closed.len().saturating_sub(1);
+        let mut closed = Vec::with_capacity(files.len());
+        let mut start = 0;
+        for (i, file) in files.into_iter().enumerate() {
+            let len = match state.closed.get(i) {
+                Some(segment) if i < known && segment.file == file => segment.len,
+                _ => file.read_records()?.len(),
+            };
+            closed.push(ClosedSegment { file, start, len });
+            start += len;
+        }
+        state.closed = closed;
+        Ok(())
+    }
+
+    /// Returns the position of the written record. A new segment is started if the record is
+    /// in a month after the last segment.
+    pub fn write(&self, record: FocusRecord) -> Result<u64> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let mut state = self.state.lock().unwrap();
+        let id = SegmentId::of_timestamp(record.focus_at);
+        if state.hot.as_ref().is_none_or(|hot| hot.id < id) {
+            let file = SegmentFile::new(&self.data_dir, id, false);
+            let dir = file.path.parent().unwrap();
+            fs::create_dir_all(dir).map_err(StorageError::io(dir))?;
+            let start = match state.hot.take() {
+                Some(hot) => {
+                    let len = hot.file.count();
+                    state.closed.push(ClosedSegment {
+                        file: SegmentFile::new(&self.data_dir, hot.id, false),
+                        start: hot.start,
+                        len,
+                    });
+                    hot.start + len
+                }
+                None => 0,
+            };
+            info!("Start record segment {:?}", file.path);
+            state.hot = Some(HotSegment {
+                id,
+                start,
+                file: FocusRecordFile::open(&file.path)?,
+            });
+        }
+        let hot = state.hot.as_ref().unwrap();
+        Ok(hot.start as u64 + hot.file.write(record.to_byte())?)
+    }
+
+    /// Count of records in all segments.
+    pub fn len(&self) -> usize {
+        let state = self.state.lock().unwrap();
+        match &state.hot {
+            Some(hot) => hot.start + hot.file.count(),
+            None => state.closed.last().map_or(0, |x| x.start + x.len),
+        }
+    }
+
+    /// Position of the first record in the segments of the month and later months.
+    pub fn start_of(&self, id: SegmentId) -> usize {
+        let state = self.state.lock().unwrap();
+        let hot = state.hot.as_ref().map(|x| (x.id, x.start));
+        match state
+            .closed
+            .iter()
+            .map(|x| (x.file.id, x.start))
+            .chain(hot)
+            .find(|x| x.0 >= id)
+        {
+            Some((_, start)) => start,
+            None => {
+                drop(state);
+                self.len()
+            }
+        }
+    }
+
+    /// The record of the latest position.
+    pub fn last(&self) -> Result<Option<FocusRecord>> {
+        let len = self.len();
+        if len == 0 {
+            return Ok(None);
+        }
+        Ok(self.read(Some(len - 1), None)?.pop())
+    }
+
+    /// Read records of positions in `start..end`, only the segments overlapping them are read.
+    pub fn read(&self, start: Option<usize>, end: Option<usize>) -> Result<Vec<FocusRecord>> {
+        self.iter(start, end).collect()
+    }
+
+    /// Iterate records of positions in `start..end`. The lock is only held to find the
+    /// overlapping segments, records are decoded from the files while iterating.
+    /// The segment files must not be replaced until the iterator is dropped.
+    pub fn iter(&self, start: Option<usize>, end: Option<usize>) -> SegmentRecords {
+        let state = self.state.lock().unwrap();
+        let start = start.unwrap_or(0);
+        let end = end.unwrap_or(usize::MAX);
+        let hot = state.hot.as_ref().map(|hot| {
+            (
+                SegmentFile::new(&self.data_dir, hot.id, false),
+                hot.start,
+                hot.file.count(),
+            )
+        });
+        let slices = state
+            .closed
+            .iter()
+            .map(|x| (x.file.clone(), x.start, x.len))
+            .chain(hot)
+            .filter(|(_, segment_start, len)| start < segment_start + len && *segment_start < end)
+            .map(|(file, segment_start, len)| SegmentSlice {
+                file,
+                from: start.saturating_sub(segment_start),
+                to: (end - segment_start).min(len),
+                map: !self.read_only,
+            })
+            .collect::<Vec<_>>();
+        SegmentRecords {
+            slices: slices.into_iter(),
+            current: None,
+        }
+    }
+
+    /// Compress closed segments which aren't compressed yet, returns the count of them.
+    pub fn compress(&self) -> Result<usize> {
+        if self.read_only {
+            return Err(StorageError::ReadOnly);
+        }
+        let mut state = self.state.lock().unwrap();
+        let mut count = 0;
+        for segment in state.closed.iter_mut().filter(|x| !x.file.compressed) {
+            let compressed = SegmentFile::new(&self.data_dir, segment.file.id, true);
+            replace_file(
+                &compressed.path,
+                &compressed_content(&segment.file.read_records()?),
+            )?;
+            fs::remove_file(&segment.file.path).map_err(StorageError::io(&segment.file.path))?;
+            info!("Compressed record segment {:?}", compressed.path);
+            segment.file = compressed;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+/// Records of positions `from..to` in a segment.
+#[derive(Debug)]
+struct SegmentSlice {
+    file: SegmentFile,
+    from: usize,
+    to: usize,
+    /// Map the file instead of reading it. Files mapped by a reader can't be replaced by
+    /// the writer on Windows, so only the writer process maps them.
+    map: bool,
+}
+
+/// The content of a segment, mapped read-only or read into memory.
+#[derive(Debug)]
+enum SegmentData {
+    Mapped(Mmap),
+    Loaded(Vec<u8>),
+}
+
+impl SegmentData {
+    fn load(file: &SegmentFile, map: bool) -> Result<Self> {
+        if file.compressed || !map {
+            return Ok(Self::Loaded(file.read()?));
+        }
+        let f = File::open(&file.path).map_err(StorageError::io(&file.path))?;
+        unsafe { Mmap::map(&f) }
+            .map(Self::Mapped)
+            .map_err(StorageError::io(&file.path))
+    }
+
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Self::Mapped(mmap) => mmap,
+            Self::Loaded(buf) => buf,
+        }
+    }
+}
+
+/// Records decoded lazily from the segments, returned by [`RecordSegments::iter`].
+/// A segment which can't be read yields one error, then the next segment is read.
+#[derive(Debug)]
+pub struct SegmentRecords {
+    slices: std::vec::IntoIter<SegmentSlice>,
+    /// The loaded segment, its version and the positions left to decode.
+    current: Option<(SegmentData, u32, Range<usize>)>,
+}
+
+impl Iterator for SegmentRecords {
+    type Item = Result<FocusRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((data, version, positions)) = &mut self.current
+                && let Some(position) = positions.next()
+            {
+                let size = record_size(*version);
+                let offset = HEADER_SIZE + position * size;
+                return Some(Ok(decode_record(
+                    &data.bytes()[offset..offset + size],
+                    *version,
+                )));
+            }
+            let slice = self.slices.next()?;
+            match load_slice(&slice) {
+                Ok(current) => self.current = Some(current),
+                Err(e) => {
+                    self.current = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
+
+/// Positions past the end of the file are dropped, in case the file is broken.
+fn load_slice(slice: &SegmentSlice) -> Result<(SegmentData, u32, Range<usize>)> {
+    let data = SegmentData::load(&slice.file, slice.map)?;
+    let header = FileHeader::parse(RECORD_MAGIC, data.bytes()).ok_or_else(|| {
+        StorageError::UnsupportedVersion {
+            path: slice.file.path.clone(),
+            version: 0,
+        }
+    })?;
+    header.check_version(&slice.file.path)?;
+    let available = (data.bytes().len() - HEADER_SIZE) / record_size(header.version);
+    let to = slice.to.min(available);
+    Ok((data, header.version, slice.from.min(to)..to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, verify};
+    use crate::util::{d_as_ms, m_as_ms, ms_as_d, now_timestamp, s_as_ms};
+    use std::fs;
+
+    #[test]
+    fn test_segments() {
+        let now = now_timestamp();
+        let dir = temp_data_dir("segments");
+        let days = [70, 70, 35, 0];
+        fs::create_dir_all(&dir).unwrap();
+        let base_day = ms_as_d(now) as u64 - 80;
+        fs::write(dir.join("index.bin"), base_day.to_le_bytes()).unwrap();
+        let storage = Storage::open(&dir).unwrap();
+        for (i, day) in days.into_iter().enumerate() {
+            let focus_at = now - d_as_ms(day) - m_as_ms(10) + s_as_ms(i as i64);
+            storage
+                .write_record("C:\\a.exe", focus_at, focus_at + m_as_ms(1))
+                .unwrap();
+        }
+        let records = storage.read_by_timestamp(0, now);
+        assert_eq!(records.len(), days.len());
+        let segments = list_segments(&dir).unwrap();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments[0].id, SegmentId::of_timestamp(records[0].focus_at));
+
+        assert_eq!(storage.compress_segments().unwrap(), 2);
+        assert_eq!(storage.compress_segments().unwrap(), 0);
+        assert_eq!(storage.read_by_timestamp(0, now), records);
+        let from = records[2].focus_at;
+        assert_eq!(storage.read_by_timestamp(from, from), records[2..3]);
+        drop(storage);
+
+        assert!(verify::verify(&dir).unwrap().is_ok());
+        let storage = Storage::open(&dir).unwrap();
+        let mut iter = storage.iter_by_timestamp(0, now);
+        assert_eq!(iter.next().unwrap().unwrap(), records[0]);
+        storage
+            .write_record("C:\\b.exe", now - m_as_ms(5), now - m_as_ms(4))
+            .unwrap();
+        let rest: Vec<_> = iter.map(|x| x.unwrap()).collect();
+        assert_eq!(rest, records[1..]);
+        assert_eq!(storage.read_by_timestamp(0, now).len(), days.len() + 1);
+        let compressed: Vec<_> = list_segments(&dir)
+            .unwrap()
+            .iter()
+            .map(|x| x.compressed)
+            .collect();
+        assert_eq!(compressed, vec![true, true, false]);
+
+        // A rewrite keeps closed months compressed, and doesn't write months which don't change.
+        let segments = list_segments(&dir).unwrap();
+        let modified = std::time::SystemTime::UNIX_EPOCH;
+        fs::File::options()
+            .write(true)
+            .open(&segments[0].path)
+            .and_then(|x| x.set_modified(modified))
+            .unwrap();
+        assert!(
+            storage
+                .split_record(records[2].focus_at + s_as_ms(10))
+                .unwrap()
+        );
+        assert_eq!(list_segments(&dir).unwrap(), segments);
+        let metadata = segments[0].path.metadata().unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        assert_eq!(storage.read_by_timestamp(0, now).len(), days.len() + 2);
+
+        // Records out of order are merged by rewriting their month, earlier ones are kept.
+        let focus_at = records[3].focus_at - s_as_ms(2);
+        storage
+            .write_record("C:\\b.exe", focus_at, focus_at + s_as_ms(1))
+            .unwrap();
+        assert_eq!(list_segments(&dir).unwrap(), segments);
+        let metadata = segments[0].path.metadata().unwrap();
+        assert_eq!(metadata.modified().unwrap(), modified);
+        let records = storage.read_by_timestamp(0, now);
+        assert_eq!(records.len(), days.len() + 3);
+        assert!(records.is_sorted_by_key(|x| x.focus_at));
+        assert!(records.iter().any(|x| x.focus_at == focus_at));
+        assert!(verify::verify(&dir).unwrap().is_ok());
+    }
+}
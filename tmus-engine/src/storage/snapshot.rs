@@ -0,0 +1,325 @@
+//! Consistent snapshots of a data directory in one archive, and restoring from them.
+//!
+//! A snapshot is not a copy of the files, which may be written meanwhile. The record files and
+//! index.bin are written from the records read at one point, and app.jsonl from the registry in
+//! memory, so it's consistent even when taken from a running tmus or a read-only storage.
+//!
+//! The archive is a zlib stream of the header `TMSN`, a u32 version and a u32 entry count,
+//! then every entry as a u32 name length, the name, a u64 content length, the content and
+//! the CRC32 of the content. Numbers are little endian.
+
+use crate::storage::Storage;
+use crate::storage::device::{DEVICE_FILE, parse_devices};
+use crate::storage::error::{Result, StorageError};
+use crate::storage::file::{read_or_default, replace_file};
+use crate::storage::focus_app::Registry;
+use crate::storage::focus_record::decode_record_file;
+use crate::storage::header::{FORMAT_VERSION, INDEX_MAGIC};
+use crate::storage::migration::backup;
+use crate::storage::rewrite::{ensure_not_in_use, index_content, parse_header, record_content};
+use crate::storage::segment::{RECORD_DIR, SegmentFile, segment_files, split_segments};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+const SNAPSHOT_MAGIC: &[u8; 4] = b"TMSN";
+const SNAPSHOT_VERSION: u32 = 1;
+/// A validated snapshot waiting to be restored on next start, see [`stage_restore`].
+const STAGED_SNAPSHOT: &str = "restore.snapshot";
+/// Engine files replaced by a restore, besides the segments.
+const ENGINE_FILES: [&str; 6] = [
+    "app.jsonl",
+    "app.txt",
+    "device.json",
+    "record.bin",
+    "index.bin",
+    "archive.bin",
+];
+
+/// A file of the snapshot, named by its path relative to the data directory with `/`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnapshotEntry {
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+impl Storage {
+    /// The engine files of a consistent snapshot, add other files of the data directory
+    /// before writing it by [`write_snapshot`].
+    pub fn snapshot(&self) -> Result<Vec<SnapshotEntry>> {
+        let (base_day, records) = {
+            let files = self.files.read().unwrap();
+            let files = files.as_ref().ok_or(StorageError::Closed)?;
+            files.index.refresh()?;
+            files.segments.refresh()?;
+            (files.index.start_day(), files.segments.read(None, None)?)
+        };
+        // Read after the records, so every app of the records is registered.
+        let mut entries = vec![SnapshotEntry {
+            name: "app.jsonl".to_string(),
+            content: self.app.content()?.into_bytes(),
+        }];
+        for (id, records) in split_segments(&records) {
+            entries.push(SnapshotEntry {
+                name: format!(
+                    "{}/{}",
+                    RECORD_DIR,
+                    SegmentFile::new(&self.data_dir, id, false).name()
+                ),
+                content: record_content(records),
+            });
+        }
+        for name in ["archive.bin", DEVICE_FILE] {
+            let path = self.data_dir.join(name);
+            if path.exists() {
+                entries.push(SnapshotEntry {
+                    name: name.to_string(),
+                    content: read_or_default(&path)?,
+                });
+            }
+        }
+        entries.push(SnapshotEntry {
+            name: "index.bin".to_string(),
+            content: index_content(FORMAT_VERSION, base_day, &records),
+        });
+        Ok(entries)
+    }
+}
+
+/// Write the entries to the archive, replacing it if it exists.
+pub fn write_snapshot(path: impl AsRef<Path>, entries: &[SnapshotEntry]) -> Result<()> {
+    let path = path.as_ref();
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+    let mut write = |buf: &[u8]| encoder.write_all(buf);
+    let written = (|| {
+        write(SNAPSHOT_MAGIC)?;
+        write(&SNAPSHOT_VERSION.to_le_bytes())?;
+        write(&(entries.len() as u32).to_le_bytes())?;
+        for entry in entries {
+            write(&(entry.name.len() as u32).to_le_bytes())?;
+            write(entry.name.as_bytes())?;
+            write(&(entry.content.len() as u64).to_le_bytes())?;
+            write(&entry.content)?;
+            write(&crc32fast::hash(&entry.content).to_le_bytes())?;
+        }
+        Ok(())
+    })();
+    let content = written
+        .and_then(|_| encoder.finish())
+        .map_err(StorageError::io(path))?;
+    replace_file(path, &content)
+}
+
+/// Read the entries of the archive, which is validated as a snapshot of the current format:
+/// entry checksums and names, the header of index.bin and record files, and app.jsonl.
+pub fn read_snapshot(path: impl AsRef<Path>) -> Result<Vec<SnapshotEntry>> {
+    let path = path.as_ref();
+    let invalid = |reason: &str| StorageError::InvalidSnapshot {
+        path: path.to_path_buf(),
+        reason: reason.to_string(),
+    };
+    let file = fs::File::open(path).map_err(StorageError::io(path))?;
+    let mut buf = Vec::new();
+    ZlibDecoder::new(file)
+        .read_to_end(&mut buf)
+        .map_err(|_| invalid("not a zlib stream"))?;
+
+    let mut reader = buf.as_slice();
+    let mut take = |len: usize| -> Result<&[u8]> {
+        if reader.len() < len {
+            return Err(invalid("unexpected end"));
+        }
+        let (head, rest) = reader.split_at(len);
+        reader = rest;
+        Ok(head)
+    };
+    if take(4)? != SNAPSHOT_MAGIC {
+        return Err(invalid("wrong magic"));
+    }
+    if u32::from_le_bytes(take(4)?.try_into().unwrap()) != SNAPSHOT_VERSION {
+        return Err(invalid("unsupported snapshot version"));
+    }
+    let count = u32::from_le_bytes(take(4)?.try_into().unwrap());
+    let mut entries = Vec::new();
+    for _ in 0..count {
+        let len = u32::from_le_bytes(take(4)?.try_into().unwrap()) as usize;
+        let name = String::from_utf8(take(len)?.to_vec());
+        let name = name.map_err(|_| invalid("name not utf-8"))?;
+        let len = u64::from_le_bytes(take(8)?.try_into().unwrap()) as usize;
+        let content = take(len)?.to_vec();
+        if u32::from_le_bytes(take(4)?.try_into().unwrap()) != crc32fast::hash(&content) {
+            return Err(invalid(&format!("checksum of {} mismatch", name)));
+        }
+        if !is_valid_name(&name) {
+            return Err(invalid(&format!("unexpected entry {}", name)));
+        }
+        entries.push(SnapshotEntry { name, content });
+    }
+    if !reader.is_empty() {
+        return Err(invalid("trailing data"));
+    }
+
+    for entry in &entries {
+        let entry_path = path.join(&entry.name);
+        if entry.name == "index.bin" {
+            let header = parse_header(INDEX_MAGIC, &entry.content, &entry_path)?;
+            if header.version != FORMAT_VERSION {
+                return Err(invalid("index.bin isn't of the current format version"));
+            }
+        } else if entry.name == "app.jsonl" {
+            Registry::parse(&String::from_utf8_lossy(&entry.content), &entry_path)?;
+        } else if entry.name.starts_with(RECORD_DIR) || entry.name == "archive.bin" {
+            decode_record_file(&entry.content, &entry_path)?;
+        } else if entry.name == DEVICE_FILE && parse_devices(&entry.content).is_none() {
+            return Err(invalid("device.json isn't a list of device names"));
+        }
+    }
+    for name in ["index.bin", "app.jsonl"] {
+        if entries.iter().all(|x| x.name != name) {
+            return Err(invalid(&format!("{} is missing", name)));
+        }
+    }
+    Ok(entries)
+}
+
+/// Replace the data directory by the snapshot, which is validated first.
+/// The engine files of the directory are backed up and removed, other files of the snapshot
+/// are backed up and written over. Returns the backup directory.
+///
+/// Fails with [`StorageError::Locked`] if tmus is running with the data directory,
+/// use [`stage_restore`] then.
+pub fn restore(data_dir: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<PathBuf> {
+    let data_dir = data_dir.as_ref();
+    let entries = read_snapshot(path)?;
+    fs::create_dir_all(data_dir).map_err(StorageError::io(data_dir))?;
+    ensure_not_in_use(data_dir)?;
+    let backup_dir = backup(data_dir, "restore")?;
+    for entry in &entries {
+        let path = data_dir.join(&entry.name);
+        if !entry.name.contains('/')
+            && !ENGINE_FILES.contains(&entry.name.as_str())
+            && path.is_file()
+        {
+            fs::copy(&path, backup_dir.join(&entry.name)).map_err(StorageError::io(&path))?;
+        }
+    }
+    // index.bin is removed first and written last, so an interrupted restore rebuilds it.
+    let stale = ENGINE_FILES
+        .iter()
+        .map(|name| data_dir.join(name))
+        .chain(segment_files(data_dir)?.into_iter().map(|x| x.path))
+        .filter(|x| x.exists());
+    for path in stale {
+        fs::remove_file(&path).map_err(StorageError::io(&path))?;
+    }
+    let (index, entries): (Vec<_>, Vec<_>) = entries.iter().partition(|x| x.name == "index.bin");
+    for entry in entries.into_iter().chain(index) {
+        let path = data_dir.join(&entry.name);
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).map_err(StorageError::io(dir))?;
+        }
+        replace_file(&path, &entry.content)?;
+    }
+    info!("Restored {:?}, backup in {:?}", data_dir, backup_dir);
+    Ok(backup_dir)
+}
+
+/// Validate the snapshot and copy it into the data directory, to be restored by
+/// [`apply_staged_restore`] on next start, before the storage is opened.
+pub fn stage_restore(data_dir: impl AsRef<Path>, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    read_snapshot(path)?;
+    let staged = data_dir.as_ref().join(STAGED_SNAPSHOT);
+    fs::copy(path, &staged).map_err(StorageError::io(&staged))?;
+    Ok(())
+}
+
+/// Restore the staged snapshot if there is one, returns whether it's restored.
+/// The staged snapshot is removed after restored.
+pub fn apply_staged_restore(data_dir: impl AsRef<Path>) -> Result<bool> {
+    let data_dir = data_dir.as_ref();
+    let staged = data_dir.join(STAGED_SNAPSHOT);
+    if !staged.exists() {
+        return Ok(false);
+    }
+    restore(data_dir, &staged)?;
+    fs::remove_file(&staged).map_err(StorageError::io(&staged))?;
+    Ok(true)
+}
+
+/// Entries are files in the data directory or the record directory.
+fn is_valid_name(name: &str) -> bool {
+    let parts: Vec<&str> = name.split('/').collect();
+    let valid_part =
+        |x: &&str| !x.is_empty() && *x != "." && *x != ".." && !x.contains(['\\', ':']);
+    match parts.as_slice() {
+        [file] => valid_part(file) && *file != STAGED_SNAPSHOT,
+        [dir, file] => *dir == RECORD_DIR && valid_part(file),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, verify};
+    use crate::util::now_timestamp;
+
+    #[test]
+    fn test_snapshot() {
+        let dir = temp_data_dir("snapshot");
+        let restored_dir = temp_data_dir("snapshot-restored");
+        let now = now_timestamp();
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - 30000, now - 20000)
+            .unwrap();
+        storage
+            .write_record("C:\\b.exe", now - 20000, now - 10000)
+            .unwrap();
+        let mut entries = storage.snapshot().unwrap();
+        entries.push(SnapshotEntry {
+            name: "rule.json".to_string(),
+            content: b"{}".to_vec(),
+        });
+        let path = dir.join("test.snapshot");
+        write_snapshot(&path, &entries).unwrap();
+        assert_eq!(read_snapshot(&path).unwrap(), entries);
+
+        // Restore over a directory which has other records.
+        let other = Storage::open(&restored_dir).unwrap();
+        other
+            .write_record("C:\\c.exe", now - 5000, now - 1000)
+            .unwrap();
+        assert!(matches!(
+            restore(&restored_dir, &path),
+            Err(StorageError::Locked(_))
+        ));
+        stage_restore(&restored_dir, &path).unwrap();
+        drop(other);
+        assert!(apply_staged_restore(&restored_dir).unwrap());
+        assert!(!apply_staged_restore(&restored_dir).unwrap());
+        let restored = Storage::open(&restored_dir).unwrap();
+        assert_eq!(restored.get_all_app(), storage.get_all_app());
+        assert_eq!(
+            restored.read_by_timestamp(0, now),
+            storage.read_by_timestamp(0, now)
+        );
+        assert_eq!(fs::read(restored_dir.join("rule.json")).unwrap(), b"{}");
+        assert!(verify::verify(&restored_dir).unwrap().is_ok());
+
+        let mut buf = fs::read(&path).unwrap();
+        let len = buf.len();
+        buf[len / 2] ^= 0xff;
+        fs::write(&path, buf).unwrap();
+        assert!(matches!(
+            read_snapshot(&path),
+            Err(StorageError::InvalidSnapshot { .. })
+        ));
+    }
+}
@@ -0,0 +1,23 @@
+//! Helpers shared by the storage tests, and the tests of the app by the `test-util` feature.
+
+use crate::models::{AppId, FocusRecord, LOCAL_DEVICE};
+use crate::util::Timestamp;
+use std::fs;
+use std::path::PathBuf;
+
+/// A data directory in the temp directory which doesn't exist yet.
+pub fn temp_data_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("tmus-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// A record of the local device.
+pub fn record(id: AppId, focus_at: Timestamp, blur_at: Timestamp) -> FocusRecord {
+    FocusRecord {
+        id,
+        focus_at,
+        blur_at,
+        device: LOCAL_DEVICE,
+    }
+}
@@ -0,0 +1,416 @@
+//! Check the consistency of a data directory, and rebuild what's broken.
+//!
+//! The files are read directly instead of through [`super::Storage`], so a damaged directory
+//! can still be checked. Reading is allowed while tmus is running, repairing is not.
+
+use crate::models::{AppId, AppInfo, FocusRecord};
+use crate::storage::error::Result;
+use crate::storage::file::{read_or_default, replace_file};
+use crate::storage::focus_app::{AppEntry, Registry, read_apps};
+use crate::storage::focus_index::{build_index, read_index};
+use crate::storage::focus_record::{decode_records, record_size};
+use crate::storage::header::{
+    FORMAT_VERSION, HEADER_SIZE, RECORD_MAGIC, check_version, parse_app_header,
+};
+use crate::storage::migration::{backup, migrate};
+use crate::storage::rewrite::{
+    ensure_not_in_use, parse_header, read_base_day, rebuild_index_file, rewrite_records,
+};
+use crate::storage::segment::{RECORD_DIR, list_segments};
+use crate::util::{Timestamp, d_as_ms, ms_as_d, start_of_next_d};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use tracing::info;
+
+/// A problem found in the data directory.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Issue {
+    /// The committed record count in the header of a record file doesn't match the records.
+    RecordCount {
+        file: String,
+        committed: usize,
+        found: usize,
+    },
+    /// An all-zero record before the last record, mostly left by a torn write.
+    ZeroRecord { position: usize },
+    /// Non-zero data after the last record.
+    TrailingData { position: usize },
+    /// The record spans across one day.
+    InvalidSpan { position: usize },
+    /// The record starts before the previous one.
+    OutOfOrder { position: usize },
+    /// A line of app.jsonl which can't be parsed or fails the checksum.
+    BrokenAppEntry { line: usize },
+    /// The app id of the record isn't registered, like its registration is broken.
+    /// Repair registers it as `unknown-app-{id}` to keep the time, unless the app is forgotten.
+    UnknownApp { position: usize, app_id: AppId },
+    /// The same app path is registered twice. It's a warning, records of both ids are valid
+    /// and it can't be repaired automatically.
+    DuplicateApp { app_id: AppId, path: String },
+    /// Days in index.bin which don't point to the first record of the day.
+    IndexMismatch { first_day: Timestamp, days: usize },
+}
+
+impl Display for Issue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Issue::RecordCount {
+                file,
+                committed,
+                found,
+            } => write!(
+                f,
+                "{} header counts {} records, but {} are found",
+                file, committed, found
+            ),
+            Issue::ZeroRecord { position } => write!(f, "record {} is all zero", position),
+            Issue::TrailingData { position } => {
+                write!(f, "unexpected data after the last record at {}", position)
+            }
+            Issue::InvalidSpan { position } => {
+                write!(f, "record {} spans across one day", position)
+            }
+            Issue::OutOfOrder { position } => {
+                write!(f, "record {} starts before the previous record", position)
+            }
+            Issue::BrokenAppEntry { line } => write!(f, "app.jsonl line {} is broken", line),
+            Issue::UnknownApp { position, app_id } => {
+                write!(f, "record {} has unknown app id {}", position, app_id)
+            }
+            Issue::DuplicateApp { app_id, path } => {
+                write!(f, "app {} is a duplicate of {}", app_id, path)
+            }
+            Issue::IndexMismatch { first_day, days } => write!(
+                f,
+                "index of {} days is wrong, the first is the day of timestamp {}",
+                days, first_day
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub app_count: usize,
+    pub record_count: usize,
+    pub issues: Vec<Issue>,
+    /// Issues which don't need a repair.
+    pub warnings: Vec<Issue>,
+}
+
+impl VerifyReport {
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Check record ordering, index consistency and app id bounds of the data directory.
+pub fn verify(data_dir: impl AsRef<Path>) -> Result<VerifyReport> {
+    Ok(scan(data_dir.as_ref())?.report)
+}
+
+/// Rewrite the segments and index.bin from the valid records, and app.jsonl without broken lines.
+/// Broken records are dropped and records are sorted by time, apps of records whose registration
+/// is lost are registered again as `unknown-app-{id}`. The files are backed up first.
+///
+/// Returns the issues found before repairing. Fails with [`StorageError::Locked`] if tmus is
+/// running with the data directory.
+pub fn repair(data_dir: impl AsRef<Path>) -> Result<VerifyReport> {
+    let data_dir = data_dir.as_ref();
+    ensure_not_in_use(data_dir)?;
+    migrate(data_dir)?;
+    let scan = scan(data_dir)?;
+    if scan.report.is_ok() {
+        return Ok(scan.report);
+    }
+    let backup_dir = backup(data_dir, "repair")?;
+    info!(
+        "Repairing {:?}, {} issues found, backup in {:?}",
+        data_dir,
+        scan.report.issues.len(),
+        backup_dir
+    );
+
+    if let Some(mut registry) = scan.registry {
+        for (&id, &(first_seen, last_seen)) in &scan.unknown_apps {
+            registry.apply(AppEntry::register(&AppInfo {
+                id,
+                path: format!("unknown-app-{}", id),
+                first_seen,
+                last_seen,
+                merged_into: None,
+            }));
+        }
+        if !registry.broken.is_empty() || !scan.unknown_apps.is_empty() {
+            replace_file(data_dir.join("app.jsonl"), registry.content().as_bytes())?;
+        }
+    }
+    let mut records = scan.valid_records;
+    records.sort_by_key(|x| x.focus_at);
+    rewrite_records(data_dir, &records)?;
+    Ok(scan.report)
+}
+
+/// Rewrite index.bin from the records, including the days without records.
+/// The base day is kept if index.bin is still readable, otherwise it's the day of the first record.
+///
+/// Records must be in time order, use [`repair`] if they aren't.
+/// Fails with [`StorageError::Locked`] if tmus is running with the data directory.
+pub fn rebuild_index(data_dir: impl AsRef<Path>) -> Result<()> {
+    let data_dir = data_dir.as_ref();
+    ensure_not_in_use(data_dir)?;
+    rebuild_index_file(data_dir)
+}
+
+struct Scan {
+    report: VerifyReport,
+    /// Records without unrecoverable issues, spans across days are split.
+    valid_records: Vec<FocusRecord>,
+    /// Unregistered apps of valid records, with the time range of their records.
+    unknown_apps: BTreeMap<AppId, (Timestamp, Timestamp)>,
+    /// `None` before format version 4.
+    registry: Option<Registry>,
+}
+
+fn scan(data_dir: &Path) -> Result<Scan> {
+    let mut report = VerifyReport::default();
+
+    let (apps, registry) = read_registry(data_dir)?;
+    if let Some(registry) = &registry {
+        for &line in &registry.broken {
+            report.issues.push(Issue::BrokenAppEntry { line });
+        }
+    }
+    let mut seen = HashMap::new();
+    for (app_id, app) in apps.iter().enumerate() {
+        let Some(app) = app else {
+            continue;
+        };
+        if let Some(first) = seen.insert(app.as_str(), app_id) {
+            report.warnings.push(Issue::DuplicateApp {
+                app_id,
+                path: apps[first].clone().unwrap_or_default(),
+            });
+        }
+    }
+    report.app_count = apps.iter().flatten().count();
+
+    let is_zero = |slot: &[u8]| slot.iter().all(|byte| *byte == 0);
+    let mut records = Vec::new();
+    let mut zero_records = Vec::new();
+    for (file, buf) in record_files(data_dir)? {
+        let (version, committed) = if buf.is_empty() {
+            (FORMAT_VERSION, 0)
+        } else {
+            let header = parse_header(RECORD_MAGIC, &buf, &data_dir.join(&file))?;
+            (header.version, header.extra as usize)
+        };
+        let size = record_size(version);
+        let slots: Vec<&[u8]> = buf
+            .get(HEADER_SIZE..)
+            .unwrap_or_default()
+            .chunks_exact(size)
+            .collect();
+        let first_zero = slots.iter().position(|x| is_zero(x)).unwrap_or(slots.len());
+        let len = first_zero.max(committed.min(slots.len()));
+        if version >= 2 && committed != len {
+            report.issues.push(Issue::RecordCount {
+                file,
+                committed,
+                found: len,
+            });
+        }
+        if let Some(position) = slots[len..].iter().position(|x| !is_zero(x)) {
+            report.issues.push(Issue::TrailingData {
+                position: records.len() + len + position,
+            });
+        }
+        records.extend(decode_records(
+            &buf[HEADER_SIZE.min(buf.len())..][..len * size],
+            version,
+        ));
+        zero_records.extend(slots[..len].iter().map(|x| is_zero(x)));
+    }
+    report.record_count = records.len();
+
+    let mut valid_records = Vec::with_capacity(records.len());
+    let mut unknown_apps = BTreeMap::new();
+    let mut last_focus_at = Timestamp::MIN;
+    for (position, record) in records.iter().enumerate() {
+        if zero_records[position] {
+            report.issues.push(Issue::ZeroRecord { position });
+            continue;
+        }
+        if apps.get(record.id).is_none_or(|x| x.is_none()) {
+            report.issues.push(Issue::UnknownApp {
+                position,
+                app_id: record.id,
+            });
+            // Records of forgotten apps are left by an interrupted forget.
+            if registry
+                .as_ref()
+                .is_none_or(|x| x.forgotten.contains(&record.id))
+            {
+                continue;
+            }
+            let range = unknown_apps
+                .entry(record.id)
+                .or_insert((record.focus_at, record.blur_at));
+            range.0 = range.0.min(record.focus_at);
+            range.1 = range.1.max(record.blur_at);
+        }
+        if record.focus_at < last_focus_at {
+            report.issues.push(Issue::OutOfOrder { position });
+        }
+        last_focus_at = record.focus_at;
+        if record.blur_at > start_of_next_d(record.focus_at) {
+            report.issues.push(Issue::InvalidSpan { position });
+            valid_records.extend(record.split_record());
+        } else {
+            valid_records.push(*record);
+        }
+    }
+
+    let base_day = read_base_day(data_dir)?;
+    let index: Vec<u64> = match base_day {
+        Some(_) => {
+            read_index(&read_or_default(data_dir.join("index.bin"))?[HEADER_SIZE..]).collect()
+        }
+        None => vec![],
+    };
+    let expected = base_day.map_or(vec![], |base_day| build_index(base_day, &records));
+    let mismatch: Vec<usize> = (0..expected.len().max(index.len()))
+        .filter(|&i| expected.get(i) != index.get(i))
+        .collect();
+    if let Some(&first) = mismatch.first() {
+        report.issues.push(Issue::IndexMismatch {
+            first_day: d_as_ms((base_day.unwrap() + 1 + first as u64) as i64),
+            days: mismatch.len(),
+        });
+    } else if base_day.is_none() && !records.is_empty() {
+        report.issues.push(Issue::IndexMismatch {
+            first_day: d_as_ms(ms_as_d(records[0].focus_at)),
+            days: expected.len().max(1),
+        });
+    }
+
+    Ok(Scan {
+        report,
+        valid_records,
+        unknown_apps,
+        registry,
+    })
+}
+
+/// App paths indexed by id, and the registry of app.jsonl.
+/// Data directories before format version 4 only have app.txt, which has no registry.
+fn read_registry(data_dir: &Path) -> Result<(Vec<Option<String>>, Option<Registry>)> {
+    let path = data_dir.join("app.txt");
+    if path.exists() {
+        let text = String::from_utf8_lossy(&read_or_default(&path)?).into_owned();
+        if !text.is_empty() {
+            let version = text.lines().next().and_then(parse_app_header).unwrap_or(0);
+            check_version(&path, version)?;
+        }
+        return Ok((read_apps(&text).into_iter().map(Some).collect(), None));
+    }
+    let path = data_dir.join("app.jsonl");
+    let registry = Registry::parse(&String::from_utf8_lossy(&read_or_default(&path)?), &path)?;
+    let apps = registry
+        .apps
+        .iter()
+        .map(|x| x.as_ref().map(|x| x.path.clone()))
+        .collect();
+    Ok((apps, Some(registry)))
+}
+
+/// The name and content of record.bin before format version 3, or of every segment.
+fn record_files(data_dir: &Path) -> Result<Vec<(String, Vec<u8>)>> {
+    let path = data_dir.join("record.bin");
+    if path.exists() {
+        return Ok(vec![("record.bin".to_string(), read_or_default(&path)?)]);
+    }
+    list_segments(data_dir)?
+        .into_iter()
+        .map(|file| Ok((format!("{}/{}", RECORD_DIR, file.name()), file.read()?)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::segment;
+    use crate::storage::test_util::temp_data_dir;
+    use crate::storage::{Storage, StorageError};
+    use crate::util::{m_as_ms, now_timestamp};
+    use std::fs;
+
+    #[test]
+    fn test_verify_and_repair() {
+        let now = now_timestamp();
+        let dir = temp_data_dir("verify");
+        let storage = Storage::open(&dir).unwrap();
+        storage
+            .write_record("C:\\a.exe", now - m_as_ms(10), now - m_as_ms(5))
+            .unwrap();
+        storage
+            .write_record("C:\\b.exe", now - m_as_ms(5), now - m_as_ms(1))
+            .unwrap();
+        let records = storage.read_by_timestamp(0, now);
+        assert!(matches!(repair(&dir), Err(StorageError::Locked(_))));
+        drop(storage);
+        assert!(verify(&dir).unwrap().is_ok());
+
+        let segment = segment::list_segments(&dir).unwrap().remove(0).path;
+        let mut buf = fs::read(&segment).unwrap();
+        buf[16..20].copy_from_slice(&7u32.to_le_bytes());
+        fs::write(&segment, &buf).unwrap();
+        fs::write(dir.join("index.bin"), []).unwrap();
+        let report = verify(&dir).unwrap();
+        assert_eq!(report.record_count, records.len());
+        assert!(report.issues.contains(&Issue::UnknownApp {
+            position: 0,
+            app_id: 7
+        }));
+
+        repair(&dir).unwrap();
+        assert!(verify(&dir).unwrap().is_ok());
+        // The record of the broken app id is kept by an unknown app.
+        let storage = Storage::open(&dir).unwrap();
+        let repaired = storage.read_by_timestamp(0, now);
+        assert_eq!(repaired[1..], records[1..]);
+        assert_eq!(repaired[0].id, 7);
+        assert_eq!(storage.get_path_by_id(7).unwrap(), "unknown-app-7");
+    }
+
+    #[test]
+    fn test_rebuild_index() {
+        let now = now_timestamp();
+        let dir = temp_data_dir("rebuild-index");
+        let storage = Storage::open(&dir).unwrap();
+        for day in [0, 2, 5] {
+            let focus_at = now + d_as_ms(day);
+            storage
+                .write_record("C:\\a.exe", focus_at, focus_at + m_as_ms(1))
+                .unwrap();
+        }
+        let index = |storage: &Storage| {
+            let records = storage.all_index_record();
+            records.iter().map(|x| x.start_index).collect::<Vec<_>>()
+        };
+        let expected = index(&storage);
+        let records = storage.read_by_timestamp(0, now + d_as_ms(6));
+        assert_eq!(records.len(), 3);
+        drop(storage);
+
+        fs::remove_file(dir.join("index.bin")).unwrap();
+        let storage = Storage::open(&dir).unwrap();
+        assert_eq!(index(&storage), expected);
+        assert_eq!(storage.read_by_timestamp(0, now + d_as_ms(6)), records);
+        assert_eq!(
+            storage.read_by_timestamp(now + d_as_ms(2), now + d_as_ms(2)),
+            records[1..2]
+        );
+    }
+}
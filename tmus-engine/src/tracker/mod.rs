@@ -1,4 +1,6 @@
 mod schedule;
 pub mod window;
 
-pub use schedule::{TrackingSpanEvent, start_tracking};
+#[cfg(all(feature = "tracker-windows", windows))]
+pub use schedule::start_tracking;
+pub use schedule::{TrackingSpanConfig, TrackingSpanEvent, handle_window_event};
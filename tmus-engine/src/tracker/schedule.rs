@@ -1,6 +1,10 @@
+#[cfg(all(feature = "tracker-windows", windows))]
 use crate::async_runtime::handle;
-use crate::tracker::window::{WindowFocusEvent, set_window_tracker};
+use crate::tracker::window::WindowFocusEvent;
+#[cfg(all(feature = "tracker-windows", windows))]
+use crate::tracker::window::set_window_tracker;
 use crate::util::{Timestamp, m_as_ms};
+#[cfg(all(feature = "tracker-windows", windows))]
 use tokio::sync::mpsc;
 use tokio::sync::mpsc::Receiver;
 use tracing::debug;
@@ -25,6 +29,7 @@ pub struct TrackingSpanEvent {
     pub blur_at: Timestamp,
 }
 
+#[cfg(all(feature = "tracker-windows", windows))]
 pub fn start_tracking<F>(on_span_event: F)
 where
     F: Fn(TrackingSpanEvent) + Send + Sync + 'static,
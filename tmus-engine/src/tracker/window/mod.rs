@@ -1,8 +1,11 @@
+#[cfg(all(feature = "tracker-windows", windows))]
 mod monitor;
 
 use crate::util::Timestamp;
+#[cfg(all(feature = "tracker-windows", windows))]
 use std::time::Duration;
 
+#[cfg(all(feature = "tracker-windows", windows))]
 pub use monitor::set_window_tracker;
 
 #[derive(Debug)]
@@ -13,11 +16,13 @@ pub struct WindowFocusEvent {
     pub focus_at: Timestamp,
 }
 
+#[cfg(all(feature = "tracker-windows", windows))]
 pub struct WindowTrackerConfig {
     /// Check the current window interval.
     loop_get_current_window_interval: Duration,
 }
 
+#[cfg(all(feature = "tracker-windows", windows))]
 impl Default for WindowTrackerConfig {
     fn default() -> Self {
         Self {